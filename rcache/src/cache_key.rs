@@ -58,7 +58,14 @@ impl HashableKey for i64 {
 impl HashableKey for u64 {
     #[inline]
     fn key_to_hash(&self) -> (u64, u64) {
-        (*self as u64, 0)
+        (*self, 0)
+    }
+}
+
+impl<const N: usize> HashableKey for [u8; N] {
+    #[inline]
+    fn key_to_hash(&self) -> (u64, u64) {
+        (&self[..]).key_to_hash()
     }
 }
 
@@ -1,20 +1,15 @@
-#![feature(default_free_fn)]
-
-use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Add, Div};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender};
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant, SystemTime};
 
 use crossbeam::channel::tick;
 use parking_lot::RwLock;
-use xxhash_rust::xxh3::Xxh3;
 
 use crate::cache_key::HashableKey;
 use crate::error::Error;
@@ -39,6 +34,9 @@ mod utils;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// longest the processing thread waits for an entry before it checks whether it's due a cleanup
+const PROCESS_WAIT: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub(crate) enum EntryFlag {
     New,
@@ -53,13 +51,6 @@ pub trait Cost<V>: Send + Sync
     fn cost(&self, v: &V) -> i64;
 }
 
-pub(crate) trait TwoHash64<K>
-where
-    K: Hash,
-{
-    fn hash(&self, key: &K) -> (u64, u64);
-}
-
 #[derive(Debug, Clone)]
 pub(crate) struct Entry<V>
     where
@@ -176,7 +167,7 @@ pub struct Cache<K, V: Clone> {
     get_buf: RingBuffer,
     closed: Arc<AtomicBool>,
     cost: Arc<dyn Cost<V>>,
-    process_thread_handle: JoinHandle<()>,
+    _process_thread_handle: JoinHandle<()>,
     marker_: PhantomData<K>,
 }
 
@@ -190,10 +181,20 @@ unsafe impl<K, V: Send + Sync + Clone> Send for Cache<K, V> {}
 
 unsafe impl<K, V: Send + Sync + Clone> Sync for Cache<K, V> {}
 
+impl<K, V> Default for Cache<K, V>
+    where
+        K: HashableKey,
+        V: Clone + Debug + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K, V> Cache<K, V>
     where
         K: HashableKey,
-        V: Clone + Debug + Send + Sync + Default + 'static,
+        V: Clone + Debug + Send + Sync + 'static,
 {
     pub fn new() -> Cache<K, V> {
         Cache::<K, V>::with_config(Config::default(), ZeroCost)
@@ -231,7 +232,7 @@ impl<K, V> Cache<K, V>
             get_buf,
             closed,
             cost: Arc::new(cost),
-            process_thread_handle: handle,
+            _process_thread_handle: handle,
             marker_: Default::default(),
         }
     }
@@ -262,7 +263,7 @@ impl<K, V> Cache<K, V>
     }
     fn check(&self) -> Result<()> {
         if self.closed.load(Ordering::Acquire) {
-            return Err(Error::CacheClosed);
+            Err(Error::CacheClosed)
         } else {
             Ok(())
         }
@@ -282,7 +283,7 @@ impl<K, V> Cache<K, V>
                 cost: 0,
                 exp: SystemTime::UNIX_EPOCH,
             })
-            .map_err(|e| Error::SendError(Box::new(e)))
+            .map_err(|e| Error::Send(Box::new(e)))
     }
 
     pub fn insert_full(&self, key: K, value: V, cost: i64, ttl: Duration) -> Result<()> {
@@ -324,7 +325,7 @@ impl<K, V> Cache<K, V>
                 metrics.add(MetricType::DropSets, key, 1);
             }
         }
-        send_results.map_err(|e| Error::SendError(Box::new(e)))
+        send_results.map_err(|e| Error::Send(Box::new(e)))
     }
     pub fn update_cost(&self, key: &K, cost: i64) -> Result<()> {
         self.check()?;
@@ -344,7 +345,7 @@ impl<K, V> Cache<K, V>
         };
         self.set_buf
             .send(entry)
-            .map_err(|e| Error::SendError(Box::new(e)))
+            .map_err(|e| Error::Send(Box::new(e)))
     }
     pub fn metrics(&self) -> Option<&Metrics> {
         self.inner.metrics.as_ref()
@@ -400,13 +401,18 @@ fn process_items<V: Clone + Debug + Send + Sync + 'static>(
         cache: cache.clone(),
         num_to_keep: 100000,
     };
-    let mut last_tick = Instant::now();
     std::thread::spawn(move || loop {
         let is_closed = close.load(Ordering::Acquire);
         if is_closed {
             break;
         }
-        if let Ok(entry) = set_buffer_handler.try_recv() {
+        // wait for the next entry rather than spin, but wake up to run the cleanup on time
+        let entry = match set_buffer_handler.recv_timeout(PROCESS_WAIT) {
+            Ok(entry) => Some(entry),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(entry) = entry {
             match entry.flag {
                 EntryFlag::New => {
                     let (victims, added) = cache.policy.add(entry.key, entry.cost);
@@ -444,9 +450,7 @@ fn process_items<V: Clone + Debug + Send + Sync + 'static>(
             }
         }
 
-        if let Ok(tick) = ticker.try_recv() {
-            println!("TICK {:?}", tick.duration_since(last_tick).as_millis());
-            last_tick = tick;
+        if ticker.try_recv().is_ok() {
             cache.store.cleanup(cache.policy.as_ref(), &handler);
         }
     })
@@ -144,14 +144,6 @@ impl Metrics {
         hits as f64 / misses as f64
     }
 
-    pub(crate) fn clear(&self) {
-        for i in self.all.iter().map(|(_, m)| m.iter()).flatten() {
-            i.store(0, Ordering::Release)
-        }
-        let mut life = self.life.write();
-        life.clear()
-    }
-
     pub(crate) fn track_eviction(&self, num_seconds: u64) {
         let mut life = self.life.write();
         let _ = life.increment(num_seconds);
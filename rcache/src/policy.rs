@@ -20,23 +20,13 @@ impl PolicyPair {
     pub(crate) fn key(&self) -> u64 {
         self.0
     }
-
-    pub(crate) fn cost(&self) -> i64 {
-        self.1
-    }
 }
 
 pub(crate) trait Policy: ring::Consumer {
     fn add(&self, key: u64, cost: i64) -> (Option<Vec<Item>>, bool);
-    fn has(&self, key: &u64) -> bool;
     fn remove(&self, key: &u64);
-    fn cap(&self) -> usize;
-    fn close(&self);
     fn update(&self, key: u64, cost: i64);
     fn cost(&self, key: &u64) -> i64;
-    fn clear(&self);
-    fn max_cost(&self) -> i64;
-    fn update_max_cost(&self, cost: i64);
 }
 
 struct Inner {
@@ -56,15 +46,14 @@ fn process_op(
     items_recv: Receiver<Vec<u64>>,
     p: Arc<Mutex<Inner>>,
 ) -> JoinHandle<()> {
-    std::thread::spawn(move || loop {
-        if close.load(Ordering::Acquire) {
-            break;
-        }
-        if let Ok(items) = items_recv.try_recv() {
-            let mut p = p.lock();
-            println!("Admit {:?}", items);
-            p.admit.push(items);
-            println!("Admited")
+    // blocks until the next batch of keys, and stops once the policy holding the sender is
+    // dropped
+    std::thread::spawn(move || {
+        while let Ok(items) = items_recv.recv() {
+            if close.load(Ordering::Acquire) {
+                break;
+            }
+            p.lock().admit.push(items);
         }
     })
 }
@@ -73,12 +62,12 @@ pub(crate) struct DefaultPolicy {
     inner: Arc<Mutex<Inner>>,
     items_channel: SyncSender<Vec<u64>>,
     is_closed: Arc<AtomicBool>,
-    processor: JoinHandle<()>,
-    max_cost: Arc<AtomicI64>,
+    _processor: JoinHandle<()>,
     metrics: Option<Metrics>
 }
 
 impl DefaultPolicy {
+    #[cfg(test)]
     pub(crate) fn new(num_counters: u64, max_cost: i64) -> Self {
         Self::new_with_metrics(num_counters, max_cost, None)
     }
@@ -95,8 +84,7 @@ impl DefaultPolicy {
             inner,
             items_channel,
             is_closed,
-            processor,
-            max_cost,
+            _processor: processor,
             metrics
         }
     }
@@ -126,7 +114,7 @@ impl ring::Consumer for DefaultPolicy {
         if let Some(metrics) = self.metrics.as_ref() {
             metrics.add(MetricType::DropGets, key, keys_len);
         }
-        return false
+        false
     }
 }
 
@@ -211,13 +199,7 @@ impl Policy for DefaultPolicy {
         if let Some(metrics) = self.metrics.as_ref() {
             metrics.add(MetricType::CostAdd, key, cost as u64);
         }
-        return (Some(victims), true);
-    }
-
-    fn has(&self, key: &u64) -> bool {
-        let p = self.inner.lock();
-
-        p.evict.key_costs.contains_key(key)
+        (Some(victims), true)
     }
 
     fn remove(&self, key: &u64) {
@@ -226,16 +208,6 @@ impl Policy for DefaultPolicy {
         p.evict.remove(key);
     }
 
-    fn cap(&self) -> usize {
-        let p = self.inner.lock();
-
-        (p.evict.max_cost() - p.evict.used) as usize
-    }
-
-    fn close(&self) {
-        self.is_closed.store(true, Ordering::Release);
-    }
-
     fn update(&self, key: u64, cost: i64) {
         let mut p = self.inner.lock();
         p.evict.update_if_has(key, cost);
@@ -248,20 +220,6 @@ impl Policy for DefaultPolicy {
         }
         -1
     }
-
-    fn clear(&self) {
-        let mut p = self.inner.lock();
-        p.evict.clear();
-        p.admit.clear();
-    }
-
-    fn max_cost(&self) -> i64 {
-        self.max_cost.load(Ordering::Acquire)
-    }
-
-    fn update_max_cost(&self, cost: i64) {
-        self.max_cost.store(cost, Ordering::Release)
-    }
 }
 
 impl Drop for DefaultPolicy {
@@ -332,11 +290,6 @@ impl SampledLFU {
         }
         false
     }
-
-    pub(crate) fn clear(&mut self) {
-        self.used = 0;
-        self.key_costs.clear()
-    }
 }
 
 pub(crate) struct TinyLFU {
@@ -384,12 +337,6 @@ impl TinyLFU {
         self.freq.clear();
         self.door.clear();
     }
-
-    pub(crate) fn clear(&mut self) {
-        self.incrs = 0;
-        self.freq.clear();
-        self.door.clear();
-    }
 }
 
 #[cfg(test)]
@@ -412,7 +359,7 @@ mod test {
             assert_eq!(1, p.admit.estimate(1));
         }
         policy.close();
-        policy.items_channel.send(vec![3, 3, 3]);
+        let _ = policy.items_channel.send(vec![3, 3, 3]);
         std::thread::sleep(Duration::from_millis(10));
         {
             let mut p = policy.inner.lock();
@@ -1,4 +1,4 @@
-use std::mem::{forget, ManuallyDrop};
+use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 
 use parking_lot::Mutex;
@@ -26,26 +26,21 @@ impl<T> Pool<T> {
         }
     }
 
-    #[inline]
+    #[cfg(test)]
     pub fn len(&self) -> usize {
         self.objects.lock().len()
     }
 
     #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.objects.lock().is_empty()
-    }
-
-    #[inline]
-    pub fn try_pull(&self) -> Option<Reusable<T>> {
+    pub fn try_pull(&self) -> Option<Reusable<'_, T>> {
         self.objects
             .lock()
             .pop()
             .map(|data| Reusable::new(self, data))
     }
 
-    #[inline]
-    pub fn pull<F: Fn() -> T>(&self, fallback: F) -> Reusable<T> {
+    #[cfg(test)]
+    pub fn pull<F: Fn() -> T>(&self, fallback: F) -> Reusable<'_, T> {
         self.try_pull()
             .unwrap_or_else(|| Reusable::new(self, fallback()))
     }
@@ -70,10 +65,10 @@ impl<'a, T> Reusable<'a, T> {
         }
     }
 
-    #[inline]
+    #[cfg(test)]
     pub fn detach(mut self) -> (&'a Pool<T>, T) {
         let ret = unsafe { (self.pool, self.take()) };
-        forget(self);
+        std::mem::forget(self);
         ret
     }
 
@@ -113,16 +108,16 @@ mod tests {
 
     #[test]
     fn detach() {
-        let pool = Pool::new(1, || Vec::new());
+        let pool = Pool::new(1, Vec::new);
         let (pool, mut object) = pool.try_pull().unwrap().detach();
         object.push(1);
-        Reusable::new(&pool, object);
+        Reusable::new(pool, object);
         assert_eq!(pool.try_pull().unwrap()[0], 1);
     }
 
     #[test]
     fn detach_then_attach() {
-        let pool = Pool::new(1, || Vec::new());
+        let pool = Pool::new(1, Vec::new);
         let (pool, mut object) = pool.try_pull().unwrap().detach();
         object.push(1);
         pool.attach(object);
@@ -131,11 +126,11 @@ mod tests {
 
     #[test]
     fn pull() {
-        let pool = Pool::<Vec<u8>>::new(1, || Vec::new());
+        let pool = Pool::<Vec<u8>>::new(1, Vec::new);
 
         let object1 = pool.try_pull();
         let object2 = pool.try_pull();
-        let object3 = pool.pull(|| Vec::new());
+        let object3 = pool.pull(Vec::new);
 
         assert!(object1.is_some());
         assert!(object2.is_none());
@@ -147,7 +142,7 @@ mod tests {
 
     #[test]
     fn e2e() {
-        let pool = Pool::new(10, || Vec::new());
+        let pool = Pool::new(10, Vec::new);
         let mut objects = Vec::new();
 
         for i in 0..10 {
@@ -160,7 +155,7 @@ mod tests {
         drop(objects);
         assert!(pool.try_pull().is_some());
 
-        for i in 10..0 {
+        for i in (0..10).rev() {
             let mut object = pool.objects.lock().pop().unwrap();
             assert_eq!(object.pop(), Some(i));
         }
@@ -24,11 +24,8 @@ impl RingStripe {
     fn push(&mut self, item: u64) {
         self.data.push(item);
         if self.data.len() >= self.capacity {
-            if self.consumer.push(self.data.clone()) {
-                self.data.clear()
-            } else {
-                self.data.truncate(0)
-            }
+            self.consumer.push(self.data.clone());
+            self.data.clear()
         }
     }
 }
@@ -54,7 +54,7 @@ where
     }
 
     fn update(&self, entry: &Entry<V>) -> Option<V> {
-        self.shards[(entry.key % NUM_SHARDS) as usize].update(&entry)
+        self.shards[(entry.key % NUM_SHARDS) as usize].update(entry)
     }
 
     fn cleanup(&self, policy: &dyn Policy, handler: &dyn Handler<V>) {
@@ -83,12 +83,6 @@ where
             })
         }
     }
-
-    fn clear(&self, callback: &dyn Handler<V>) {
-        for i in 0..NUM_SHARDS {
-            self.shards[i as usize].clear(callback)
-        }
-    }
 }
 
 #[derive(Debug)]
@@ -129,8 +123,8 @@ where
             return None;
         }
 
-        let now = SystemTime::now();
-        if now > entry.exp {
+        // entries without a ttl never expire
+        if !is_time_zero(&entry.exp) && SystemTime::now() > entry.exp {
             return None;
         }
         entry.value.clone()
@@ -171,19 +165,6 @@ where
         self.em
             .update(new_entry.key, new_entry.conflict, entry.exp, new_entry.exp);
         let entry = data.insert(new_entry.key, new_entry.clone());
-        entry.map(|v| v.value).flatten()
-    }
-
-    fn clear(&self, handler: &dyn Handler<V>) {
-        let mut data = self.data.write();
-        for (_, e) in data.iter() {
-            handler.on_evict(PartialEntry {
-                key: e.key,
-                conflict: e.conflict,
-                value: e.value.clone(),
-                cost: e.cost,
-            });
-        }
-        data.clear();
+        entry.and_then(|v| v.value)
     }
 }
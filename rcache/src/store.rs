@@ -13,5 +13,4 @@ pub(crate) trait Store<V>
     fn remove(&self, key: u64, conflict: u64) -> (u64, Option<V>);
     fn update(&self, entry: &Entry<V>) -> Option<V>;
     fn cleanup(&self, policy: &dyn Policy, handler: &dyn Handler<V>);
-    fn clear(&self, callback: &dyn Handler<V>);
 }
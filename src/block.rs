@@ -1,169 +1,493 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::io::Write;
-use crate::codec::decode_fixed32;
-use crate::constant::{BLOCK_ENTRY_HEADER_SIZE, BLOCK_META_SIZE, CHECKSUM_SIZE};
-use crate::table::{decode_key, decode_key_value, TableOptions};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::cmp::Comparator;
+use crate::codec::{decode_fixed32, decode_varint32, encode_varint32};
+use crate::compress::{compress, decompress};
+use crate::constant::{BLOCK_META_SIZE, CHECKSUM_SIZE, COMPRESSION_TYPE_SIZE};
+use crate::table::{CompressionType, TableOptions, LATEST_FORMAT_VERSION};
 use crate::{ensure, Error};
 
-#[derive(Debug)]
-pub(crate) struct Block<'a> {
-    pub(crate) block_offset: usize,
-    pub(crate) data: &'a [u8],
-    pub(crate) entry_offsets: Vec<usize>,
-    pub(crate) checksum: u32,
+fn bad_entry() -> Error {
+    Error::Corruption("bad block entry".to_string())
+}
+
+/// Entry is an entry decoded from a block. Its key is the first `shared` bytes of the key
+/// before it followed by `unshared`.
+struct Entry<'a> {
+    shared: usize,
+    unshared: &'a [u8],
+    value: Range<usize>,
+    // offset of the entry after it
+    next: usize,
+}
+
+impl Entry<'_> {
+    /// apply turns `key`, the key of the entry before, into the key of this entry.
+    fn apply(&self, key: &mut Vec<u8>) -> crate::Result<()> {
+        ensure!(self.shared <= key.len(), bad_entry());
+        key.truncate(self.shared);
+        key.extend_from_slice(self.unshared);
+        Ok(())
+    }
+}
+
+/// write_block writes `data` to `dst` with the trailer every block ends with, compressed with
+/// `compression` if that saves enough, and returns the number of bytes written.
+pub(crate) fn write_block<W: Write>(
+    dst: &mut W,
+    data: &[u8],
+    compression: CompressionType,
+    opts: &TableOptions,
+) -> crate::Result<usize> {
+    let mut compressed = Vec::new();
+    let (payload, typ) = match compression {
+        CompressionType::None => (data, CompressionType::None),
+        typ => {
+            compress(typ, data, &mut compressed)?;
+            let max_size = data.len() as f64 * (1.0 - opts.min_compression_saving);
+            if compressed.len() as f64 <= max_size {
+                (compressed.as_slice(), typ)
+            } else {
+                (data, CompressionType::None)
+            }
+        }
+    };
+    let mut digest = crc32fast::Hasher::new();
+    digest.update(payload);
+    digest.update(&[typ as u8]);
+    let crc = digest.finalize();
+
+    dst.write_all(payload)?;
+    dst.write_all(&[typ as u8])?;
+    dst.write_all(&crc.to_le_bytes())?;
+    Ok(payload.len() + COMPRESSION_TYPE_SIZE + CHECKSUM_SIZE)
+}
+
+/// read_block checks the trailer of a block written by `write_block` and returns its contents
+/// along with its checksum. Uncompressed contents are borrowed from `data`.
+pub(crate) fn read_block<'a>(
+    data: &'a [u8],
+    opts: &TableOptions,
+) -> crate::Result<(Cow<'a, [u8]>, u32)> {
+    ensure!(
+        data.len() >= COMPRESSION_TYPE_SIZE + CHECKSUM_SIZE,
+        Error::Corruption("truncated block".to_string())
+    );
+    let checksum = decode_fixed32(&data[data.len() - CHECKSUM_SIZE..data.len()]);
+    if opts.checksum {
+        let cal_checksum = crc32fast::hash(&data[..data.len() - CHECKSUM_SIZE]);
+        ensure!(
+            cal_checksum == checksum,
+            Error::Corruption("checksum failed".to_string())
+        );
+    }
+    let payload_len = data.len() - COMPRESSION_TYPE_SIZE - CHECKSUM_SIZE;
+    let contents = match CompressionType::try_from(data[payload_len])? {
+        CompressionType::None => Cow::Borrowed(&data[..payload_len]),
+        typ => Cow::Owned(decompress(typ, &data[..payload_len])?),
+    };
+    Ok((contents, checksum))
+}
+
+/// Restarts locates the entries of a block whose keys are stored in full, where decoding can
+/// start. Entries are encoded, depending on the format version of the table, as
+///
+/// ```text
+/// 0: key_len: u32 | value_len: u32 | key | value
+/// 1: shared: u32 | unshared: u32 | value_len: u32 | key[shared..] | value
+/// 2: shared: varint32 | unshared: varint32 | value_len: varint32 | key[shared..] | value
+/// ```
+///
+/// where `shared` is the length of the prefix the key has in common with the key before it,
+/// and 0 at restart points. In format version 0 every entry is a restart point.
+#[derive(Debug, Clone)]
+struct Restarts {
+    offsets: Vec<usize>,
+    // where the entries end and the restart offsets begin
+    entries_end: usize,
+    format_version: u32,
+}
+
+impl Restarts {
+    fn parse(data: &[u8], format_version: u32) -> crate::Result<Self> {
+        ensure!(
+            format_version <= LATEST_FORMAT_VERSION,
+            Error::Corruption(format!("unknown table format version {}", format_version))
+        );
+        ensure!(
+            data.len() >= BLOCK_META_SIZE,
+            Error::Corruption("truncated block".to_string())
+        );
+        let count = decode_fixed32(&data[data.len() - BLOCK_META_SIZE..]) as usize;
+        ensure!(
+            data.len() >= BLOCK_META_SIZE + count * 4,
+            Error::Corruption("bad block restart count".to_string())
+        );
+        let entries_end = data.len() - BLOCK_META_SIZE - count * 4;
+        let offsets: Vec<_> = data[entries_end..entries_end + count * 4]
+            .chunks_exact(4)
+            .map(|k| decode_fixed32(k) as usize)
+            .collect();
+        ensure!(
+            offsets.iter().all(|offset| *offset < entries_end),
+            Error::Corruption("bad block restart offset".to_string())
+        );
+        Ok(Self {
+            offsets,
+            entries_end,
+            format_version,
+        })
+    }
+
+    fn entry<'a>(&self, data: &'a [u8], offset: usize) -> crate::Result<Entry<'a>> {
+        let entries = &data[..self.entries_end];
+        let mut at = offset;
+        let mut field = || -> crate::Result<usize> {
+            let v = if self.format_version < 2 {
+                ensure!(at + 4 <= entries.len(), bad_entry());
+                at += 4;
+                decode_fixed32(&entries[at - 4..at])
+            } else {
+                let (v, len) = decode_varint32(&entries[at..]).map_err(|_| bad_entry())?;
+                at += len;
+                v
+            };
+            Ok(v as usize)
+        };
+        let shared = if self.format_version == 0 {
+            0
+        } else {
+            field()?
+        };
+        let unshared_len = field()?;
+        let value_len = field()?;
+        let key_start = at;
+        let value_start = key_start + unshared_len;
+        let next = value_start + value_len;
+        ensure!(next <= self.entries_end, bad_entry());
+        Ok(Entry {
+            shared,
+            unshared: &data[key_start..value_start],
+            value: value_start..next,
+            next,
+        })
+    }
+
+    /// find returns the last restart point whose key sorts before `target`, or the first one,
+    /// which is where a scan for the first entry at or after `target` starts.
+    fn find(&self, data: &[u8], cmp: &dyn Comparator, target: &[u8]) -> crate::Result<usize> {
+        let (mut low, mut high) = (0, self.offsets.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let entry = self.entry(data, self.offsets[mid])?;
+            ensure!(entry.shared == 0, bad_entry());
+            if cmp.cmp(entry.unshared, target)? == Ordering::Less {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low.saturating_sub(1))
+    }
 }
 
+/// Block is a table block opened for reading. Its data is borrowed from the table unless the
+/// block was compressed.
+///
+/// A block is laid out as
+///
+/// ```text
+/// entries | restart offsets: u32 * n | n: u32 | compression type: u8 | crc: u32
+/// ```
+///
+/// where everything before the compression type may be compressed, and the checksum covers
+/// the compressed bytes and the compression type.
 #[derive(Debug)]
-pub(crate) struct OwnedBlock {
+pub(crate) struct Block<'a> {
     pub(crate) block_offset: usize,
-    pub(crate) data: Vec<u8>,
-    pub(crate) entry_offsets: Vec<usize>,
+    pub(crate) data: BlockData<'a>,
+    restarts: Restarts,
     pub(crate) checksum: u32,
+    cmp: Arc<Box<dyn Comparator>>,
 }
 
 impl<'a> Block<'a> {
     pub(crate) fn open(
         block_offset: usize,
         data: &'a [u8],
+        format_version: u32,
         opts: &TableOptions,
     ) -> crate::Result<Self> {
-        let checksum = decode_fixed32(&data[data.len() - CHECKSUM_SIZE..data.len()]);
-        if opts.checksum {
-            let cal_checksum = crc32fast::hash(&data[..data.len() - CHECKSUM_SIZE]);
-            ensure!(
-                cal_checksum == checksum,
-                Error::Corruption("checksum failed".to_string())
-            );
-        }
-        let block_meta_size = decode_fixed32(
-            &data[data.len() - CHECKSUM_SIZE - BLOCK_META_SIZE..data.len() - CHECKSUM_SIZE],
-        ) as usize;
-        let block_meta_offset =
-            data.len() - CHECKSUM_SIZE - BLOCK_META_SIZE - (block_meta_size * 4);
-        let block_metadata = &data[block_meta_offset..block_meta_offset + (block_meta_size * 4)];
-        let entry_offsets: Vec<_> = block_metadata
-            .chunks_exact(4)
-            .map(|k| decode_fixed32(k) as usize)
-            .collect();
+        let (data, checksum) = read_block(data, opts)?;
+        let data = match data {
+            Cow::Borrowed(data) => BlockData::Borrowed(data),
+            Cow::Owned(data) => BlockData::Owned(data),
+        };
+        let restarts = Restarts::parse(&data, format_version)?;
         Ok(Self {
             block_offset,
             data,
-            entry_offsets,
+            restarts,
             checksum,
+            cmp: opts.comparator.clone(),
         })
     }
 
-    pub(crate) fn get(&'a self, key: &[u8]) -> Option<&'a [u8]> {
-        match self.get_value_offset(key) {
-            None => None,
-            Some((start, end)) => Some(&self.data[start..end]),
+    /// get returns the first entry at or after `key`, or `None` when every entry sorts before
+    /// it.
+    pub(crate) fn get(&self, key: &[u8]) -> crate::Result<Option<(Vec<u8>, &[u8])>> {
+        if self.restarts.offsets.is_empty() {
+            return Ok(None);
         }
+        let restart = self.restarts.find(&self.data, &**self.cmp, key)?;
+        let mut offset = self.restarts.offsets[restart];
+        let mut current = Vec::new();
+        while offset < self.restarts.entries_end {
+            let entry = self.restarts.entry(&self.data, offset)?;
+            entry.apply(&mut current)?;
+            if self.cmp.cmp(&current, key)? != Ordering::Less {
+                return Ok(Some((current, &self.data[entry.value])));
+            }
+            offset = entry.next;
+        }
+        Ok(None)
     }
 
-    pub(crate) fn get_value_offset(&self, key: &[u8]) -> Option<(usize, usize)> {
-        self.entry_offsets
-            .binary_search_by(|entry_offset| {
-                let entry_key = decode_key(&self.data[*entry_offset..]);
-                entry_key.cmp(key)
-            })
-            .map(|index| {
-                let entry_offset = self.entry_offsets[index];
-                let key_size = decode_fixed32(&self.data[entry_offset..entry_offset + 4]) as usize;
-                let value_size =
-                    decode_fixed32(&self.data[entry_offset + 4..entry_offset + 8]) as usize;
-                let value_offset_start = BLOCK_ENTRY_HEADER_SIZE + key_size;
-                let value_offset_end = value_offset_start + value_size;
-                Some((value_offset_start, value_offset_end))
-            })
-            .unwrap_or_default()
-    }
-
-    pub(crate) fn get_value_offset_abs(&self, key: &[u8]) -> Option<(usize, usize)> {
-        self.get_value_offset(key)
-            .map(|(start, end)| (start + self.block_offset, end + self.block_offset))
+    /// into_owned copies the data of the block if it's borrowed, so the block can outlive the
+    /// table it was read from.
+    pub(crate) fn into_owned(self) -> Block<'static> {
+        self.map_data(|data, _| match data {
+            BlockData::Borrowed(data) => BlockData::Owned(data.to_vec()),
+            BlockData::Owned(data) => BlockData::Owned(data),
+            BlockData::Mapped(file, range) => BlockData::Mapped(file, range),
+        })
     }
 
-    pub(crate) fn size(&self) -> usize {
-        self.entry_offsets.len()
+    /// into_shared keeps a block borrowed from `file`, which it was opened from, mapped by
+    /// holding on to `file` instead of copying it.
+    pub(crate) fn into_shared(self, file: &Arc<Mmap>) -> Block<'static> {
+        self.map_data(|data, block_offset| match data {
+            BlockData::Borrowed(data) => {
+                // the contents of an uncompressed block start where the block does
+                let range = block_offset..block_offset + data.len();
+                debug_assert_eq!(file[range.clone()].as_ptr(), data.as_ptr());
+                BlockData::Mapped(file.clone(), range)
+            }
+            BlockData::Owned(data) => BlockData::Owned(data),
+            BlockData::Mapped(file, range) => BlockData::Mapped(file, range),
+        })
     }
 
-    fn to_owned(self) -> OwnedBlock {
-        OwnedBlock {
+    fn map_data<'b>(self, f: impl FnOnce(BlockData<'a>, usize) -> BlockData<'b>) -> Block<'b> {
+        Block {
             block_offset: self.block_offset,
-            data: self.data.to_vec(),
-            entry_offsets: self.entry_offsets,
+            data: f(self.data, self.block_offset),
+            restarts: self.restarts,
             checksum: self.checksum,
+            cmp: self.cmp,
         }
     }
+
     pub(crate) fn into_iter(self) -> BlockIterator {
-        let cursor = 0;
-        BlockIterator {
-            cursor,
-            block: self.to_owned(),
+        BlockIterator::new(Arc::new(self.into_owned()))
+    }
+}
+
+/// BlockData holds the contents of a block: borrowed from the table, decompressed into a
+/// buffer of its own, or in a mapped table file the block shares.
+#[derive(Debug)]
+pub(crate) enum BlockData<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl Deref for BlockData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockData::Borrowed(data) => data,
+            BlockData::Owned(data) => data,
+            BlockData::Mapped(file, range) => &file[range.clone()],
         }
     }
 }
 
+/// BlockIterator walks the entries of a block. Keys are rebuilt from the restart point before
+/// them, so `prev` decodes forward from there.
 #[derive(Debug)]
 pub(crate) struct BlockIterator {
-    cursor: isize,
-    block: OwnedBlock,
+    block: Arc<Block<'static>>,
+    // offset of the current entry and of the one after it
+    offset: usize,
+    next: usize,
+    // index of the last restart point at or before the current entry
+    restart: usize,
+    key: Vec<u8>,
+    value: Range<usize>,
+    valid: bool,
+    error: Option<Error>,
 }
 
 impl BlockIterator {
+    /// new returns an iterator at the first entry of `block`, which blocks from the block cache
+    /// are shared with.
+    pub(crate) fn new(block: Arc<Block<'static>>) -> BlockIterator {
+        let mut iter = BlockIterator {
+            block,
+            offset: 0,
+            next: 0,
+            restart: 0,
+            key: Vec::new(),
+            value: 0..0,
+            valid: false,
+            error: None,
+        };
+        iter.seek_to_first();
+        iter
+    }
+
     pub(crate) fn valid(&self) -> bool {
-        if self.cursor < 0 || self.cursor > self.block.entry_offsets.len() as isize - 1 {
-            return false;
+        self.valid
+    }
+
+    /// status returns the error that stopped the iterator, if any.
+    pub(crate) fn status(&mut self) -> crate::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
         }
-        true
+    }
+
+    fn seek_to_restart(&mut self, restart: usize) {
+        self.key.clear();
+        self.restart = restart;
+        self.next = self.block.restarts.offsets[restart];
+    }
+
+    /// parse_next decodes the entry after the current one, leaving the iterator invalid past
+    /// the last entry or on corruption.
+    fn parse_next(&mut self) {
+        let restarts = &self.block.restarts;
+        if self.next >= restarts.entries_end {
+            self.offset = restarts.entries_end;
+            self.valid = false;
+            return;
+        }
+        let entry = match restarts
+            .entry(&self.block.data, self.next)
+            .and_then(|entry| entry.apply(&mut self.key).map(|()| entry))
+        {
+            Ok(entry) => entry,
+            Err(err) => {
+                self.error = Some(err);
+                self.valid = false;
+                return;
+            }
+        };
+        self.offset = self.next;
+        self.next = entry.next;
+        self.value = entry.value;
+        while restarts
+            .offsets
+            .get(self.restart + 1)
+            .is_some_and(|offset| *offset <= self.offset)
+        {
+            self.restart += 1;
+        }
+        self.valid = true;
     }
 
     pub(crate) fn prev(&mut self) {
         if !self.valid() {
             return;
         }
-        self.cursor -= 1;
+        let original = self.offset;
+        let mut restart = self.restart;
+        while self.block.restarts.offsets[restart] >= original {
+            if restart == 0 {
+                // before the first entry
+                self.valid = false;
+                return;
+            }
+            restart -= 1;
+        }
+        self.seek_to_restart(restart);
+        loop {
+            self.parse_next();
+            if !self.valid || self.next >= original {
+                return;
+            }
+        }
     }
 
     pub(crate) fn next(&mut self) {
         if !self.valid() {
             return;
         }
-        self.cursor += 1;
+        self.parse_next();
     }
 
     pub(crate) fn current(&self) -> Option<(&[u8], &[u8])> {
-        self.block
-            .entry_offsets
-            .get(self.cursor as usize)
-            .map(|entry_offset| decode_key_value(&self.block.data[*entry_offset..]))
-    }
-
-    pub(crate) fn seek(&mut self, target: &[u8]) {
-        self.cursor = match self.block.entry_offsets.binary_search_by(|entry_offset| {
-            let entry_key = decode_key(&self.block.data[*entry_offset..]);
-            entry_key.cmp(target)
-        }) {
-            Ok(index) => index as isize,
-            Err(index) => index as isize,
-        };
+        self.valid
+            .then(|| (self.key.as_slice(), &self.block.data[self.value.clone()]))
+    }
+
+    pub(crate) fn seek(&mut self, target: &[u8]) -> crate::Result<()> {
+        if self.block.restarts.offsets.is_empty() {
+            self.valid = false;
+            return Ok(());
+        }
+        let restart = self
+            .block
+            .restarts
+            .find(&self.block.data, &**self.block.cmp, target)?;
+        self.seek_to_restart(restart);
+        loop {
+            self.parse_next();
+            if !self.valid || self.block.cmp.cmp(&self.key, target)? != Ordering::Less {
+                return self.status();
+            }
+        }
     }
 
     pub(crate) fn seek_to_first(&mut self) {
-        self.cursor = (*self.block.entry_offsets.first().unwrap_or(&0)) as isize;
+        if self.block.restarts.offsets.is_empty() {
+            self.valid = false;
+            return;
+        }
+        self.seek_to_restart(0);
+        self.parse_next();
     }
 
     pub(crate) fn seek_to_last(&mut self) {
-        self.cursor = (*self.block.entry_offsets.last().unwrap_or(&0)) as isize;
+        if self.block.restarts.offsets.is_empty() {
+            self.valid = false;
+            return;
+        }
+        self.seek_to_restart(self.block.restarts.offsets.len() - 1);
+        self.parse_next();
+        while self.valid && self.next < self.block.restarts.entries_end {
+            self.parse_next();
+        }
     }
 }
 
 pub(crate) struct BlockBuilder {
     pub(crate) data: Vec<u8>,
     pub(crate) base_key: Vec<u8>,
-    pub(crate) entry_offsets: Vec<u32>,
+    pub(crate) restarts: Vec<u32>,
     pub(crate) key_hashes: Vec<u32>,
     pub(crate) entry_count: usize,
-    pub(crate) entries_offset: u32,
+    // the key added last, which the next one is encoded against
+    last_key: Vec<u8>,
 }
 
 impl BlockBuilder {
@@ -171,29 +495,80 @@ impl BlockBuilder {
         Self {
             data: vec![],
             base_key: vec![],
-            entry_offsets: vec![],
+            restarts: vec![],
             key_hashes: vec![],
             entry_count: 0,
-            entries_offset: 0,
+            last_key: vec![],
+        }
+    }
+
+    /// add appends an entry in the format version of `opts`. Keys must be added in order.
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8], opts: &TableOptions) {
+        let restart_interval = match opts.format_version {
+            0 => 1,
+            _ => opts.block_restart_interval.max(1),
+        };
+        let mut shared = 0;
+        if self.entry_count.is_multiple_of(restart_interval) {
+            self.restarts.push(self.data.len() as u32);
+        } else {
+            shared = key
+                .iter()
+                .zip(self.last_key.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        let unshared = (key.len() - shared) as u32;
+        match opts.format_version {
+            0 => {
+                self.data.extend_from_slice(&unshared.to_le_bytes());
+                self.data
+                    .extend_from_slice(&(value.len() as u32).to_le_bytes());
+            }
+            1 => {
+                self.data.extend_from_slice(&(shared as u32).to_le_bytes());
+                self.data.extend_from_slice(&unshared.to_le_bytes());
+                self.data
+                    .extend_from_slice(&(value.len() as u32).to_le_bytes());
+            }
+            _ => {
+                encode_varint32(&mut self.data, shared as u32);
+                encode_varint32(&mut self.data, unshared);
+                encode_varint32(&mut self.data, value.len() as u32);
+            }
+        }
+        self.data.extend_from_slice(&key[shared..]);
+        self.data.extend_from_slice(value);
+
+        if self.entry_count == 0 {
+            self.base_key = key.to_vec();
         }
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.entry_count += 1;
     }
 
-    pub(crate) fn finish<W: Write>(&mut self, dst: &mut W) -> crate::Result<usize> {
-        let offset_count = self.entry_offsets.len();
-        for offset in &self.entry_offsets {
+    /// finish writes the block to `dst`, compressed if that saves enough, and resets the
+    /// builder for the next block.
+    pub(crate) fn finish<W: Write>(
+        &mut self,
+        dst: &mut W,
+        opts: &TableOptions,
+    ) -> crate::Result<usize> {
+        let restart_count = self.restarts.len();
+        for offset in &self.restarts {
             self.data.extend_from_slice(offset.to_le_bytes().as_slice());
         }
         self.data
-            .extend_from_slice(&(offset_count as u32).to_le_bytes());
-        let crc = crc32fast::hash(self.data.as_slice());
+            .extend_from_slice(&(restart_count as u32).to_le_bytes());
 
-        let written_bytes = dst.write(self.data.as_slice())? + dst.write(&crc.to_le_bytes())?;
+        let written_bytes = write_block(dst, &self.data, opts.compression, opts)?;
         self.data.clear();
         self.base_key.clear();
-        self.entry_offsets.clear();
+        self.restarts.clear();
         self.key_hashes.clear();
         self.entry_count = 0;
-        self.entries_offset = 0;
+        self.last_key.clear();
 
         Ok(written_bytes)
     }
@@ -204,3 +579,73 @@ impl Default for BlockBuilder {
         BlockBuilder::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{Block, BlockBuilder};
+    use crate::table::TableOptions;
+
+    fn build(keys: &[String], opts: &TableOptions) -> Vec<u8> {
+        let mut builder = BlockBuilder::new();
+        for key in keys {
+            builder.add(key.as_bytes(), key.to_uppercase().as_bytes(), opts);
+        }
+        let mut data = Vec::new();
+        builder.finish(&mut data, opts).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_block_formats() {
+        let keys: Vec<String> = (0..100_u32)
+            .map(|i| format!("user/{:04}/profile", i * 2))
+            .collect();
+        let mut sizes = Vec::new();
+        let formats = [(0, 16), (1, 1), (1, 4), (1, 16), (2, 16)];
+        for (format_version, block_restart_interval) in formats {
+            let opts = TableOptions {
+                format_version,
+                block_restart_interval,
+                ..Default::default()
+            };
+            let data = build(&keys, &opts);
+            sizes.push(data.len());
+            let block = Block::open(0, &data, format_version, &opts).unwrap();
+
+            for (i, key) in keys.iter().enumerate() {
+                let (found, value) = block.get(key.as_bytes()).unwrap().unwrap();
+                assert_eq!(found, key.as_bytes());
+                assert_eq!(value, key.to_uppercase().as_bytes());
+                // between two keys
+                let odd = format!("user/{:04}", i * 2 + 1);
+                let next = block.get(odd.as_bytes()).unwrap().map(|(k, _)| k);
+                assert_eq!(next, keys.get(i + 1).map(|k| k.as_bytes().to_vec()));
+            }
+            assert_eq!(block.get(b"a").unwrap().unwrap().0, keys[0].as_bytes());
+
+            let mut iter = block.into_iter();
+            for key in keys.iter() {
+                assert_eq!(iter.current().unwrap().0, key.as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for key in keys.iter().rev() {
+                assert_eq!(iter.current().unwrap().0, key.as_bytes());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.seek(b"user/0051").unwrap();
+            assert_eq!(iter.current().unwrap().0, keys[26].as_bytes());
+            iter.prev();
+            assert_eq!(iter.current().unwrap().0, keys[25].as_bytes());
+            iter.seek(b"z").unwrap();
+            assert!(!iter.valid());
+            assert!(iter.status().is_ok());
+        }
+        // shared prefixes are only stored once between restart points
+        assert!(sizes[3] < sizes[2] && sizes[2] < sizes[0]);
+        // and lengths take a byte each with varints
+        assert_eq!(sizes[3] - sizes[4], keys.len() * 9);
+    }
+}
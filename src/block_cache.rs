@@ -0,0 +1,131 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rcache::{Cache, Config, Cost};
+
+use crate::block::Block;
+use crate::types::{make_cache_key, CacheKey};
+
+/// BlockCacheStats counts the lookups of table blocks in the block cache.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct BlockCost;
+
+impl Cost<Arc<Block<'static>>> for BlockCost {
+    fn cost(&self, block: &Arc<Block<'static>>) -> i64 {
+        block.data.len() as i64
+    }
+}
+
+/// BlockCache keeps decoded table blocks, keyed by table number and block offset, up to a
+/// total size in bytes. One cache is shared by the tables of a database.
+pub(crate) struct BlockCache {
+    cache: Cache<CacheKey, Arc<Block<'static>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BlockCache {
+    /// new returns a cache for `capacity` bytes of blocks, which are about `block_size` bytes
+    /// each.
+    pub(crate) fn new(capacity: usize, block_size: usize) -> Self {
+        let config = Config {
+            // the admission policy wants about ten counters for each block it may hold
+            num_counters: ((capacity / block_size.max(1)).max(1) * 10) as u64,
+            max_cost: capacity as i64,
+            enable_metrics: false,
+            ..Default::default()
+        };
+        Self {
+            cache: Cache::with_config(config, BlockCost),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn get(&self, table: u64, offset: usize) -> Option<Arc<Block<'static>>> {
+        let block = self.cache.get(make_cache_key(table, offset as u64));
+        let counter = match block {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// insert offers `block` to the cache, which admits it in the background unless its policy
+    /// deems it less valuable than the blocks it would evict.
+    pub(crate) fn insert(&self, table: u64, offset: usize, block: Arc<Block<'static>>) {
+        // a block the cache drops is read from the table again next time
+        let _ = self
+            .cache
+            .insert(make_cache_key(table, offset as u64), block);
+    }
+
+    pub(crate) fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Debug for BlockCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::block::{Block, BlockBuilder};
+    use crate::block_cache::BlockCache;
+    use crate::table::TableOptions;
+
+    fn block(key: &[u8]) -> Arc<Block<'static>> {
+        let opts = TableOptions::default();
+        let mut builder = BlockBuilder::new();
+        builder.add(key, b"value", &opts);
+        let mut data = Vec::new();
+        builder.finish(&mut data, &opts).unwrap();
+        Arc::new(
+            Block::open(0, &data, opts.format_version, &opts)
+                .unwrap()
+                .into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_get_insert() {
+        let cache = BlockCache::new(1 << 20, 4096);
+        assert!(cache.get(1, 0).is_none());
+        cache.insert(1, 0, block(b"a"));
+        cache.insert(1, 100, block(b"b"));
+        // the cache admits blocks in the background
+        let mut cached = None;
+        for _ in 0..100 {
+            cached = cache.get(1, 100);
+            if cached.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (key, _) = cached.unwrap().get(b"b").unwrap().unwrap();
+        assert_eq!(key, b"b");
+        assert!(cache.get(2, 100).is_none());
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        // the lookups before the block was admitted and the one of another table missed
+        assert!(stats.misses >= 2);
+    }
+}
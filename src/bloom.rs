@@ -19,11 +19,8 @@ fn hash(data: &[u8], seed: u32) -> u32 {
     }
     assert!(limit - ix < 4);
     if limit - ix > 0 {
-        let mut i = 0;
-
-        for b in data[ix..].iter() {
+        for (i, b) in data[ix..].iter().enumerate() {
             h = h.overflowing_add((*b as u32) << (8 * i)).0;
-            i += 1;
         }
 
         h = (h as u64 * m as u64) as u32;
@@ -32,7 +29,7 @@ fn hash(data: &[u8], seed: u32) -> u32 {
     h
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct BloomFilterPolicy {
     bit_per_key: usize,
     k: usize,
@@ -48,39 +45,14 @@ impl BloomFilterPolicy {
         Self { bit_per_key, k }
     }
 
-    pub(crate) fn create_filter<'a, Keys: AsRef<[&'a [u8]]>>(&self, keys: Keys, dst: &mut Vec<u8>) {
-        let mut bits = keys.as_ref().len() * self.bit_per_key;
-        if bits < 64 {
-            bits = 64;
-        }
-        let bytes = (bits + 7) / 8;
-        bits = bytes * 8;
-        let initial_size = dst.len();
-        dst.resize(bytes + initial_size, 0);
-        dst.push(self.k as u8);
-
-        let array = &mut dst[initial_size..];
-        for key in keys.as_ref() {
-            // Use double-hashing to generate a sequence of hash values.
-            // See analysis in [Kirsch,Mitzenmacher 2006].
-            let mut h = bloom_hash(key);
-            let delta = (h >> 17) | (h << 15);
-
-            for _ in 0..self.k {
-                let bitpos = h % (bits as u32);
-                array[(bitpos / 8) as usize] |= 1 << (bitpos % 8);
-                h = h.overflowing_add(delta).0;
-            }
-        }
+    /// filter_len is the size of a filter built from `keys` keys.
+    pub(crate) fn filter_len(&self, keys: usize) -> usize {
+        (keys * self.bit_per_key).max(64).div_ceil(8) + 1
     }
 
     pub(crate) fn create_filter_from_hashes(&self, hashes: &[u32]) -> Vec<u8> {
-        let mut bits = hashes.as_ref().len() * self.bit_per_key;
-        if bits < 64 {
-            bits = 64;
-        }
-        let bytes = (bits + 7) / 8;
-        bits = bytes * 8;
+        let bytes = self.filter_len(hashes.len()) - 1;
+        let bits = bytes * 8;
         let mut dst = vec![0; bytes];
         dst.push(self.k as u8);
 
@@ -89,7 +61,7 @@ impl BloomFilterPolicy {
             // Use double-hashing to generate a sequence of hash values.
             // See analysis in [Kirsch,Mitzenmacher 2006].
             let mut h = *key_hash;
-            let delta = (h >> 17) | (h << 15);
+            let delta = h.rotate_left(15);
             for _ in 0..self.k {
                 let bitpos = h % (bits as u32);
                 array[(bitpos / 8) as usize] |= 1 << (bitpos % 8);
@@ -110,7 +82,7 @@ impl BloomFilterPolicy {
         }
 
         let mut h = bloom_hash(key);
-        let delta = (h >> 17) | (h << 15);
+        let delta = h.rotate_left(15);
         for _ in 0..k {
             let bitpos = h % (bits as u32);
             if (bloom_filter[(bitpos / 8) as usize] & (1 << (bitpos % 8))) == 0 {
@@ -118,7 +90,7 @@ impl BloomFilterPolicy {
             }
             h = h.overflowing_add(delta).0;
         }
-        return true;
+        true
     }
 }
 
@@ -126,7 +98,7 @@ impl BloomFilterPolicy {
 mod test {
     use test_utils::bloom_test::BloomTest;
 
-    use crate::bloom::BloomFilterPolicy;
+    use crate::bloom::{bloom_hash, BloomFilterPolicy};
 
     struct BloomTestImpl {
         policy: BloomFilterPolicy,
@@ -158,7 +130,7 @@ mod test {
             if !self.keys.is_empty() {
                 self.build();
             }
-            self.policy.key_and_match(key, &mut self.filter)
+            self.policy.key_and_match(key, &self.filter)
         }
 
         fn filter_size(&self) -> usize {
@@ -166,12 +138,8 @@ mod test {
         }
 
         fn build(&mut self) {
-            let mut key_slices = Vec::new();
-            for key in self.keys.iter() {
-                key_slices.push(key.as_slice())
-            }
-            self.filter.clear();
-            self.policy.create_filter(key_slices, &mut self.filter);
+            let hashes: Vec<u32> = self.keys.iter().map(|key| bloom_hash(key)).collect();
+            self.filter = self.policy.create_filter_from_hashes(&hashes);
             self.keys.clear()
         }
     }
@@ -1,30 +1,123 @@
 use std::cmp::Ordering;
+use std::fmt::Debug;
 
-pub trait Comparator {
+use crate::codec::Codec;
+use crate::types::{split_internal_key, user_key, MemEntry};
+
+pub trait Comparator: Debug + Send + Sync {
     fn cmp(&self, a: &[u8], b: &[u8]) -> crate::Result<Ordering>;
+
+    /// name identifies the order, which tables record in their properties.
+    fn name(&self) -> &'static str;
+
+    /// user_key is the part of `key` point lookups match on, which is what table filters are
+    /// built from.
+    fn user_key<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        key
+    }
+
+    /// tag returns the entry tag `key` ends with, for keys that carry one.
+    fn tag(&self, _key: &[u8]) -> Option<u64> {
+        None
+    }
 }
 
+/// MemTableComparator orders encoded memtable entries by user key and then newest sequence
+/// first, the same order tables keep their internal keys in.
+#[derive(Debug)]
 pub struct MemTableComparator;
 
 impl Comparator for MemTableComparator {
-    fn cmp(&self, _a: &[u8], _b: &[u8]) -> crate::Result<Ordering> {
-        // let a = MemEntry::decode(a)?;
-        // let b = MemEntry::decode(b)?;
-        //
-        // let ord = match a.key.cmp(&b.key) {
-        //     Ordering::Less => Ordering::Less,
-        //     Ordering::Equal => b.seq.cmp(&a.seq),
-        //     Ordering::Greater => Ordering::Greater,
-        // };
-        // Ok(ord)
-        todo!()
+    fn cmp(&self, a: &[u8], b: &[u8]) -> crate::Result<Ordering> {
+        let a = MemEntry::decode_from_slice(a)?;
+        let b = MemEntry::decode_from_slice(b)?;
+        Ok(a.key().cmp(b.key()).then(b.seq().cmp(&a.seq())))
+    }
+
+    fn name(&self) -> &'static str {
+        "beardb.MemTableComparator"
     }
 }
 
+#[derive(Debug)]
 pub struct DefaultComparator;
 
 impl Comparator for DefaultComparator {
     fn cmp(&self, a: &[u8], b: &[u8]) -> crate::Result<Ordering> {
         Ok(a.cmp(b))
     }
+
+    fn name(&self) -> &'static str {
+        "beardb.BytewiseComparator"
+    }
+}
+
+/// InternalKeyComparator orders table keys, a user key followed by the entry tag, by user key
+/// and then newest sequence first.
+#[derive(Debug)]
+pub struct InternalKeyComparator;
+
+impl Comparator for InternalKeyComparator {
+    fn cmp(&self, a: &[u8], b: &[u8]) -> crate::Result<Ordering> {
+        let (a_key, a_tag) = split_internal_key(a)?;
+        let (b_key, b_tag) = split_internal_key(b)?;
+        Ok(a_key.cmp(b_key).then(b_tag.cmp(&a_tag)))
+    }
+
+    fn name(&self) -> &'static str {
+        "beardb.InternalKeyComparator"
+    }
+
+    fn user_key<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        user_key(key)
+    }
+
+    fn tag(&self, key: &[u8]) -> Option<u64> {
+        split_internal_key(key).ok().map(|(_, tag)| tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::cmp::{Comparator, InternalKeyComparator, MemTableComparator};
+    use crate::codec::Codec;
+    use crate::types::{internal_key, MemEntry, ValueType};
+
+    #[test]
+    fn test_memtable_order() {
+        let cmp = MemTableComparator;
+        let entry = |key: &[u8], seq, value: &[u8]| {
+            MemEntry::new(seq, ValueType::Value, key, value)
+                .encode()
+                .unwrap()
+        };
+        let a1 = entry(b"a", 1, b"zzz");
+        let a2 = entry(b"a", 2, b"");
+        let b1 = entry(b"b", 1, b"");
+        assert_eq!(cmp.cmp(&a2, &a1).unwrap(), Ordering::Less);
+        assert_eq!(cmp.cmp(&a1, &b1).unwrap(), Ordering::Less);
+        assert_eq!(cmp.cmp(&a1, &a1).unwrap(), Ordering::Equal);
+        // by key rather than by the encoded key length in front of it
+        let long = entry(b"aaaaaaaa", 1, b"");
+        assert_eq!(cmp.cmp(&long, &b1).unwrap(), Ordering::Less);
+        assert!(cmp.cmp(b"a", &a1).is_err());
+    }
+
+    #[test]
+    fn test_internal_key_order() {
+        let cmp = InternalKeyComparator;
+        let a1 = internal_key(b"a", 1, ValueType::Value);
+        let a2 = internal_key(b"a", 2, ValueType::Deletion);
+        let b1 = internal_key(b"b", 1, ValueType::Value);
+        assert_eq!(cmp.cmp(&a2, &a1).unwrap(), Ordering::Less);
+        assert_eq!(cmp.cmp(&a1, &b1).unwrap(), Ordering::Less);
+        assert_eq!(cmp.cmp(&a1, &a1).unwrap(), Ordering::Equal);
+        // a shorter user key sorts first even when its tag bytes are larger
+        let ab = internal_key(b"ab", 0, ValueType::Deletion);
+        assert_eq!(cmp.cmp(&a2, &ab).unwrap(), Ordering::Less);
+        assert_eq!(cmp.user_key(&a1), b"a");
+        assert!(cmp.cmp(b"a", &a1).is_err());
+    }
 }
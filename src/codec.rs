@@ -1,58 +1,128 @@
-use std::io::Read;
-use std::u64;
+use crate::{ensure, Error};
 
 pub(crate) trait Codec<'de>: Sized {
     fn encode(&self) -> crate::Result<Vec<u8>>;
     fn decode_from_slice(buf: &'de [u8]) -> crate::Result<Self>;
-    fn decode_from_reader<R: Read>(reader: R) -> crate::Result<Self>;
 }
 
-pub(crate) struct Reader<R>
-where
-    R: Read,
-{
-    rdr: R,
+pub(crate) fn decode_fixed32(d: &[u8]) -> u32 {
+    unsafe { (d.as_ptr() as *const u32).read_unaligned() }
 }
 
-impl<R> Reader<R>
-where
-    R: Read,
-{
-    pub(crate) fn new(r: R) -> Self {
-        Self { rdr: r }
-    }
+pub(crate) fn decode_fixed64(d: &[u8]) -> u64 {
+    unsafe { (d.as_ptr() as *const u64).read_unaligned() }
+}
 
-    pub(crate) fn read_u32_le(&mut self) -> crate::Result<u32> {
-        let mut b = [0_u8; 4];
-        self.rdr.read_exact(&mut b)?;
-        return Ok(u32::from_le_bytes(b));
-    }
+/// encode_varint32 appends `v` to `dst` as a varint: seven bits a byte, low bits first, with
+/// the high bit set on every byte but the last.
+pub(crate) fn encode_varint32(dst: &mut Vec<u8>, v: u32) {
+    encode_varint64(dst, v as u64)
+}
 
-    pub(crate) fn read_u64_le(&mut self) -> crate::Result<u64> {
-        let mut b = [0_u8; 8];
-        self.rdr.read_exact(&mut b)?;
-        return Ok(u64::from_le_bytes(b));
+pub(crate) fn encode_varint64(dst: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        dst.push(v as u8 | 0x80);
+        v >>= 7;
     }
+    dst.push(v as u8);
+}
 
-    pub(crate) fn read_exact(&mut self, len: usize) -> crate::Result<Vec<u8>> {
-        let mut b = vec![0; len];
-        self.rdr.read_exact(&mut b)?;
-        return Ok(b);
-    }
+/// varint_length is the number of bytes `v` takes as a varint.
+pub(crate) fn varint_length(v: u64) -> usize {
+    (64 - (v | 1).leading_zeros() as usize).div_ceil(7)
 }
 
-pub(crate) fn decode_fixed32(d: &[u8]) -> u32 {
-    unsafe { (d.as_ptr() as *const u32).read_unaligned() }
+/// decode_varint32 decodes the varint at the start of `d` and returns it along with the number
+/// of bytes it took. Varints that run past the end of `d` or don't fit a u32 are an error.
+pub(crate) fn decode_varint32(d: &[u8]) -> crate::Result<(u32, usize)> {
+    let (v, len) = decode_varint(d, 32)?;
+    Ok((v as u32, len))
 }
 
-pub(crate) fn encode_fixed32<'a>(d: u32) -> [u8; std::mem::size_of::<u32>()] {
-    d.to_le_bytes()
+pub(crate) fn decode_varint64(d: &[u8]) -> crate::Result<(u64, usize)> {
+    decode_varint(d, 64)
 }
 
-pub(crate) fn decode_fixed64(d: &[u8]) -> u64 {
-    unsafe { (d.as_ptr() as *const u64).read_unaligned() }
+fn decode_varint(d: &[u8], bits: u32) -> crate::Result<(u64, usize)> {
+    let mut v = 0_u64;
+    let mut shift = 0;
+    for (i, b) in d.iter().enumerate() {
+        let part = (b & 0x7f) as u64;
+        // the last byte may only hold the bits that are left
+        ensure!(
+            shift < bits && (bits - shift >= 7 || part >> (bits - shift) == 0),
+            Error::CodecError
+        );
+        v |= part << shift;
+        if b & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+        shift += 7;
+    }
+    Err(Error::CodecError)
 }
 
-pub(crate) fn encode_fixed64<'a>(d: u64) -> [u8; std::mem::size_of::<u64>()] {
-    d.to_le_bytes()
+#[cfg(test)]
+mod tests {
+    use crate::codec::{
+        decode_varint32, decode_varint64, encode_varint32, encode_varint64, varint_length,
+    };
+    use crate::Error;
+
+    #[test]
+    fn test_varint() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u32::MAX as u64,
+            u32::MAX as u64 + 1,
+            1 << 56,
+            u64::MAX,
+        ];
+        let mut buf = Vec::new();
+        for v in values {
+            let start = buf.len();
+            encode_varint64(&mut buf, v);
+            assert_eq!(buf.len() - start, varint_length(v), "{}", v);
+        }
+        let mut at = 0;
+        for v in values {
+            let (decoded, len) = decode_varint64(&buf[at..]).unwrap();
+            assert_eq!(decoded, v);
+            at += len;
+        }
+        assert_eq!(at, buf.len());
+
+        let mut buf = Vec::new();
+        encode_varint32(&mut buf, 300);
+        assert_eq!(buf, [0xac, 0x02]);
+        assert_eq!(decode_varint32(&buf).unwrap(), (300, 2));
+        buf.clear();
+        encode_varint32(&mut buf, u32::MAX);
+        assert_eq!(decode_varint32(&buf).unwrap(), (u32::MAX, 5));
+    }
+
+    #[test]
+    fn test_varint_bounds() {
+        // truncated
+        assert_eq!(decode_varint32(&[]), Err(Error::CodecError));
+        assert_eq!(decode_varint32(&[0x80, 0x80]), Err(Error::CodecError));
+        assert_eq!(decode_varint64(&[0xff; 9]), Err(Error::CodecError));
+        // too large for the type
+        let mut buf = Vec::new();
+        encode_varint64(&mut buf, u32::MAX as u64 + 1);
+        assert_eq!(decode_varint32(&buf), Err(Error::CodecError));
+        assert_eq!(decode_varint64(&[0xff; 11]), Err(Error::CodecError));
+        assert_eq!(
+            decode_varint64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]),
+            Err(Error::CodecError)
+        );
+        // trailing bytes are left alone
+        assert_eq!(decode_varint32(&[0x05, 0xff]).unwrap(), (5, 1));
+    }
 }
@@ -0,0 +1,491 @@
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::filename::{create_file, sync_dir, table_file_name, temp_file_name};
+use crate::iter::InternalIterator;
+use crate::merging_iterator::MergingIterator;
+use crate::options::{CompactionStyle, Options};
+use crate::table::TableOptions;
+use crate::table_builder::TableBuilder;
+use crate::types::parse_internal_key;
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::version_set::{Version, NUM_LEVELS};
+
+/// Compaction merges its input tables, which may come from several levels, into new tables
+/// at `output_level`.
+pub(crate) struct Compaction {
+    pub(crate) inputs: Vec<(usize, Arc<FileMetaData>)>,
+    pub(crate) output_level: usize,
+    // tables holding older data than the inputs
+    older: Vec<Arc<FileMetaData>>,
+    version: Arc<Version>,
+}
+
+impl Compaction {
+    /// is_trivial_move reports whether the single input can be moved to the output level as it
+    /// is, because nothing there overlaps it.
+    pub(crate) fn is_trivial_move(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].0 != self.output_level
+    }
+
+    pub(crate) fn add_input_deletions(&self, edit: &mut VersionEdit) {
+        for (level, file) in self.inputs.iter() {
+            edit.delete_file(*level, file.number);
+        }
+    }
+
+    /// is_base_level_for_key reports whether no table older than the inputs may hold
+    /// `user_key`, in which case a tombstone for it has nothing left to shadow.
+    pub(crate) fn is_base_level_for_key(&self, user_key: &[u8]) -> bool {
+        !self.older.iter().any(|f| f.overlaps(user_key, user_key))
+    }
+
+    /// input_iter merges the entries of every input table in internal key order. It's
+    /// unpositioned until it's seeked.
+    pub(crate) fn input_iter(&self) -> crate::Result<MergingIterator> {
+        let mut iters: Vec<Box<dyn InternalIterator>> = Vec::new();
+        for (_, file) in self.inputs.iter() {
+            iters.push(Box::new(self.version.table(file.number)?.iter()?));
+        }
+        Ok(MergingIterator::new(iters))
+    }
+}
+
+/// CompactionPicker decides what the background thread compacts next.
+pub(crate) trait CompactionPicker: Send {
+    /// needs_compaction is a cheap check of whether `version` calls for a compaction at all.
+    fn needs_compaction(&self, version: &Version) -> bool;
+
+    fn pick_compaction(&mut self, version: &Arc<Version>) -> crate::Result<Option<Compaction>>;
+}
+
+pub(crate) fn new_picker(opts: &Options) -> Box<dyn CompactionPicker> {
+    match opts.compaction_style {
+        CompactionStyle::Leveled => Box::new(LeveledPicker {
+            level0_file_num_compaction_trigger: opts.level0_file_num_compaction_trigger.max(1),
+            max_bytes_for_level_base: opts.max_bytes_for_level_base,
+            max_bytes_for_level_multiplier: opts.max_bytes_for_level_multiplier,
+            compact_pointers: vec![Vec::new(); NUM_LEVELS],
+        }),
+        CompactionStyle::Tiered => Box::new(TieredPicker {
+            size_ratio: opts.tiered_size_ratio,
+            max_run_count: opts.tiered_max_run_count.max(1),
+        }),
+    }
+}
+
+/// LeveledPicker compacts the level with the highest score into the next one. Level 0 tables
+/// may overlap each other, so all of them are compacted together. Deeper levels compact one
+/// table at a time, going round their key space from the last key compacted at each level.
+struct LeveledPicker {
+    level0_file_num_compaction_trigger: usize,
+    max_bytes_for_level_base: u64,
+    max_bytes_for_level_multiplier: u64,
+    compact_pointers: Vec<Vec<u8>>,
+}
+
+impl LeveledPicker {
+    fn max_bytes_for_level(&self, level: usize) -> u64 {
+        let mut max_bytes = self.max_bytes_for_level_base;
+        for _ in 1..level {
+            max_bytes = max_bytes.saturating_mul(self.max_bytes_for_level_multiplier);
+        }
+        max_bytes
+    }
+
+    /// compaction_score returns the level most in need of a compaction and how badly: level 0
+    /// by its number of tables, which may all overlap and slow reads down, deeper levels by
+    /// their size. A score of at least 1 calls for a compaction.
+    fn compaction_score(&self, version: &Version) -> (f64, usize) {
+        let mut best = (0.0, 0);
+        // the last level has nowhere to compact into
+        for level in 0..NUM_LEVELS - 1 {
+            let score = if level == 0 {
+                version.files(0).len() as f64 / self.level0_file_num_compaction_trigger as f64
+            } else {
+                version.level_size(level) as f64 / self.max_bytes_for_level(level) as f64
+            };
+            if score > best.0 {
+                best = (score, level);
+            }
+        }
+        best
+    }
+}
+
+impl CompactionPicker for LeveledPicker {
+    fn needs_compaction(&self, version: &Version) -> bool {
+        self.compaction_score(version).0 >= 1.0
+    }
+
+    fn pick_compaction(&mut self, version: &Arc<Version>) -> crate::Result<Option<Compaction>> {
+        let (score, level) = self.compaction_score(version);
+        if score < 1.0 {
+            return Ok(None);
+        }
+        let files = version.files(level);
+        let upper: Vec<_> = if level == 0 {
+            files.to_vec()
+        } else {
+            let pointer = &self.compact_pointers[level];
+            let next = files
+                .iter()
+                .find(|f| f.largest_user_key() > pointer.as_slice())
+                .unwrap_or(&files[0]);
+            vec![next.clone()]
+        };
+        let smallest = upper.iter().map(|f| f.smallest_user_key()).min().unwrap();
+        let largest = upper.iter().map(|f| f.largest_user_key()).max().unwrap();
+        let overlapping = version.overlapping_files(level + 1, smallest, largest);
+        self.compact_pointers[level] = largest.to_vec();
+
+        let inputs = (upper.into_iter().map(|f| (level, f)))
+            .chain(overlapping.into_iter().map(|f| (level + 1, f)))
+            .collect();
+        let older = (level + 2..NUM_LEVELS)
+            .flat_map(|l| version.files(l).iter().cloned())
+            .collect();
+        Ok(Some(Compaction {
+            inputs,
+            output_level: level + 1,
+            older,
+            version: version.clone(),
+        }))
+    }
+}
+
+/// SortedRun is a set of tables with disjoint key ranges: a single level 0 table or a whole
+/// deeper level.
+struct SortedRun {
+    level: usize,
+    files: Vec<Arc<FileMetaData>>,
+    size: u64,
+    keys: u64,
+}
+
+/// TieredPicker treats every level 0 table and every deeper level as a sorted run, newest
+/// first. Once there are more than `max_run_count` runs it merges the newest window of runs
+/// whose sizes and key counts grow by at most `size_ratio` percent from one run to the next,
+/// or failing that just enough of the newest runs to get back to `max_run_count`. A merged
+/// window is written to a single deeper level, so it stays one run however many tables it's
+/// split into, and runs stay ordered by age.
+struct TieredPicker {
+    size_ratio: u64,
+    max_run_count: usize,
+}
+
+impl TieredPicker {
+    /// sorted_runs returns the runs of `version`, newest first. Their key counts come from the
+    /// file metadata, so no table is opened to pick a compaction. Files added before they
+    /// carried their number of entries are estimated from their size, at the average entry
+    /// size of the files that do.
+    fn sorted_runs(version: &Version) -> Vec<SortedRun> {
+        let (mut counted_size, mut counted) = (0, 0);
+        for file in (0..NUM_LEVELS).flat_map(|level| version.files(level)) {
+            if file.entries > 0 {
+                counted_size += file.size;
+                counted += file.entries;
+            }
+        }
+        let entry_size = counted_size.checked_div(counted).unwrap_or(1).max(1);
+        let entries = |file: &FileMetaData| match file.entries {
+            0 => file.size / entry_size,
+            entries => entries,
+        };
+
+        let mut runs = Vec::new();
+        for level in 0..NUM_LEVELS {
+            let files = version.files(level);
+            let groups: Vec<Vec<Arc<FileMetaData>>> = if level == 0 {
+                files.iter().map(|f| vec![f.clone()]).collect()
+            } else if files.is_empty() {
+                Vec::new()
+            } else {
+                vec![files.to_vec()]
+            };
+            for files in groups {
+                runs.push(SortedRun {
+                    level,
+                    size: files.iter().map(|f| f.size).sum(),
+                    keys: files.iter().map(|f| entries(f)).sum(),
+                    files,
+                });
+            }
+        }
+        runs
+    }
+
+    fn run_count(version: &Version) -> usize {
+        version.files(0).len()
+            + (1..NUM_LEVELS)
+                .filter(|level| !version.files(*level).is_empty())
+                .count()
+    }
+
+    /// similar_window returns the end of the window of similar runs starting at `start`.
+    fn similar_window(&self, runs: &[SortedRun], start: usize) -> usize {
+        let grown = |total: u64, next: u64| {
+            next.saturating_mul(100) > total.saturating_mul(100 + self.size_ratio)
+        };
+        let (mut size, mut keys) = (runs[start].size, runs[start].keys);
+        let mut end = start + 1;
+        while end < runs.len() && !grown(size, runs[end].size) && !grown(keys, runs[end].keys) {
+            size += runs[end].size;
+            keys += runs[end].keys;
+            end += 1;
+        }
+        end
+    }
+}
+
+impl CompactionPicker for TieredPicker {
+    fn needs_compaction(&self, version: &Version) -> bool {
+        TieredPicker::run_count(version) > self.max_run_count
+    }
+
+    fn pick_compaction(&mut self, version: &Arc<Version>) -> crate::Result<Option<Compaction>> {
+        if !self.needs_compaction(version) {
+            return Ok(None);
+        }
+        let runs = TieredPicker::sorted_runs(version);
+        let window = (0..runs.len())
+            .map(|start| (start, self.similar_window(&runs, start)))
+            .find(|(start, end)| end - start >= 2)
+            .unwrap_or((0, runs.len() - self.max_run_count + 1));
+
+        let (start, mut end) = window;
+        let output_level = if runs[end - 1].level > 0 {
+            // the window takes in the whole level of its oldest run
+            runs[end - 1].level
+        } else {
+            // level 0 can't hold the output as one run, so it goes to the empty level above
+            // the next older run, taking in the older level 0 tables it'd otherwise overtake
+            end = version.files(0).len();
+            match runs.get(end) {
+                None => NUM_LEVELS - 1,
+                Some(next) if next.level > 1 => next.level - 1,
+                Some(_) => {
+                    end += 1;
+                    1
+                }
+            }
+        };
+        let inputs = runs[start..end]
+            .iter()
+            .flat_map(|run| run.files.iter().map(|f| (run.level, f.clone())))
+            .collect();
+        let older = runs[end..]
+            .iter()
+            .flat_map(|run| run.files.iter().cloned())
+            .collect();
+        Ok(Some(Compaction {
+            inputs,
+            output_level,
+            older,
+            version: version.clone(),
+        }))
+    }
+}
+
+/// TableOutput writes a table for a flush or a compaction. It's built under a temporary name
+/// and only renamed into place once synced, so a crash never leaves a partial table behind.
+pub(crate) struct TableOutput {
+    number: u64,
+    builder: TableBuilder<File>,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+    largest_seq: u64,
+    entries: u64,
+}
+
+impl TableOutput {
+    pub(crate) fn create(dir: &Path, number: u64, opts: Arc<TableOptions>) -> crate::Result<Self> {
+        let file = create_file(&temp_file_name(dir, number))?;
+        Ok(Self {
+            number,
+            builder: TableBuilder::new_with_options(file, opts),
+            smallest: Vec::new(),
+            largest: Vec::new(),
+            largest_seq: 0,
+            entries: 0,
+        })
+    }
+
+    /// add appends an entry; keys must be added in increasing order.
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) -> crate::Result<()> {
+        let (_, seq, _) = parse_internal_key(key)?;
+        self.largest_seq = self.largest_seq.max(seq);
+        if self.smallest.is_empty() {
+            self.smallest = key.to_vec();
+        }
+        self.largest.clear();
+        self.largest.extend_from_slice(key);
+        self.entries += 1;
+        self.builder.add(key, value)
+    }
+
+    pub(crate) fn estimated_size(&self) -> u64 {
+        self.builder.estimated_size() as u64
+    }
+
+    pub(crate) fn finish(self, dir: &Path) -> crate::Result<FileMetaData> {
+        let file = self.builder.finish()?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+        fs::rename(
+            temp_file_name(dir, self.number),
+            table_file_name(dir, self.number),
+        )?;
+        sync_dir(dir)?;
+        Ok(FileMetaData {
+            number: self.number,
+            size,
+            smallest: self.smallest,
+            largest: self.largest,
+            largest_seq: self.largest_seq,
+            entries: self.entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use memmap2::Mmap;
+    use tempfile::tempdir;
+
+    use crate::cmp::InternalKeyComparator;
+    use crate::compaction::{new_picker, TableOutput};
+    use crate::disk_env::PosixDiskEnv;
+    use crate::iter::InternalIterator;
+    use crate::merging_iterator::MergingIterator;
+    use crate::options::{CompactionStyle, Options};
+    use crate::table::{Table, TableOptions};
+    use crate::table_cache::TableCache;
+    use crate::types::{internal_key, parse_internal_key, ValueType};
+    use crate::version_edit::VersionEdit;
+    use crate::version_set::VersionSet;
+
+    #[test]
+    fn test_merge_outputs() {
+        let dir = tempdir().unwrap();
+        let opts = Arc::new(TableOptions {
+            block_size: 64,
+            comparator: Arc::new(Box::new(InternalKeyComparator)),
+            ..Default::default()
+        });
+        let contents: [&[(&str, u64)]; 3] = [
+            &[("a", 1), ("c", 5), ("e", 2)],
+            &[("b", 3), ("c", 7), ("f", 4)],
+            &[("c", 6), ("d", 8)],
+        ];
+        let mut iters = Vec::new();
+        for (number, entries) in contents.iter().enumerate() {
+            let mut output = TableOutput::create(dir.path(), number as u64, opts.clone()).unwrap();
+            for (key, seq) in entries.iter() {
+                let ikey = internal_key(key.as_bytes(), *seq, ValueType::Value);
+                output.add(&ikey, key.as_bytes()).unwrap();
+            }
+            let file = output.finish(dir.path()).unwrap();
+            assert_eq!(file.smallest_user_key(), entries[0].0.as_bytes());
+            let data = unsafe {
+                Mmap::map(
+                    &std::fs::File::open(dir.path().join(format!("{:06}.sst", number))).unwrap(),
+                )
+                .unwrap()
+            };
+            let table = Table::open(number as u64, data, opts.clone()).unwrap();
+            iters.push(Box::new(table.iter().unwrap()) as Box<dyn InternalIterator>);
+        }
+
+        let mut merged = MergingIterator::new(iters);
+        merged.seek_to_first();
+        let mut found = Vec::new();
+        while merged.valid() {
+            let (key, seq, _) = parse_internal_key(merged.key()).unwrap();
+            found.push((String::from_utf8(key.to_vec()).unwrap(), seq));
+            merged.next();
+        }
+        assert!(merged.status().is_ok());
+        let expected: Vec<_> = [
+            ("a", 1),
+            ("b", 3),
+            ("c", 7),
+            ("c", 6),
+            ("c", 5),
+            ("d", 8),
+            ("e", 2),
+            ("f", 4),
+        ]
+        .iter()
+        .map(|(k, s)| (k.to_string(), *s))
+        .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_tiered_picker() {
+        let dir = tempdir().unwrap();
+        let table_opts = Arc::new(TableOptions {
+            comparator: Arc::new(Box::new(InternalKeyComparator)),
+            ..Default::default()
+        });
+        let env = Arc::new(PosixDiskEnv::new());
+        let table_cache = Arc::new(TableCache::new(
+            dir.path().to_path_buf(),
+            env,
+            table_opts.clone(),
+            100,
+        ));
+        let mut versions = VersionSet::recover(dir.path(), table_cache.clone()).unwrap();
+        // runs of 300 keys at level 6, 100 at level 5, then 20 and 10 at level 0, the smallest
+        // ones newest
+        let mut seq = 0;
+        let mut numbers = Vec::new();
+        for (keys, level) in [(300, 6), (100, 5), (20, 0), (10, 0)] {
+            let number = versions.new_file_number();
+            let mut output = TableOutput::create(dir.path(), number, table_opts.clone()).unwrap();
+            for i in 0..keys {
+                seq += 1;
+                let ikey = internal_key(format!("key{:04}", i).as_bytes(), seq, ValueType::Value);
+                output.add(&ikey, b"value").unwrap();
+            }
+            let mut edit = VersionEdit::default();
+            edit.add_file(level, output.finish(dir.path()).unwrap());
+            versions.set_last_sequence(seq);
+            versions.log_and_apply(edit).unwrap();
+            numbers.insert(0, number);
+        }
+        let version = versions.current();
+        let found: Vec<_> = version.files(0).iter().map(|f| f.number).collect();
+        assert_eq!(found, numbers[..2]);
+        assert_eq!(version.files(6)[0].entries, 300);
+        for number in numbers.iter() {
+            table_cache.evict(*number);
+        }
+
+        let picked = |size_ratio: u64, max_run_count: usize| {
+            let opts = Options {
+                compaction_style: CompactionStyle::Tiered,
+                tiered_size_ratio: size_ratio,
+                tiered_max_run_count: max_run_count,
+                ..Default::default()
+            };
+            let mut picker = new_picker(&opts);
+            let c = picker.pick_compaction(&version).unwrap()?;
+            let inputs = c.inputs.iter().map(|(_, f)| f.number).collect::<Vec<_>>();
+            Some((inputs, c.output_level))
+        };
+        assert_eq!(picked(1, 4), None);
+        // no runs are similar, so the newest ones are merged down to the limit
+        assert_eq!(picked(1, 2), Some((numbers[..3].to_vec(), 5)));
+        // the level 0 runs are merged above the next older run
+        assert_eq!(picked(100, 3), Some((numbers[..2].to_vec(), 4)));
+        assert_eq!(picked(1000, 3), Some((numbers.clone(), 6)));
+        // picking reads the file metadata, not the tables
+        assert_eq!(table_cache.open_count(), 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::Read;
+
+use crate::table::CompressionType;
+use crate::{ensure, Error};
+
+// no snappy element writes more than 64 bytes for the 3 it takes, well under 32 per byte
+const SNAPPY_MAX_EXPANSION: usize = 32;
+// every extra byte of an lz4 match length adds at most 255 bytes to the match
+const LZ4_MAX_EXPANSION: usize = 255;
+// a zstd block decodes to at most 128KB and takes at least 4 bytes with its header
+const ZSTD_MAX_EXPANSION: usize = 1 << 15;
+
+/// compress appends `src` compressed with `typ` to `dst`. Snappy blocks are in the snappy raw
+/// format, lz4 blocks are an lz4 block preceded by the uncompressed length as a u32, and zstd
+/// blocks are a zstd frame.
+pub(crate) fn compress(typ: CompressionType, src: &[u8], dst: &mut Vec<u8>) -> crate::Result<()> {
+    match typ {
+        CompressionType::None => dst.extend_from_slice(src),
+        CompressionType::Snappy => {
+            let compressed = snap::raw::Encoder::new()
+                .compress_vec(src)
+                .map_err(|e| Error::InvalidArgument(format!("snappy: {}", e)))?;
+            dst.extend_from_slice(&compressed);
+        }
+        CompressionType::Lz4 => dst.extend_from_slice(&lz4_flex::compress_prepend_size(src)),
+        CompressionType::Zstd => {
+            let compressed = zstd::bulk::compress(src, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            dst.extend_from_slice(&compressed);
+        }
+    }
+    Ok(())
+}
+
+/// decompress returns the data `src` was compressed from with `typ`. The length a block claims
+/// is only trusted as far as `src` could expand to, so a corrupt block can't make it allocate
+/// more than a bounded multiple of its own size.
+pub(crate) fn decompress(typ: CompressionType, src: &[u8]) -> crate::Result<Vec<u8>> {
+    match typ {
+        CompressionType::None => Ok(src.to_vec()),
+        CompressionType::Snappy => snappy_decompress(src),
+        CompressionType::Lz4 => lz4_decompress(src),
+        CompressionType::Zstd => zstd_decompress(src, src.len().saturating_mul(ZSTD_MAX_EXPANSION)),
+    }
+}
+
+fn snappy_decompress(src: &[u8]) -> crate::Result<Vec<u8>> {
+    let corrupt = |e: snap::Error| Error::Corruption(format!("bad snappy block: {}", e));
+    let len = snap::raw::decompress_len(src).map_err(corrupt)?;
+    ensure!(
+        len <= src.len().saturating_mul(SNAPPY_MAX_EXPANSION),
+        Error::Corruption(format!("snappy block claims {} bytes", len))
+    );
+    let mut dst = vec![0; len];
+    let n = snap::raw::Decoder::new()
+        .decompress(src, &mut dst)
+        .map_err(corrupt)?;
+    ensure!(
+        n == len,
+        Error::Corruption("bad snappy block: length mismatch".to_string())
+    );
+    Ok(dst)
+}
+
+fn lz4_decompress(src: &[u8]) -> crate::Result<Vec<u8>> {
+    let corrupt =
+        |e: lz4_flex::block::DecompressError| Error::Corruption(format!("bad lz4 block: {}", e));
+    let (len, block) = lz4_flex::block::uncompressed_size(src).map_err(corrupt)?;
+    ensure!(
+        len <= block.len().saturating_mul(LZ4_MAX_EXPANSION),
+        Error::Corruption(format!("lz4 block claims {} bytes", len))
+    );
+    let mut dst = vec![0; len];
+    let n = lz4_flex::decompress_into(block, &mut dst).map_err(corrupt)?;
+    ensure!(
+        n == len,
+        Error::Corruption("bad lz4 block: length mismatch".to_string())
+    );
+    Ok(dst)
+}
+
+fn zstd_decompress(src: &[u8], limit: usize) -> crate::Result<Vec<u8>> {
+    let corrupt = |e: std::io::Error| Error::Corruption(format!("bad zstd block: {}", e));
+    // zstd frames may leave their length out, so the output grows as it's decoded, up to one
+    // byte past the limit to tell a block that reaches it from one that goes over
+    let mut dst = Vec::with_capacity(src.len());
+    zstd::stream::read::Decoder::with_buffer(src)
+        .map_err(corrupt)?
+        .take(limit as u64 + 1)
+        .read_to_end(&mut dst)
+        .map_err(corrupt)?;
+    ensure!(
+        dst.len() <= limit,
+        Error::Corruption(format!("zstd block expands past {} bytes", limit))
+    );
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::{compress, decompress, zstd_decompress};
+    use crate::table::CompressionType;
+    use crate::Error;
+
+    const TYPES: [CompressionType; 3] = [
+        CompressionType::Snappy,
+        CompressionType::Lz4,
+        CompressionType::Zstd,
+    ];
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let mut text = Vec::new();
+        for i in 0..500_u32 {
+            text.extend_from_slice(format!("key{:05} value{} ", i, i % 7).as_bytes());
+        }
+        let noise: Vec<u8> = (0..5000_u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        vec![
+            vec![],
+            b"a".to_vec(),
+            b"abcdabcdabcdabcdabcd".to_vec(),
+            vec![7; 100_000],
+            text,
+            noise,
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for typ in TYPES {
+            for input in inputs() {
+                let mut compressed = Vec::new();
+                compress(typ, &input, &mut compressed).unwrap();
+                assert_eq!(decompress(typ, &compressed).unwrap(), input);
+            }
+            let mut compressed = Vec::new();
+            compress(typ, &inputs()[4], &mut compressed).unwrap();
+            assert!(compressed.len() < inputs()[4].len() / 2, "{:?}", typ);
+        }
+    }
+
+    #[test]
+    fn test_known_encodings() {
+        // a literal, then a copy overlapping the bytes it writes
+        assert_eq!(
+            decompress(
+                CompressionType::Snappy,
+                &[12, 1 << 2, b'a', b'b', 0b01 | (6 << 2), 2]
+            )
+            .unwrap(),
+            b"abababababab"
+        );
+        assert_eq!(
+            decompress(
+                CompressionType::Snappy,
+                &[5, 4 << 2, b'h', b'e', b'l', b'l', b'o']
+            )
+            .unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            decompress(
+                CompressionType::Lz4,
+                &[8, 0, 0, 0, 0x22, b'a', b'b', 2, 0, 0]
+            )
+            .unwrap(),
+            b"abababab"
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        for typ in TYPES {
+            let input = inputs().swap_remove(4);
+            let mut compressed = Vec::new();
+            compress(typ, &input, &mut compressed).unwrap();
+            for cut in [1, 10, compressed.len() / 2] {
+                assert!(matches!(
+                    decompress(typ, &compressed[..compressed.len() - cut]),
+                    Err(Error::Corruption(_))
+                ));
+            }
+        }
+        // lengths the block couldn't expand to aren't allocated for
+        let huge = [0xff, 0xff, 0xff, 0xff, 0x0f, 0];
+        assert!(matches!(
+            decompress(CompressionType::Snappy, &huge),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(
+            decompress(CompressionType::Lz4, &[0xff, 0xff, 0xff, 0xff, 0]),
+            Err(Error::Corruption(_))
+        ));
+    }
+
+    // zstd_frame returns a frame of `blocks` run-length blocks of `block_size` bytes each.
+    fn zstd_frame(blocks: u32, block_size: u32) -> Vec<u8> {
+        // magic number, no optional fields, 8MB window
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x68];
+        for i in 0..blocks {
+            let last = (i + 1 == blocks) as u32;
+            let header = last | (1 << 1) | (block_size << 3);
+            frame.extend_from_slice(&header.to_le_bytes()[..3]);
+            frame.push(7);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_zstd_expansion() {
+        // the largest a frame can expand to stays within the limit
+        let frame = zstd_frame(8, 128 << 10);
+        assert_eq!(
+            decompress(CompressionType::Zstd, &frame).unwrap(),
+            vec![7; 1 << 20]
+        );
+        // blocks past the maximum block size are corrupt
+        assert!(matches!(
+            decompress(CompressionType::Zstd, &zstd_frame(1, (1 << 21) - 1)),
+            Err(Error::Corruption(_))
+        ));
+        // decoding stops once the output passes the limit
+        assert_eq!(zstd_decompress(&frame, 1 << 20).unwrap().len(), 1 << 20);
+        assert!(matches!(
+            zstd_decompress(&frame, (1 << 20) - 1),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
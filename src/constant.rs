@@ -1,3 +1,4 @@
 pub const BLOCK_META_SIZE: usize = 4;
 pub const BLOCK_ENTRY_HEADER_SIZE: usize = 8;
 pub const CHECKSUM_SIZE: usize = 4;
+pub const COMPRESSION_TYPE_SIZE: usize = 1;
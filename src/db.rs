@@ -0,0 +1,2099 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::thread::JoinHandle;
+
+use fs2::FileExt;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+use crate::block_cache::{BlockCache, BlockCacheStats};
+use crate::cmp::InternalKeyComparator;
+use crate::codec::Codec;
+use crate::compaction::{new_picker, Compaction, CompactionPicker, TableOutput};
+use crate::db_iter::DBIterator;
+use crate::disk_env::PosixDiskEnv;
+use crate::filename::{
+    create_file, current_file_name, lock_file_name, log_file_name, manifest_file_name,
+    parse_file_name, table_file_name, temp_file_name, FileType,
+};
+use crate::footer::FOOTER_FORMAT_VERSION;
+use crate::iter::{InternalIterator, IterBounds};
+use crate::log::{LogReader, LogWriter};
+use crate::memtable::MemTable;
+use crate::merging_iterator::MergingIterator;
+use crate::options::{Options, ReadOptions, WalRecoveryMode, WalSyncMode, WriteOptions};
+use crate::table::{FilterMode, TableOptions, LATEST_FORMAT_VERSION};
+use crate::table_cache::TableCache;
+use crate::table_properties::TableProperties;
+use crate::types::{internal_key, parse_internal_key, MemEntry, ValueType, MAX_SEQUENCE};
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::version_set::{Version, VersionSet, NUM_LEVELS};
+use crate::write_batch::WriteBatch;
+use crate::{ensure, Error};
+
+struct DBState {
+    mem: Arc<MemTable>,
+    // frozen memtable being flushed to a table, still visible to reads until the table is
+    // installed
+    imm: Option<Arc<MemTable>>,
+    // taken by the leader of a write group while it appends to the log unlocked
+    log: Option<LogWriter<BufWriter<File>>>,
+    log_number: u64,
+    // a handle on the current log for the sync thread, and how much was appended to it since it
+    // was last synced
+    log_file: Arc<File>,
+    unsynced_bytes: u64,
+    // obsolete logs kept to be written over by later logs; only the logs from
+    // `first_recyclable_log` on were written in the recyclable format and may be reused
+    recycle_logs: VecDeque<u64>,
+    first_recyclable_log: u64,
+    // writes waiting their turn; the one in front leads the next group
+    writers: VecDeque<PendingWrite>,
+    next_writer_id: u64,
+    // outcome of the writes a leader made on behalf of the rest of its group
+    write_results: HashMap<u64, Result<(), String>>,
+    versions: VersionSet,
+    picker: Box<dyn CompactionPicker>,
+    // tables being written by the background thread that no version refers to yet
+    pending_outputs: HashSet<u64>,
+    // sequences of the live snapshots and how many snapshots share each
+    snapshots: BTreeMap<u64, usize>,
+    bg_error: Option<String>,
+    shutting_down: bool,
+}
+
+struct InnerDB {
+    path: PathBuf,
+    opts: Options,
+    table_opts: Arc<TableOptions>,
+    table_cache: Arc<TableCache>,
+    lock: File,
+    state: Mutex<DBState>,
+    // signalled when a memtable is frozen, a flush or compaction finishes or the database shuts
+    // down
+    bg_cv: Condvar,
+    // signalled when a write group is done
+    writers_cv: Condvar,
+    // signalled when the log is due a sync or the database shuts down
+    sync_cv: Condvar,
+}
+
+/// PendingWrite is a write in the writer queue. A write without a batch asks for the memtable
+/// to be frozen, which goes through the queue so it never races a log append.
+struct PendingWrite {
+    id: u64,
+    batch: Option<WriteBatch>,
+    sync: bool,
+}
+
+// upper bound for the batches a leader merges into one log record
+const MAX_GROUP_SIZE: usize = 1 << 20;
+
+impl Drop for InnerDB {
+    fn drop(&mut self) {
+        if let Some(log) = self.state.lock().log.as_mut() {
+            let _ = log.flush();
+        }
+        let _ = self.lock.unlock();
+    }
+}
+
+/// DB is a handle to an open database directory. Writes are appended to the write-ahead log
+/// before they're applied to the memtable, and reads consult the memtable before the tables
+/// on disk, newest first. A memtable that outgrows `Options::write_buffer_size` is frozen and
+/// flushed to a level 0 table by a background thread, which also compacts the tables into
+/// deeper levels as they fill up.
+pub struct DB {
+    inner: Arc<InnerDB>,
+    bg_thread: Option<JoinHandle<()>>,
+    sync_thread: Option<JoinHandle<()>>,
+}
+
+impl DB {
+    pub fn open<P: AsRef<Path>>(path: P, opts: Options) -> crate::Result<DB> {
+        let path = path.as_ref().to_path_buf();
+        let exists = current_file_name(&path).exists();
+        ensure!(
+            exists || opts.create_if_missing,
+            Error::InvalidArgument(format!(
+                "{} does not exist (create_if_missing is false)",
+                path.display()
+            ))
+        );
+        ensure!(
+            !exists || !opts.error_if_exists,
+            Error::InvalidArgument(format!(
+                "{} exists (error_if_exists is true)",
+                path.display()
+            ))
+        );
+        ensure!(
+            opts.table_options.format_version <= LATEST_FORMAT_VERSION,
+            Error::InvalidArgument(format!(
+                "unknown table format version {}",
+                opts.table_options.format_version
+            ))
+        );
+        ensure!(
+            opts.table_options.filter_mode == FilterMode::PerBlock
+                || opts.table_options.format_version >= FOOTER_FORMAT_VERSION,
+            Error::InvalidArgument(format!(
+                "{:?} filters need table format version {}",
+                opts.table_options.filter_mode, FOOTER_FORMAT_VERSION
+            ))
+        );
+        ensure!(
+            opts.max_open_files > 0,
+            Error::InvalidArgument("max_open_files must be positive".to_string())
+        );
+        fs::create_dir_all(&path)?;
+        let lock = lock_dir(&path)?;
+
+        let block_cache = match opts.table_options.block_cache_size {
+            0 => None,
+            size => Some(Arc::new(BlockCache::new(
+                size,
+                opts.table_options.block_size,
+            ))),
+        };
+        let table_opts = Arc::new(TableOptions {
+            comparator: Arc::new(Box::new(InternalKeyComparator)),
+            block_cache,
+            ..opts.table_options.clone()
+        });
+        let table_cache = Arc::new(TableCache::new(
+            path.clone(),
+            Arc::new(PosixDiskEnv::new()),
+            table_opts.clone(),
+            opts.max_open_files,
+        ));
+        let mut versions = VersionSet::recover(&path, table_cache.clone())?;
+
+        // Logs from before the last flush are obsolete, the others hold the memtable.
+        let mut logs = Vec::new();
+        for (number, typ) in list_files(&path)? {
+            versions.mark_file_number_used(number);
+            if typ == FileType::Log && number >= versions.log_number() {
+                logs.push(number);
+            }
+        }
+        let mem = MemTable::new();
+        let mut recycled = false;
+        for number in logs.iter() {
+            let (log_sequence, log_recycled) =
+                recover_log(&path, *number, &mem, opts.wal_recovery_mode)?;
+            if log_sequence > versions.last_sequence() {
+                versions.set_last_sequence(log_sequence);
+            }
+            recycled = log_recycled;
+        }
+
+        // Keep appending to the newest log; its torn tail, if any, was truncated above. A log
+        // in the recyclable format may go on past its last record with an old log's records,
+        // which would hide anything appended after them, so it's never appended to; with
+        // recycling every open starts a new log.
+        let (log, log_number) = match logs.last() {
+            Some(number) if opts.recycle_log_file_num == 0 && !recycled => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(log_file_name(&path, *number))?;
+                let offset = file.metadata()?.len() as usize;
+                (
+                    LogWriter::new_with_off(BufWriter::new(file), offset),
+                    *number,
+                )
+            }
+            _ => {
+                let number = versions.new_file_number();
+                (create_log(&path, number, None, &opts)?, number)
+            }
+        };
+
+        let log_file = Arc::new(log.get_ref().get_ref().try_clone()?);
+
+        // start a new manifest holding the recovered version
+        versions.log_and_apply(VersionEdit::default())?;
+
+        let state = DBState {
+            mem: Arc::new(mem),
+            imm: None,
+            log: Some(log),
+            log_number,
+            log_file,
+            unsynced_bytes: 0,
+            recycle_logs: VecDeque::new(),
+            first_recyclable_log: log_number,
+            writers: VecDeque::new(),
+            next_writer_id: 0,
+            write_results: HashMap::new(),
+            versions,
+            picker: new_picker(&opts),
+            pending_outputs: HashSet::new(),
+            snapshots: BTreeMap::new(),
+            bg_error: None,
+            shutting_down: false,
+        };
+        let inner = Arc::new(InnerDB {
+            path,
+            opts,
+            table_opts,
+            table_cache,
+            lock,
+            state: Mutex::new(state),
+            bg_cv: Condvar::new(),
+            writers_cv: Condvar::new(),
+            sync_cv: Condvar::new(),
+        });
+        inner.delete_obsolete_files(&mut inner.state.lock());
+        let bg_thread = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("beardb-bg".to_string())
+                .spawn(move || inner.background_loop())?
+        };
+        let sync_thread = match inner.opts.wal_sync_mode {
+            WalSyncMode::NoSync => None,
+            _ => {
+                let inner = inner.clone();
+                let handle = thread::Builder::new()
+                    .name("beardb-sync".to_string())
+                    .spawn(move || inner.sync_loop())?;
+                Some(handle)
+            }
+        };
+        Ok(DB {
+            inner,
+            bg_thread: Some(bg_thread),
+            sync_thread,
+        })
+    }
+
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> crate::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(batch)
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&self, key: K) -> crate::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch)
+    }
+
+    /// write applies every entry of `batch` atomically. The batch is logged within a single
+    /// record, so recovery after a crash restores all of its entries or none of them.
+    /// Concurrent writes are grouped into one record.
+    pub fn write(&self, batch: WriteBatch) -> crate::Result<()> {
+        self.write_with_options(&WriteOptions::default(), batch)
+    }
+
+    pub fn write_with_options(&self, opts: &WriteOptions, batch: WriteBatch) -> crate::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        for entry in batch.iter() {
+            let (_, key, _) = entry?;
+            ensure!(
+                !key.is_empty(),
+                Error::InvalidArgument("empty key".to_string())
+            );
+        }
+
+        self.inner.write(Some(batch), opts.sync)
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> crate::Result<Option<Vec<u8>>> {
+        self.get_with_options(&ReadOptions::default(), key)
+    }
+
+    /// get_with_options reads `key` as of `opts.snapshot`, or as of now without one.
+    pub fn get_with_options<K: AsRef<[u8]>>(
+        &self,
+        opts: &ReadOptions,
+        key: K,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        let key = key.as_ref();
+        let (mem, imm, version, sequence) = self.read_state(opts);
+        for mem in std::iter::once(mem).chain(imm) {
+            if let Some((vtype, value)) = mem.get(key, sequence)? {
+                return Ok(found(vtype, value));
+            }
+        }
+        Ok(version
+            .get(key, sequence)?
+            .and_then(|(vtype, value)| found(vtype, &value)))
+    }
+
+    /// iter returns an iterator over the keys of the database as of `opts.snapshot`, or as of
+    /// now without one, within the bounds of `opts`. It's unpositioned until one of its seek
+    /// methods is called.
+    pub fn iter(&self, opts: &ReadOptions) -> crate::Result<DBIterator> {
+        let (mem, imm, version, sequence) = self.read_state(opts);
+        let bounds = Arc::new(IterBounds::for_user_keys(
+            opts.lower_bound,
+            opts.upper_bound,
+        ));
+        let mut iters: Vec<Box<dyn InternalIterator>> =
+            vec![Box::new(mem.internal_iter(bounds.clone()))];
+        if let Some(imm) = imm {
+            iters.push(Box::new(imm.internal_iter(bounds.clone())));
+        }
+        version.add_iterators(&mut iters, &bounds)?;
+        Ok(DBIterator::new(
+            MergingIterator::new(iters),
+            sequence,
+            version,
+            opts.prefix_same_as_start,
+        ))
+    }
+
+    /// read_state returns what a read with `opts` sees: the memtables, the current version and
+    /// the sequence to read as of.
+    fn read_state(
+        &self,
+        opts: &ReadOptions,
+    ) -> (Arc<MemTable>, Option<Arc<MemTable>>, Arc<Version>, u64) {
+        let state = self.inner.state.lock();
+        let sequence = match opts.snapshot {
+            Some(snapshot) => snapshot.sequence,
+            None => state.versions.last_sequence(),
+        };
+        (
+            state.mem.clone(),
+            state.imm.clone(),
+            state.versions.current(),
+            sequence,
+        )
+    }
+
+    /// snapshot pins the current state of the database for reads through
+    /// `ReadOptions::snapshot`, until the snapshot is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut state = self.inner.state.lock();
+        let sequence = state.versions.last_sequence();
+        *state.snapshots.entry(sequence).or_default() += 1;
+        Snapshot {
+            sequence,
+            db: Arc::downgrade(&self.inner),
+        }
+    }
+
+    /// block_cache_stats returns how many table block reads the block cache served and how many
+    /// it missed. Both stay 0 when `TableOptions::block_cache_size` is 0.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.inner
+            .table_opts
+            .block_cache
+            .as_ref()
+            .map_or_else(BlockCacheStats::default, |cache| cache.stats())
+    }
+
+    /// table_properties returns the properties of every live table by file number. Their
+    /// smallest and largest keys are internal keys, ending in the sequence and type of the entry.
+    pub fn table_properties(&self) -> crate::Result<BTreeMap<u64, TableProperties>> {
+        let version = self.inner.state.lock().versions.current();
+        let mut props = BTreeMap::new();
+        for level in 0..NUM_LEVELS {
+            for file in version.files(level) {
+                if let Some(table_props) = version.table(file.number)?.properties() {
+                    props.insert(file.number, table_props.clone());
+                }
+            }
+        }
+        Ok(props)
+    }
+
+    /// flush writes the memtable to a table and waits for it, after which the write-ahead log
+    /// is no longer needed to recover its entries.
+    pub fn flush(&self) -> crate::Result<()> {
+        self.inner.write(None, false)?;
+        let mut state = self.inner.state.lock();
+        while state.imm.is_some() && state.bg_error.is_none() {
+            self.inner.bg_cv.wait(&mut state);
+        }
+        match &state.bg_error {
+            Some(err) => Err(Error::BackgroundError(err.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// close waits for a pending flush and the running compaction, flushes the write-ahead log,
+    /// syncing it unless the sync mode is `WalSyncMode::NoSync`, and releases the lock on the
+    /// database directory.
+    pub fn close(mut self) -> crate::Result<()> {
+        self.stop_background();
+        match self.inner.state.lock().log.as_mut() {
+            Some(log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn stop_background(&mut self) {
+        self.inner.state.lock().shutting_down = true;
+        self.inner.bg_cv.notify_all();
+        self.inner.sync_cv.notify_all();
+        for handle in [self.bg_thread.take(), self.sync_thread.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        self.stop_background();
+    }
+}
+
+/// Snapshot is a consistent view of the database as of the writes before it was taken. Reads
+/// through it ignore later writes, and compactions keep the versions it sees.
+pub struct Snapshot {
+    sequence: u64,
+    db: Weak<InnerDB>,
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.upgrade() {
+            let mut state = db.state.lock();
+            if let Some(count) = state.snapshots.get_mut(&self.sequence) {
+                *count -= 1;
+                if *count == 0 {
+                    state.snapshots.remove(&self.sequence);
+                }
+            }
+        }
+    }
+}
+
+fn found(vtype: ValueType, value: &[u8]) -> Option<Vec<u8>> {
+    match vtype {
+        ValueType::Deletion => None,
+        ValueType::Value => Some(value.to_vec()),
+    }
+}
+
+impl InnerDB {
+    /// write queues `batch` behind the writes in progress. The writer in front of the queue
+    /// leads: it merges the batches queued behind its own into one log record, appends it with
+    /// the lock released, and hands the result to the writers of its group. A `None` batch
+    /// freezes the memtable instead.
+    fn write(&self, batch: Option<WriteBatch>, sync: bool) -> crate::Result<()> {
+        let mut state = self.state.lock();
+        let id = state.next_writer_id;
+        state.next_writer_id += 1;
+        state.writers.push_back(PendingWrite { id, batch, sync });
+        loop {
+            if let Some(result) = state.write_results.remove(&id) {
+                return result.map_err(Error::BackgroundError);
+            }
+            if state.writers.front().map(|w| w.id) == Some(id) {
+                break;
+            }
+            self.writers_cv.wait(&mut state);
+        }
+
+        let group_len = build_group(&state.writers);
+        let (result, group_len) = match state.writers[0].batch.take() {
+            Some(batch) => {
+                let mut group = batch;
+                for writer in state.writers.range_mut(1..group_len) {
+                    group.append(&writer.batch.take().unwrap());
+                }
+                let result = self
+                    .make_room_for_write(&mut state, false)
+                    .and_then(|()| self.write_group(&mut state, group, sync));
+                (result, group_len)
+            }
+            None => (self.make_room_for_write(&mut state, true), 1),
+        };
+
+        let message = result.as_ref().map(|_| ()).map_err(|err| err.to_string());
+        let state = &mut *state;
+        for writer in state.writers.drain(..group_len).skip(1) {
+            state.write_results.insert(writer.id, message.clone());
+        }
+        self.writers_cv.notify_all();
+        result
+    }
+
+    /// write_group logs `group`, syncing the log if asked to, and applies it to the memtable.
+    /// Its entries only become visible to reads once the last sequence is raised past them.
+    fn write_group(
+        &self,
+        state: &mut MutexGuard<DBState>,
+        mut group: WriteBatch,
+        sync: bool,
+    ) -> crate::Result<()> {
+        let sequence = state.versions.last_sequence() + 1;
+        group.set_sequence(sequence);
+        let mut log = state
+            .log
+            .take()
+            .expect("only the write leader takes the log");
+        let mem = state.mem.clone();
+        let result: crate::Result<usize> = MutexGuard::unlocked(state, || {
+            let written = log.add_record(&group.encode()?)?;
+            log.flush()?;
+            if sync {
+                log.get_ref().get_ref().sync_data()?;
+            }
+            group.insert_into(&mem)?;
+            Ok(written)
+        });
+        state.log = Some(log);
+        if let Err(err) = &result {
+            // the log may end in a partial record now, so later writes must not follow it
+            state.bg_error = Some(err.to_string());
+            self.bg_cv.notify_all();
+        }
+        let written = result?;
+        state
+            .versions
+            .set_last_sequence(sequence + group.count() as u64 - 1);
+        if sync {
+            state.unsynced_bytes = 0;
+        } else {
+            state.unsynced_bytes += written as u64;
+            if let WalSyncMode::Bytes(bytes) = self.opts.wal_sync_mode {
+                if state.unsynced_bytes >= bytes {
+                    self.sync_cv.notify_all();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// sync_loop syncs the log in the background as the sync mode asks, and once more when the
+    /// database shuts down.
+    fn sync_loop(&self) {
+        let mut state = self.state.lock();
+        loop {
+            let shutting_down = state.shutting_down;
+            if !shutting_down {
+                match self.opts.wal_sync_mode {
+                    WalSyncMode::Interval(interval) => {
+                        self.sync_cv.wait_for(&mut state, interval);
+                    }
+                    WalSyncMode::Bytes(bytes) if state.unsynced_bytes < bytes => {
+                        self.sync_cv.wait(&mut state);
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            if state.unsynced_bytes > 0 && state.bg_error.is_none() {
+                let file = state.log_file.clone();
+                state.unsynced_bytes = 0;
+                if let Err(err) = MutexGuard::unlocked(&mut state, || file.sync_data()) {
+                    state.bg_error = Some(Error::from(err).to_string());
+                    self.bg_cv.notify_all();
+                }
+            }
+            if shutting_down {
+                return;
+            }
+        }
+    }
+
+    /// make_room_for_write freezes the memtable once it's full, or whenever `force` is set, and
+    /// starts a new log for its successor. Writers stall while the previous frozen memtable is
+    /// still being flushed.
+    fn make_room_for_write(
+        &self,
+        state: &mut MutexGuard<DBState>,
+        force: bool,
+    ) -> crate::Result<()> {
+        loop {
+            if let Some(err) = &state.bg_error {
+                return Err(Error::BackgroundError(err.clone()));
+            }
+            if state.mem.is_empty()
+                || (!force && state.mem.approximate_memory_usage() < self.opts.write_buffer_size)
+            {
+                return Ok(());
+            }
+            if state.imm.is_some() {
+                self.bg_cv.wait(state);
+                continue;
+            }
+            let number = state.versions.new_file_number();
+            let reuse = state.recycle_logs.pop_front();
+            let log = create_log(&self.path, number, reuse, &self.opts)?;
+            if let Some(log) = state.log.as_mut() {
+                log.flush()?;
+                // the sync thread only follows the current log
+                if self.opts.wal_sync_mode != WalSyncMode::NoSync {
+                    log.get_ref().get_ref().sync_data()?;
+                }
+            }
+            state.log_file = Arc::new(log.get_ref().get_ref().try_clone()?);
+            state.unsynced_bytes = 0;
+            state.log = Some(log);
+            state.log_number = number;
+            let mem = std::mem::replace(&mut state.mem, Arc::new(MemTable::new()));
+            state.imm = Some(mem);
+            self.bg_cv.notify_all();
+            return Ok(());
+        }
+    }
+
+    /// background_loop flushes frozen memtables, which writers may be waiting on, before it
+    /// compacts. A shutdown waits for a pending flush but starts no new compaction.
+    fn background_loop(&self) {
+        let mut state = self.state.lock();
+        loop {
+            if state.bg_error.is_some() {
+                if state.shutting_down {
+                    return;
+                }
+                self.bg_cv.wait(&mut state);
+                continue;
+            }
+            let result = if let Some(imm) = state.imm.clone() {
+                self.flush_imm(&mut state, &imm)
+            } else if state.shutting_down {
+                return;
+            } else {
+                match self.pick_compaction(&mut state) {
+                    Ok(Some(c)) => self.compact(&mut state, c),
+                    Ok(None) => {
+                        self.bg_cv.wait(&mut state);
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                }
+            };
+            if let Err(err) = result {
+                state.bg_error = Some(err.to_string());
+            }
+            self.bg_cv.notify_all();
+        }
+    }
+
+    /// flush_imm writes the frozen memtable to a level 0 table and records it, together with
+    /// the log that replaces the memtable's, in the manifest.
+    fn flush_imm(&self, state: &mut MutexGuard<DBState>, imm: &MemTable) -> crate::Result<()> {
+        let number = state.versions.new_file_number();
+        state.pending_outputs.insert(number);
+        let result =
+            MutexGuard::unlocked(state, || self.write_table(imm, number)).and_then(|file| {
+                let mut edit = VersionEdit {
+                    log_number: Some(state.log_number),
+                    ..Default::default()
+                };
+                if let Some(file) = file {
+                    edit.add_file(0, file);
+                }
+                self.log_and_apply(state, edit)
+            });
+        state.pending_outputs.remove(&number);
+        result?;
+        state.imm = None;
+        self.delete_obsolete_files(state);
+        Ok(())
+    }
+
+    /// log_and_apply saves `edit` to the manifest and installs the version it produces. The
+    /// manifest is written and synced with the mutex released, so reads and writes go on
+    /// meanwhile; edits are still applied one at a time.
+    fn log_and_apply(
+        &self,
+        state: &mut MutexGuard<DBState>,
+        edit: VersionEdit,
+    ) -> crate::Result<()> {
+        while state.versions.applying() {
+            self.bg_cv.wait(state);
+        }
+        let mut edit = state.versions.begin_apply(edit)?;
+        let result = MutexGuard::unlocked(state, || edit.write());
+        let result = state.versions.finish_apply(edit, result);
+        self.bg_cv.notify_all();
+        result
+    }
+
+    /// write_table writes the entries of `mem` to table `number`.
+    fn write_table(&self, mem: &MemTable, number: u64) -> crate::Result<Option<FileMetaData>> {
+        if mem.is_empty() {
+            return Ok(None);
+        }
+        let mut output = TableOutput::create(&self.path, number, self.table_opts.clone())?;
+        let mut iter = mem.iter();
+        while let Some(raw_entry) = iter.current() {
+            let entry = MemEntry::decode_from_slice(raw_entry)?;
+            let key = internal_key(entry.key(), entry.seq(), entry.value_type());
+            output.add(&key, entry.value())?;
+            iter.next();
+        }
+        Ok(Some(output.finish(&self.path)?))
+    }
+
+    fn pick_compaction(&self, state: &mut DBState) -> crate::Result<Option<Compaction>> {
+        let version = state.versions.current();
+        if !state.picker.needs_compaction(&version) {
+            return Ok(None);
+        }
+        state.picker.pick_compaction(&version)
+    }
+
+    /// compact runs `c` and installs its outputs in place of its inputs. A single input that
+    /// overlaps nothing in the output level is just moved there.
+    fn compact(&self, state: &mut MutexGuard<DBState>, c: Compaction) -> crate::Result<()> {
+        let mut edit = VersionEdit::default();
+        c.add_input_deletions(&mut edit);
+        let result = if c.is_trivial_move() {
+            edit.add_file(c.output_level, c.inputs[0].1.as_ref().clone());
+            self.log_and_apply(state, edit)
+        } else {
+            // snapshots taken from now on see everything the compaction sees
+            let smallest_snapshot = match state.snapshots.keys().next() {
+                Some(sequence) => *sequence,
+                None => state.versions.last_sequence(),
+            };
+            MutexGuard::unlocked(state, || self.do_compaction_work(&c, smallest_snapshot)).and_then(
+                |outputs| {
+                    for file in outputs {
+                        edit.add_file(c.output_level, file);
+                    }
+                    self.log_and_apply(state, edit)
+                },
+            )
+        };
+        // only one compaction runs at a time and any flush it made in between is done
+        state.pending_outputs.clear();
+        result?;
+        // the compaction holds on to the version of its inputs, which would keep them live
+        drop(c);
+        self.delete_obsolete_files(state);
+        Ok(())
+    }
+
+    /// do_compaction_work merges the inputs of `c` into new tables. A version of a key is dropped
+    /// once a newer one is visible to every snapshot, at or after `smallest_snapshot`, and a
+    /// deletion once no snapshot sees past it and no deeper level may still hold the key.
+    /// Outputs are cut at `TableOptions::table_size`, but never between two versions of a key,
+    /// so the tables of a level don't share user keys.
+    fn do_compaction_work(
+        &self,
+        c: &Compaction,
+        smallest_snapshot: u64,
+    ) -> crate::Result<Vec<FileMetaData>> {
+        let mut input = c.input_iter()?;
+        input.seek_to_first();
+        let mut outputs = Vec::new();
+        let mut output: Option<TableOutput> = None;
+        let mut current_user_key: Option<Vec<u8>> = None;
+        // sequence of the previous, newer version of the current key
+        let mut last_sequence_for_key = MAX_SEQUENCE;
+        while input.valid() {
+            let (key, value) = (input.key(), input.value());
+            let (user_key, sequence, vtype) = parse_internal_key(key)?;
+            if current_user_key.as_deref() != Some(user_key) {
+                if let Some(o) =
+                    output.take_if(|o| o.estimated_size() >= self.table_opts.table_size as u64)
+                {
+                    outputs.push(o.finish(&self.path)?);
+                }
+                let current = current_user_key.get_or_insert_with(Vec::new);
+                current.clear();
+                current.extend_from_slice(user_key);
+                last_sequence_for_key = MAX_SEQUENCE;
+            }
+            let obsolete = last_sequence_for_key <= smallest_snapshot
+                || (vtype == ValueType::Deletion
+                    && sequence <= smallest_snapshot
+                    && c.is_base_level_for_key(user_key));
+            last_sequence_for_key = sequence;
+            if !obsolete {
+                if output.is_none() {
+                    let number = self.new_output_number()?;
+                    output = Some(TableOutput::create(
+                        &self.path,
+                        number,
+                        self.table_opts.clone(),
+                    )?);
+                }
+                output.as_mut().unwrap().add(key, value)?;
+            }
+            input.next();
+        }
+        input.status()?;
+        if let Some(o) = output {
+            outputs.push(o.finish(&self.path)?);
+        }
+        Ok(outputs)
+    }
+
+    /// new_output_number allocates the number of a compaction output. A frozen memtable is
+    /// flushed first, so writers waiting on it don't stall for the whole compaction.
+    fn new_output_number(&self) -> crate::Result<u64> {
+        let mut state = self.state.lock();
+        if let Some(imm) = state.imm.clone() {
+            self.flush_imm(&mut state, &imm)?;
+            self.bg_cv.notify_all();
+        }
+        let number = state.versions.new_file_number();
+        state.pending_outputs.insert(number);
+        Ok(number)
+    }
+
+    /// delete_obsolete_files removes the logs older than the oldest one the memtables need,
+    /// unless they're kept for recycling, the tables no live version refers to, old manifests
+    /// and leftover temporary files. It runs on
+    /// the background thread, or before it starts, which is the only place tables are built, so
+    /// the only temporary files in use are those of pending outputs.
+    fn delete_obsolete_files(&self, state: &mut DBState) {
+        let live = state.versions.live_files();
+        let files = match list_files(&self.path) {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+        for (number, typ) in files {
+            let (keep, path) = match typ {
+                FileType::Log => {
+                    let mut keep = number >= state.versions.log_number()
+                        || state.recycle_logs.contains(&number);
+                    if !keep
+                        && number >= state.first_recyclable_log
+                        && state.recycle_logs.len() < self.opts.recycle_log_file_num
+                    {
+                        state.recycle_logs.push_back(number);
+                        keep = true;
+                    }
+                    (keep, log_file_name(&self.path, number))
+                }
+                FileType::Table => (
+                    live.contains(&number) || state.pending_outputs.contains(&number),
+                    table_file_name(&self.path, number),
+                ),
+                FileType::Manifest => (
+                    number == state.versions.manifest_number(),
+                    manifest_file_name(&self.path, number),
+                ),
+                FileType::Temp => (
+                    state.pending_outputs.contains(&number),
+                    temp_file_name(&self.path, number),
+                ),
+                FileType::Current => (true, current_file_name(&self.path)),
+            };
+            if !keep {
+                if typ == FileType::Table {
+                    self.table_cache.evict(number);
+                }
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// build_group returns how many writes from the front of `writers` the leader writes together.
+/// A small write isn't held up by much larger ones, and a write that asks for a sync doesn't
+/// join a leader that won't sync.
+fn build_group(writers: &VecDeque<PendingWrite>) -> usize {
+    let leader_size = match &writers[0].batch {
+        Some(batch) => batch.approximate_size(),
+        None => return 1,
+    };
+    let max_size = if leader_size <= 128 << 10 {
+        leader_size + (128 << 10)
+    } else {
+        MAX_GROUP_SIZE
+    };
+    let mut size = leader_size;
+    let mut len = 1;
+    for writer in writers.iter().skip(1) {
+        let batch = match &writer.batch {
+            Some(batch) if !writer.sync || writers[0].sync => batch,
+            _ => break,
+        };
+        size += batch.approximate_size();
+        if size > max_size {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+/// create_log starts log `number`, written over the obsolete log `reuse` if given.
+fn create_log(
+    dir: &Path,
+    number: u64,
+    reuse: Option<u64>,
+    opts: &Options,
+) -> crate::Result<LogWriter<BufWriter<File>>> {
+    let path = log_file_name(dir, number);
+    let file = match reuse {
+        Some(old) => {
+            fs::rename(log_file_name(dir, old), &path)?;
+            OpenOptions::new().write(true).open(&path)?
+        }
+        None => create_file(&path)?,
+    };
+    if opts.recycle_log_file_num > 0 {
+        return Ok(LogWriter::new_recyclable(BufWriter::new(file), number));
+    }
+    Ok(LogWriter::new(BufWriter::new(file)))
+}
+
+/// recover_log replays the write batches of write-ahead log `number` into `mem` and returns the
+/// highest sequence number found in it, and whether the log is in the recyclable format.
+fn recover_log(
+    dir: &Path,
+    number: u64,
+    mem: &MemTable,
+    mode: WalRecoveryMode,
+) -> crate::Result<(u64, bool)> {
+    let path = &log_file_name(dir, number);
+    let mut reader = LogReader::new_with_log_number(File::open(path)?, true, number);
+    let mut record = Vec::new();
+    let mut last_sequence = 0;
+    loop {
+        match reader.read(&mut record) {
+            Ok(0) => break,
+            Ok(_) => {
+                let batch = WriteBatch::decode_from_slice(&record)?;
+                batch.insert_into(mem)?;
+                last_sequence = last_sequence.max(batch.sequence() + batch.count() as u64 - 1);
+            }
+            Err(err @ Error::Corruption(_)) => {
+                // Only a damaged record followed by the end of the log is a torn write, anything
+                // else is corruption whatever the recovery mode.
+                let at_tail = matches!(reader.read(&mut record), Ok(0));
+                if mode == WalRecoveryMode::Fail || !at_tail {
+                    return Err(err);
+                }
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(reader.record_end() as u64)?;
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok((last_sequence, reader.recycled()))
+}
+
+/// list_files returns the database files found in `dir` sorted by file number.
+fn list_files(dir: &Path) -> crate::Result<Vec<(u64, FileType)>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(file) = entry.file_name().to_str().and_then(parse_file_name) {
+            files.push(file);
+        }
+    }
+    files.sort_by_key(|(number, _)| *number);
+    Ok(files)
+}
+
+fn lock_dir(dir: &Path) -> crate::Result<File> {
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file_name(dir))?;
+    lock.try_lock_exclusive()
+        .map_err(|e| Error::IOError(format!("lock on {} is already held: {}", dir.display(), e)))?;
+    Ok(lock)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::{File, OpenOptions};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use crate::codec::Codec;
+    use crate::db::{build_group, list_files, PendingWrite};
+    use crate::filename::{log_file_name, FileType};
+    use crate::log::LogReader;
+    use crate::types::user_key;
+    use crate::version_set::NUM_LEVELS;
+    use crate::{
+        BlockCacheStats, CompactionStyle, CompressionType, DBIterator, Error, Options, ReadOptions,
+        TableOptions, WalRecoveryMode, WalSyncMode, WriteBatch, WriteOptions, DB,
+    };
+
+    /// wait_for_background waits until nothing is left to flush or compact.
+    fn wait_for_background(db: &DB) {
+        let mut state = db.inner.state.lock();
+        while state.bg_error.is_none()
+            && (state.imm.is_some() || state.picker.needs_compaction(&state.versions.current()))
+        {
+            db.inner.bg_cv.wait(&mut state);
+        }
+        assert_eq!(state.bg_error, None);
+    }
+
+    /// wait_for_sync waits until the background thread synced the log.
+    fn wait_for_sync(db: &DB) {
+        for _ in 0..100 {
+            if db.inner.state.lock().unsynced_bytes == 0 {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("log wasn't synced");
+    }
+
+    fn count_files(dir: &Path, typ: FileType) -> usize {
+        list_files(dir)
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| *t == typ)
+            .count()
+    }
+
+    #[test]
+    fn test_put_get_delete() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.put(b"kiwi", b"green").unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"yellow".to_vec()));
+        assert_eq!(db.get(b"cherry").unwrap(), None);
+
+        db.put(b"apple", b"green").unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+
+        db.delete(b"banana").unwrap();
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        db.put(b"banana", b"brown").unwrap();
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"brown".to_vec()));
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_open_options() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let opts = Options {
+            create_if_missing: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(&path, opts),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+        let opts = Options {
+            error_if_exists: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(&path, opts),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_zstd_tables() {
+        let dir = tempdir().unwrap();
+        let opts = || Options {
+            table_options: TableOptions {
+                compression: CompressionType::Zstd,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts()).unwrap();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i), format!("value{}", i).repeat(10))
+                .unwrap();
+        }
+        db.flush().unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), opts()).unwrap();
+        assert!(db.inner.state.lock().mem.is_empty());
+        for i in 0..100 {
+            assert_eq!(
+                db.get(format!("key{:03}", i)).unwrap(),
+                Some(format!("value{}", i).repeat(10).into_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert!(matches!(
+            DB::open(dir.path(), Options::default()),
+            Err(Error::IOError(_))
+        ));
+        db.close().unwrap();
+        DB::open(dir.path(), Options::default()).unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.put(b"kiwi", b"green").unwrap();
+        db.delete(b"banana").unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+
+        // sequence numbers carry on from the recovered log, so newer writes win
+        db.put(b"apple", b"green").unwrap();
+        db.close().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+    }
+
+    #[test]
+    fn test_recover_torn_tail() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.close().unwrap();
+
+        let log = OpenOptions::new()
+            .write(true)
+            .open(log_file_name(dir.path(), 1))
+            .unwrap();
+        let len = log.metadata().unwrap().len();
+        log.set_len(len - 3).unwrap();
+
+        let opts = Options {
+            wal_recovery_mode: WalRecoveryMode::Fail,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(dir.path(), opts),
+            Err(Error::Corruption(_))
+        ));
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        db.put(b"kiwi", b"green").unwrap();
+        db.close().unwrap();
+
+        let opts = Options {
+            wal_recovery_mode: WalRecoveryMode::Fail,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"apple", b"red");
+        batch.delete(b"banana");
+        batch.put(b"kiwi", b"green");
+        batch.put(b"apple", b"green");
+        db.write(batch).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"plum", b"purple");
+        batch.put(b"", b"nothing");
+        assert!(matches!(db.write(batch), Err(Error::InvalidArgument(_))));
+        assert_eq!(db.get(b"plum").unwrap(), None);
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        assert_eq!(db.get(b"kiwi").unwrap(), Some(b"green".to_vec()));
+        db.put(b"banana", b"brown").unwrap();
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"brown".to_vec()));
+    }
+
+    #[test]
+    fn test_write_batch_torn() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+
+        // large enough to be split into several log fragments
+        let mut batch = WriteBatch::new();
+        for i in 0..100_u32 {
+            batch.put(i.to_be_bytes(), vec![i as u8; 1024]);
+        }
+        db.write(batch).unwrap();
+        db.close().unwrap();
+
+        let log = OpenOptions::new()
+            .write(true)
+            .open(log_file_name(dir.path(), 1))
+            .unwrap();
+        let len = log.metadata().unwrap().len();
+        log.set_len(len - 10).unwrap();
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        for i in 0..100_u32 {
+            assert_eq!(db.get(i.to_be_bytes()).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.flush().unwrap();
+        assert_eq!(count_files(dir.path(), FileType::Table), 1);
+        assert_eq!(count_files(dir.path(), FileType::Log), 1);
+
+        // newer versions in the memtable and in newer tables shadow the older table
+        db.delete(b"apple").unwrap();
+        db.put(b"banana", b"brown").unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), None);
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"brown".to_vec()));
+        db.flush().unwrap();
+        assert_eq!(count_files(dir.path(), FileType::Table), 2);
+        assert_eq!(db.get(b"apple").unwrap(), None);
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"brown".to_vec()));
+        assert_eq!(db.get(b"cherry").unwrap(), None);
+
+        // nothing to flush
+        db.flush().unwrap();
+        assert_eq!(count_files(dir.path(), FileType::Table), 2);
+        db.put(b"apple", b"green").unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"brown".to_vec()));
+        db.flush().unwrap();
+        db.close().unwrap();
+
+        // with every log flushed the sequence comes from the manifest
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 5);
+        assert_eq!(count_files(dir.path(), FileType::Manifest), 1);
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+    }
+
+    #[test]
+    fn test_flush_write_buffer_size() {
+        let dir = tempdir().unwrap();
+        let opts = Options {
+            write_buffer_size: 32 << 10,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        for round in 0..3_u32 {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", i);
+                if round == 2 && i % 3 == 0 {
+                    db.delete(&key).unwrap();
+                } else {
+                    db.put(&key, format!("{}-{}", key, round).repeat(10))
+                        .unwrap();
+                }
+            }
+        }
+        let check = |db: &DB| {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", i);
+                let expected = (i % 3 != 0).then(|| format!("{}-2", key).repeat(10).into_bytes());
+                assert_eq!(db.get(&key).unwrap(), expected, "{}", key);
+            }
+        };
+        check(&db);
+        db.close().unwrap();
+        assert!(count_files(dir.path(), FileType::Table) > 1);
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        check(&db);
+        db.flush().unwrap();
+        assert_eq!(count_files(dir.path(), FileType::Log), 1);
+        check(&db);
+    }
+
+    #[test]
+    fn test_compaction_drops_obsolete_entries() {
+        let dir = tempdir().unwrap();
+        let opts = Options {
+            level0_file_num_compaction_trigger: 2,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        for i in 0..100_u32 {
+            db.put(format!("key{:03}", i), b"old").unwrap();
+        }
+        db.flush().unwrap();
+        for i in 0..100_u32 {
+            if i % 2 == 0 {
+                db.delete(format!("key{:03}", i)).unwrap();
+            } else {
+                db.put(format!("key{:03}", i), b"new").unwrap();
+            }
+        }
+        db.flush().unwrap();
+        wait_for_background(&db);
+
+        // with no deeper level to shadow, only the newest live versions are left
+        assert_eq!(count_files(dir.path(), FileType::Table), 1);
+        let version = db.inner.state.lock().versions.current();
+        assert!(version.files(0).is_empty());
+        assert_eq!(version.files(1).len(), 1);
+        let table = version.table(version.files(1)[0].number).unwrap();
+        assert_eq!(table.index().keys_count(), 50);
+        for i in 0..100_u32 {
+            let expected = (i % 2 == 1).then(|| b"new".to_vec());
+            assert_eq!(db.get(format!("key{:03}", i)).unwrap(), expected);
+        }
+
+        drop(version);
+
+        // deleting everything compacts to nothing
+        for i in (1..100_u32).step_by(2) {
+            db.delete(format!("key{:03}", i)).unwrap();
+        }
+        db.flush().unwrap();
+        db.put(b"key000", b"newest").unwrap();
+        db.delete(b"key000").unwrap();
+        db.flush().unwrap();
+        wait_for_background(&db);
+        assert_eq!(count_files(dir.path(), FileType::Table), 0);
+        assert_eq!(db.get(b"key001").unwrap(), None);
+    }
+
+    #[test]
+    fn test_leveled_compaction() {
+        let dir = tempdir().unwrap();
+        let opts = || Options {
+            write_buffer_size: 16 << 10,
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 64 << 10,
+            max_bytes_for_level_multiplier: 2,
+            table_options: TableOptions {
+                table_size: 16 << 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts()).unwrap();
+        for round in 0..4_u32 {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", (i * 7919) % 1000);
+                if round == 3 && i % 4 == 0 {
+                    db.delete(&key).unwrap();
+                } else {
+                    db.put(&key, format!("{}-{}", key, round).repeat(20))
+                        .unwrap();
+                }
+            }
+        }
+        wait_for_background(&db);
+        let check = |db: &DB| {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", (i * 7919) % 1000);
+                let expected = (i % 4 != 0).then(|| format!("{}-3", key).repeat(20).into_bytes());
+                assert_eq!(db.get(&key).unwrap(), expected, "{}", key);
+            }
+        };
+        check(&db);
+
+        let version = db.inner.state.lock().versions.current();
+        assert!(version.files(0).len() < 2);
+        assert!(version.files(1).len() > 1);
+        assert!((2..NUM_LEVELS).any(|level| !version.files(level).is_empty()));
+        for level in 1..NUM_LEVELS {
+            // deeper levels hold disjoint key ranges
+            for pair in version.files(level).windows(2) {
+                assert!(pair[0].largest_user_key() < pair[1].smallest_user_key());
+            }
+        }
+        drop(version);
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), opts()).unwrap();
+        check(&db);
+    }
+
+    #[test]
+    fn test_tiered_compaction() {
+        let dir = tempdir().unwrap();
+        let opts = || Options {
+            write_buffer_size: 16 << 10,
+            compaction_style: CompactionStyle::Tiered,
+            tiered_max_run_count: 3,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts()).unwrap();
+        for round in 0..4_u32 {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", (i * 7919) % 1000);
+                if round == 3 && i % 4 == 0 {
+                    db.delete(&key).unwrap();
+                } else {
+                    db.put(&key, format!("{}-{}", key, round).repeat(10))
+                        .unwrap();
+                }
+            }
+        }
+        wait_for_background(&db);
+        let check = |db: &DB| {
+            for i in 0..1000_u32 {
+                let key = format!("key{:05}", (i * 7919) % 1000);
+                let expected = (i % 4 != 0).then(|| format!("{}-3", key).repeat(10).into_bytes());
+                assert_eq!(db.get(&key).unwrap(), expected, "{}", key);
+            }
+        };
+        check(&db);
+        let version = db.inner.state.lock().versions.current();
+        let runs = version.files(0).len()
+            + (1..NUM_LEVELS)
+                .filter(|level| !version.files(*level).is_empty())
+                .count();
+        assert!(runs <= 3);
+        drop(version);
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), opts()).unwrap();
+        check(&db);
+    }
+
+    #[test]
+    fn test_tiered_compaction_split_outputs() {
+        let dir = tempdir().unwrap();
+        // merged runs are much larger than a table
+        let opts = Options {
+            write_buffer_size: 64 << 10,
+            compaction_style: CompactionStyle::Tiered,
+            tiered_max_run_count: 3,
+            table_options: TableOptions {
+                table_size: 32 << 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        for i in 0..20000_u32 {
+            db.put(format!("key{:05}", i), [b'v'; 100]).unwrap();
+        }
+        wait_for_background(&db);
+        let version = db.inner.state.lock().versions.current();
+        let runs = version.files(0).len()
+            + (1..NUM_LEVELS)
+                .filter(|level| !version.files(*level).is_empty())
+                .count();
+        assert!(runs <= 3);
+        assert!((1..NUM_LEVELS).any(|level| version.files(level).len() > 1));
+        drop(version);
+        for i in (0..20000_u32).step_by(97) {
+            assert_eq!(
+                db.get(format!("key{:05}", i)).unwrap(),
+                Some(vec![b'v'; 100])
+            );
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = tempdir().unwrap();
+        let opts = Options {
+            level0_file_num_compaction_trigger: 2,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        let keys_count = |db: &DB| {
+            let version = db.inner.state.lock().versions.current();
+            (0..NUM_LEVELS)
+                .flat_map(|level| version.files(level).iter())
+                .map(|f| version.table(f.number).unwrap().index().keys_count())
+                .sum::<usize>()
+        };
+
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        let snapshot = db.snapshot();
+        let at_snapshot = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..Default::default()
+        };
+        db.put(b"apple", b"green").unwrap();
+        db.delete(b"banana").unwrap();
+        db.put(b"cherry", b"red").unwrap();
+        let check = |db: &DB| {
+            let get = |key: &[u8]| db.get_with_options(&at_snapshot, key).unwrap();
+            assert_eq!(get(b"apple"), Some(b"red".to_vec()));
+            assert_eq!(get(b"banana"), Some(b"yellow".to_vec()));
+            assert_eq!(get(b"cherry"), None);
+            assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+            assert_eq!(db.get(b"banana").unwrap(), None);
+        };
+        check(&db);
+
+        // the versions the snapshot sees survive a compaction
+        db.flush().unwrap();
+        db.put(b"apple", b"yellow").unwrap();
+        db.flush().unwrap();
+        wait_for_background(&db);
+        assert!(db.inner.state.lock().versions.current().files(0).is_empty());
+        assert_eq!(keys_count(&db), 6);
+        assert_eq!(
+            db.get_with_options(&at_snapshot, b"apple").unwrap(),
+            Some(b"red".to_vec())
+        );
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"yellow".to_vec()));
+
+        // and are dropped by the first compaction after it's released
+        drop(snapshot);
+        assert!(db.inner.state.lock().snapshots.is_empty());
+        db.put(b"apple", b"orange").unwrap();
+        db.flush().unwrap();
+        db.put(b"cherry", b"black").unwrap();
+        db.flush().unwrap();
+        wait_for_background(&db);
+        assert_eq!(keys_count(&db), 2);
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"orange".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        assert_eq!(db.get(b"cherry").unwrap(), Some(b"black".to_vec()));
+    }
+
+    #[test]
+    fn test_iter() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        let collect = |iter: &mut DBIterator, forward: bool| {
+            let mut entries = Vec::new();
+            while iter.valid() {
+                entries.push((iter.key().to_vec(), iter.value().to_vec()));
+                if forward {
+                    iter.next();
+                } else {
+                    iter.prev();
+                }
+            }
+            assert!(iter.status().is_ok());
+            entries
+        };
+        let entries = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        // entries of a key spread over a table and the memtable
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.put(b"cherry", b"red").unwrap();
+        db.put(b"durian", b"green").unwrap();
+        db.flush().unwrap();
+        let snapshot = db.snapshot();
+        db.put(b"apple", b"green").unwrap();
+        db.delete(b"banana").unwrap();
+        db.delete(b"durian").unwrap();
+        db.put(b"elderberry", b"black").unwrap();
+
+        let now = entries(&[
+            ("apple", "green"),
+            ("cherry", "red"),
+            ("elderberry", "black"),
+        ]);
+        let mut iter = db.iter(&ReadOptions::default()).unwrap();
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert_eq!(collect(&mut iter, true), now);
+        iter.seek_to_last();
+        let mut backward = collect(&mut iter, false);
+        backward.reverse();
+        assert_eq!(backward, now);
+
+        // seeking lands on the first key at or after the target, which may be deleted
+        iter.seek(b"b");
+        assert_eq!(iter.key(), b"cherry");
+        iter.seek(b"cherry");
+        assert_eq!(iter.key(), b"cherry");
+        iter.seek(b"zebra");
+        assert!(!iter.valid());
+
+        // switching directions
+        iter.seek(b"cherry");
+        iter.prev();
+        assert_eq!((iter.key(), iter.value()), (&b"apple"[..], &b"green"[..]));
+        iter.next();
+        assert_eq!(iter.key(), b"cherry");
+        iter.next();
+        assert_eq!(iter.key(), b"elderberry");
+        iter.prev();
+        iter.prev();
+        assert_eq!(iter.key(), b"apple");
+        iter.prev();
+        assert!(!iter.valid());
+
+        // an iterator doesn't see later writes, and one at a snapshot sees what it pinned
+        db.put(b"banana", b"green").unwrap();
+        iter.seek_to_first();
+        assert_eq!(collect(&mut iter, true), now);
+        let at_snapshot = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..Default::default()
+        };
+        let mut iter = db.iter(&at_snapshot).unwrap();
+        iter.seek_to_first();
+        assert_eq!(
+            collect(&mut iter, true),
+            entries(&[
+                ("apple", "red"),
+                ("banana", "yellow"),
+                ("cherry", "red"),
+                ("durian", "green")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_iter_bounds() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        // keys in a table, in the memtable, and deleted
+        for key in ["a1", "b1", "b2", "b3", "c1"] {
+            db.put(key, key).unwrap();
+        }
+        db.flush().unwrap();
+        for key in ["a2", "b4", "c2"] {
+            db.put(key, key).unwrap();
+        }
+        db.delete(b"b2").unwrap();
+        let scan = |opts: &ReadOptions, seek: Option<&[u8]>, forward: bool| {
+            let mut iter = db.iter(opts).unwrap();
+            match (seek, forward) {
+                (Some(target), _) => iter.seek(target),
+                (None, true) => iter.seek_to_first(),
+                (None, false) => iter.seek_to_last(),
+            }
+            let mut keys = Vec::new();
+            while iter.valid() {
+                keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+                if forward {
+                    iter.next();
+                } else {
+                    iter.prev();
+                }
+            }
+            assert!(iter.status().is_ok());
+            keys
+        };
+
+        let bounded = ReadOptions {
+            lower_bound: Some(b"a2"),
+            upper_bound: Some(b"c1"),
+            ..Default::default()
+        };
+        assert_eq!(scan(&bounded, None, true), ["a2", "b1", "b3", "b4"]);
+        assert_eq!(scan(&bounded, None, false), ["b4", "b3", "b1", "a2"]);
+        // seeks before the lower bound land on it
+        assert_eq!(scan(&bounded, Some(b"a"), true), ["a2", "b1", "b3", "b4"]);
+        assert_eq!(scan(&bounded, Some(b"b2"), false), ["b3", "b1", "a2"]);
+        assert!(scan(&bounded, Some(b"c"), true).is_empty());
+
+        // a bound range no table overlaps sees the memtable only
+        let above_tables = ReadOptions {
+            lower_bound: Some(b"c2"),
+            ..Default::default()
+        };
+        assert_eq!(scan(&above_tables, None, true), ["c2"]);
+
+        let prefix = ReadOptions {
+            prefix_same_as_start: true,
+            ..Default::default()
+        };
+        assert_eq!(scan(&prefix, Some(b"b"), true), ["b1", "b3", "b4"]);
+        assert_eq!(scan(&prefix, Some(b"b3"), true), ["b3"]);
+        assert!(scan(&prefix, Some(b"b2"), true).is_empty());
+        assert_eq!(scan(&prefix, Some(b"b"), false), ["b1"]);
+        // without a seek the prefix doesn't apply
+        assert_eq!(scan(&prefix, None, true).len(), 7);
+    }
+
+    #[test]
+    fn test_sequence_shared_by_writers() {
+        let dir = tempdir().unwrap();
+        let opts = || Options {
+            write_buffer_size: 16 << 10,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts()).unwrap();
+        std::thread::scope(|scope| {
+            for t in 0..4_u32 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in 0..250_u32 {
+                        let mut batch = WriteBatch::new();
+                        batch.put(format!("key{}-{:03}", t, i), b"value");
+                        batch.put(b"last", format!("{}-{}", t, i));
+                        db.write(batch).unwrap();
+                    }
+                });
+            }
+        });
+        // every entry took its own sequence
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 2000);
+        let last = db.get(b"last").unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), opts()).unwrap();
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 2000);
+        assert_eq!(db.get(b"last").unwrap(), last);
+        db.flush().unwrap();
+        db.close().unwrap();
+
+        // carried by the manifest once no log holds the writes any more
+        let db = DB::open(dir.path(), opts()).unwrap();
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 2000);
+        db.put(b"last", b"newest").unwrap();
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 2001);
+        assert_eq!(db.get(b"last").unwrap(), Some(b"newest".to_vec()));
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+
+        // hold the queue with a stand-in leader until every writer is waiting behind it
+        db.inner.state.lock().writers.push_back(PendingWrite {
+            id: u64::MAX,
+            batch: None,
+            sync: false,
+        });
+        std::thread::scope(|scope| {
+            for t in 0..8_u32 {
+                let db = &db;
+                scope.spawn(move || db.put(format!("key{}", t), b"value").unwrap());
+            }
+            let mut state = db.inner.state.lock();
+            while state.writers.len() < 9 {
+                drop(state);
+                std::thread::yield_now();
+                state = db.inner.state.lock();
+            }
+            state.writers.pop_front();
+            db.inner.writers_cv.notify_all();
+        });
+        for t in 0..8_u32 {
+            assert_eq!(
+                db.get(format!("key{}", t)).unwrap(),
+                Some(b"value".to_vec())
+            );
+        }
+        assert_eq!(db.inner.state.lock().versions.last_sequence(), 9);
+        db.close().unwrap();
+
+        // the eight writes went to the log as one record
+        let mut reader = LogReader::new(File::open(log_file_name(dir.path(), 1)).unwrap(), true);
+        let mut record = Vec::new();
+        let mut counts = Vec::new();
+        while reader.read(&mut record).unwrap() > 0 {
+            counts.push(WriteBatch::decode_from_slice(&record).unwrap().count());
+        }
+        assert_eq!(counts, vec![1, 8]);
+    }
+
+    #[test]
+    fn test_wal_sync() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert!(db.sync_thread.is_none());
+        db.put(b"apple", b"red").unwrap();
+        assert!(db.inner.state.lock().unsynced_bytes > 0);
+        let mut batch = WriteBatch::new();
+        batch.put(b"banana", b"yellow");
+        db.write_with_options(&WriteOptions { sync: true }, batch)
+            .unwrap();
+        assert_eq!(db.inner.state.lock().unsynced_bytes, 0);
+        db.close().unwrap();
+
+        // synced by the background thread once enough was written
+        let db = DB::open(
+            dir.path(),
+            Options {
+                wal_sync_mode: WalSyncMode::Bytes(256),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        db.put(b"cherry", b"red").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(db.inner.state.lock().unsynced_bytes > 0);
+        db.put(b"durian", vec![0; 512]).unwrap();
+        wait_for_sync(&db);
+        db.close().unwrap();
+
+        // or every interval
+        let db = DB::open(
+            dir.path(),
+            Options {
+                wal_sync_mode: WalSyncMode::Interval(Duration::from_millis(10)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        db.put(b"elderberry", b"black").unwrap();
+        wait_for_sync(&db);
+        for key in ["apple", "banana", "cherry", "durian", "elderberry"] {
+            assert!(db.get(key).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_sync_write_not_grouped_behind_unsynced_leader() {
+        let write = |id, sync| {
+            let mut batch = WriteBatch::new();
+            batch.put(b"key", b"value");
+            PendingWrite {
+                id,
+                batch: Some(batch),
+                sync,
+            }
+        };
+        let writers = [
+            write(1, false),
+            write(2, false),
+            write(3, true),
+            write(4, false),
+        ];
+        assert_eq!(build_group(&writers.into_iter().collect()), 2);
+        let writers = [write(1, true), write(2, false), write(3, true)];
+        assert_eq!(build_group(&writers.into_iter().collect()), 3);
+    }
+
+    #[test]
+    fn test_recycle_logs() {
+        let dir = tempdir().unwrap();
+        let opts = || Options {
+            recycle_log_file_num: 2,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts()).unwrap();
+        for round in 0..5_u32 {
+            for i in 0..100_u32 {
+                db.put(
+                    format!("key{:03}", i),
+                    format!("value{}", round).repeat(round as usize + 1),
+                )
+                .unwrap();
+            }
+            db.flush().unwrap();
+            wait_for_background(&db);
+        }
+        // each new memtable wrote over the log of the one flushed before
+        let state = db.inner.state.lock();
+        assert_eq!(state.recycle_logs.len(), 1);
+        assert!(state.log_number > 6);
+        drop(state);
+        assert_eq!(count_files(dir.path(), FileType::Log), 2);
+
+        // the current log was written over a longer one
+        db.put(b"apple", b"red").unwrap();
+        let log_number = db.inner.state.lock().log_number;
+        db.close().unwrap();
+        let log_len = fs::metadata(log_file_name(dir.path(), log_number))
+            .unwrap()
+            .len();
+        assert!(log_len > 1000);
+
+        for recycle_log_file_num in [2, 0] {
+            let db = DB::open(
+                dir.path(),
+                Options {
+                    recycle_log_file_num,
+                    wal_recovery_mode: WalRecoveryMode::Fail,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+            assert_eq!(db.get(b"key042").unwrap(), Some(b"value4".repeat(5)));
+            assert_eq!(db.inner.state.lock().versions.last_sequence(), 501);
+            db.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reopen_recycled_log_without_recycling() {
+        let dir = tempdir().unwrap();
+        let opts = Options {
+            recycle_log_file_num: 2,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        for round in 0..3_u32 {
+            for i in 0..100_u32 {
+                db.put(format!("key{:03}", i), format!("value{}", round).repeat(10))
+                    .unwrap();
+            }
+            db.flush().unwrap();
+            wait_for_background(&db);
+        }
+        // the current log was written over a longer one
+        db.put(b"apple", b"red").unwrap();
+        let log_number = db.inner.state.lock().log_number;
+        db.close().unwrap();
+
+        // the log that may go on with old records isn't appended to
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert!(db.inner.state.lock().log_number > log_number);
+        db.put(b"banana", b"yellow").unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"red".to_vec()));
+        assert_eq!(db.get(b"banana").unwrap(), Some(b"yellow".to_vec()));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_table_properties() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        assert!(db.table_properties().unwrap().is_empty());
+        for i in 0..100 {
+            db.put(format!("key{:03}", i), format!("value{}", i))
+                .unwrap();
+        }
+        db.delete(b"key042").unwrap();
+        db.flush().unwrap();
+
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        let (number, table_props) = props.iter().next().unwrap();
+        let version = db.inner.state.lock().versions.current();
+        assert_eq!(version.files(0)[0].number, *number);
+        assert_eq!(table_props.entry_count, 101);
+        assert_eq!(table_props.deletion_count, 1);
+        assert_eq!(
+            (table_props.min_sequence, table_props.max_sequence),
+            (1, 101)
+        );
+        assert_eq!(user_key(&table_props.smallest_key), b"key000");
+        assert_eq!(user_key(&table_props.largest_key), b"key099");
+        assert_eq!(table_props.compression, CompressionType::None);
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let dir = tempdir().unwrap();
+        let db = DB::open(dir.path(), Options::default()).unwrap();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i), format!("value{}", i))
+                .unwrap();
+        }
+        db.flush().unwrap();
+        // the first read of a block misses, later ones hit once the cache admitted it
+        for _ in 0..100 {
+            assert_eq!(db.get(b"key042").unwrap(), Some(b"value42".to_vec()));
+            if db.block_cache_stats().hits > 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let stats = db.block_cache_stats();
+        assert!(stats.hits > 0);
+        assert!(stats.misses > 0);
+        db.close().unwrap();
+
+        let opts = Options {
+            table_options: TableOptions {
+                block_cache_size: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        assert_eq!(db.get(b"key042").unwrap(), Some(b"value42".to_vec()));
+        assert_eq!(db.block_cache_stats(), BlockCacheStats::default());
+    }
+
+    #[test]
+    fn test_max_open_files() {
+        let dir = tempdir().unwrap();
+        let opts = Options {
+            max_open_files: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(dir.path(), opts),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let opts = Options {
+            max_open_files: 2,
+            level0_file_num_compaction_trigger: 100,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path(), opts).unwrap();
+        for round in 0..6 {
+            for i in 0..10 {
+                db.put(format!("key{}-{}", round, i), format!("value{}", round))
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+        assert_eq!(count_files(dir.path(), FileType::Table), 6);
+        // each table is opened again when the cache didn't keep it, and no more than two
+        // stay open
+        for _ in 0..3 {
+            for round in 0..6 {
+                assert_eq!(
+                    db.get(format!("key{}-5", round)).unwrap(),
+                    Some(format!("value{}", round).into_bytes())
+                );
+                assert!(db.inner.table_cache.open_count() <= 2);
+            }
+        }
+        assert_eq!(db.inner.table_cache.open_count(), 2);
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use crate::iter::InternalIterator;
+use crate::merging_iterator::MergingIterator;
+use crate::types::{internal_key, parse_internal_key, user_key, ValueType};
+use crate::version_set::Version;
+use crate::Error;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Direction {
+    // the merged iterator is at the entry of the current key
+    Forward,
+    // the merged iterator is before the entries of the current key, which is in `saved_key`
+    Reverse,
+}
+
+/// DBIterator walks the keys of the database in order with their newest value as of a
+/// sequence, skipping older versions and deleted keys. It reads the memtables and the tables of
+/// the version it was created with, and doesn't see later writes.
+pub struct DBIterator {
+    iter: MergingIterator,
+    sequence: u64,
+    prefix_same_as_start: bool,
+    // the target of the last seek, which keys have to start with if prefix_same_as_start
+    prefix: Option<Vec<u8>>,
+    direction: Direction,
+    valid: bool,
+    // the current entry moving backward, or the user key to skip moving forward
+    saved_key: Vec<u8>,
+    saved_value: Vec<u8>,
+    error: Option<Error>,
+    // keeps the tables being read from being deleted
+    _version: Arc<Version>,
+}
+
+impl DBIterator {
+    pub(crate) fn new(
+        iter: MergingIterator,
+        sequence: u64,
+        version: Arc<Version>,
+        prefix_same_as_start: bool,
+    ) -> Self {
+        Self {
+            iter,
+            sequence,
+            prefix_same_as_start,
+            prefix: None,
+            direction: Direction::Forward,
+            valid: false,
+            saved_key: Vec::new(),
+            saved_value: Vec::new(),
+            error: None,
+            _version: version,
+        }
+    }
+
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    /// key returns the current key, which is empty unless the iterator is valid.
+    pub fn key(&self) -> &[u8] {
+        if !self.valid {
+            return &[];
+        }
+        match self.direction {
+            Direction::Forward => user_key(self.iter.key()),
+            Direction::Reverse => &self.saved_key,
+        }
+    }
+
+    /// value returns the current value, which is empty unless the iterator is valid.
+    pub fn value(&self) -> &[u8] {
+        if !self.valid {
+            return &[];
+        }
+        match self.direction {
+            Direction::Forward => self.iter.value(),
+            Direction::Reverse => &self.saved_value,
+        }
+    }
+
+    pub fn next(&mut self) {
+        if !self.valid {
+            return;
+        }
+        if self.direction == Direction::Reverse {
+            self.direction = Direction::Forward;
+            // step into the entries of the current key, which saved_key skips below
+            if self.iter.valid() {
+                self.iter.next();
+            } else {
+                self.iter.seek_to_first();
+            }
+        } else {
+            save(&mut self.saved_key, user_key(self.iter.key()));
+            self.iter.next();
+        }
+        self.find_next_user_entry(true);
+        self.check_prefix();
+    }
+
+    pub fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+        if self.direction == Direction::Forward {
+            // step back before the entries of the current key
+            save(&mut self.saved_key, user_key(self.iter.key()));
+            loop {
+                self.iter.prev();
+                if !self.iter.valid() || user_key(self.iter.key()) < self.saved_key.as_slice() {
+                    break;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+        self.check_prefix();
+    }
+
+    /// seek moves to the first key at or after `target`. With
+    /// `ReadOptions::prefix_same_as_start`, the iterator then stops at the first key that doesn't
+    /// start with `target`, in either direction.
+    pub fn seek<K: AsRef<[u8]>>(&mut self, target: K) {
+        self.prefix = self.prefix_same_as_start.then(|| target.as_ref().to_vec());
+        self.direction = Direction::Forward;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter.seek(&internal_key(
+            target.as_ref(),
+            self.sequence,
+            ValueType::Value,
+        ));
+        self.find_next_user_entry(false);
+        self.check_prefix();
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.prefix = None;
+        self.direction = Direction::Forward;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter.seek_to_first();
+        self.find_next_user_entry(false);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.prefix = None;
+        self.direction = Direction::Reverse;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter.seek_to_last();
+        self.find_prev_user_entry();
+    }
+
+    /// status returns the error that invalidated the iterator, if any.
+    pub fn status(&mut self) -> crate::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// find_next_user_entry moves forward to the newest visible value of the next key that
+    /// isn't deleted, skipping the entries of `saved_key` if `skipping`.
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        while self.iter.valid() {
+            let (key, sequence, vtype) = match parse_internal_key(self.iter.key()) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    self.error = Some(err);
+                    break;
+                }
+            };
+            if sequence <= self.sequence {
+                match vtype {
+                    ValueType::Deletion => {
+                        // the older entries of a deleted key are hidden
+                        save(&mut self.saved_key, key);
+                        skipping = true;
+                    }
+                    ValueType::Value if skipping && key <= self.saved_key.as_slice() => {}
+                    ValueType::Value => {
+                        self.saved_key.clear();
+                        self.valid = true;
+                        return;
+                    }
+                }
+            }
+            self.iter.next();
+        }
+        self.saved_key.clear();
+        self.invalidate();
+    }
+
+    /// find_prev_user_entry moves backward to the previous key that isn't deleted, buffering
+    /// its newest visible value, and leaves the merged iterator before its entries.
+    fn find_prev_user_entry(&mut self) {
+        let mut vtype = ValueType::Deletion;
+        while self.iter.valid() {
+            let (key, sequence, entry_type) = match parse_internal_key(self.iter.key()) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    self.error = Some(err);
+                    break;
+                }
+            };
+            if sequence <= self.sequence {
+                // entries of a key come oldest first backward, so the newest decides
+                if vtype != ValueType::Deletion && key < self.saved_key.as_slice() {
+                    break;
+                }
+                vtype = entry_type;
+                if vtype == ValueType::Deletion {
+                    self.saved_key.clear();
+                    self.saved_value.clear();
+                } else {
+                    save(&mut self.saved_key, key);
+                    save(&mut self.saved_value, self.iter.value());
+                }
+            }
+            self.iter.prev();
+        }
+        if vtype == ValueType::Deletion || self.error.is_some() {
+            self.direction = Direction::Forward;
+            self.saved_key.clear();
+            self.saved_value.clear();
+            self.invalidate();
+        } else {
+            self.valid = true;
+        }
+    }
+
+    /// check_prefix stops the iterator at a key without the prefix it was seeked to.
+    fn check_prefix(&mut self) {
+        if let Some(prefix) = &self.prefix {
+            if self.valid && !self.key().starts_with(prefix) {
+                self.valid = false;
+            }
+        }
+    }
+
+    /// invalidate marks the iterator done, keeping the error of the merged iterator if it
+    /// stopped on one.
+    fn invalidate(&mut self) {
+        self.valid = false;
+        if let Err(err) = self.iter.status() {
+            self.error.get_or_insert(err);
+        }
+    }
+}
+
+fn save(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.clear();
+    buf.extend_from_slice(data);
+}
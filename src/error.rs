@@ -20,6 +20,12 @@ pub enum Error {
     #[error("corruption {0}")]
     Corruption(String),
 
+    #[error("invalid argument {0}")]
+    InvalidArgument(String),
+
+    #[error("background error {0}")]
+    BackgroundError(String),
+
     #[error("Error {0}")]
     AnyError(Box<dyn std::error::Error + Send + Sync>),
 }
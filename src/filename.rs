@@ -0,0 +1,115 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum FileType {
+    Log,
+    Table,
+    Temp,
+    Manifest,
+    Current,
+}
+
+pub(crate) fn log_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.log", number))
+}
+
+pub(crate) fn table_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.sst", number))
+}
+
+/// temp_file_name is where a table is built before it's renamed into place.
+pub(crate) fn temp_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.dbtmp", number))
+}
+
+pub(crate) fn manifest_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("MANIFEST-{:06}", number))
+}
+
+/// current_file_name names the file holding the name of the manifest in use.
+pub(crate) fn current_file_name(dir: &Path) -> PathBuf {
+    dir.join("CURRENT")
+}
+
+pub(crate) fn lock_file_name(dir: &Path) -> PathBuf {
+    dir.join("LOCK")
+}
+
+/// parse_file_name extracts the file number and type from a database file name such as
+/// `000012.log`. `CURRENT` has number 0, and files that weren't created by the database return
+/// `None`.
+pub(crate) fn parse_file_name(name: &str) -> Option<(u64, FileType)> {
+    if name == "CURRENT" {
+        return Some((0, FileType::Current));
+    }
+    if let Some(number) = name.strip_prefix("MANIFEST-") {
+        return Some((number.parse::<u64>().ok()?, FileType::Manifest));
+    }
+    let (number, extension) = name.split_once('.')?;
+    let number = number.parse::<u64>().ok()?;
+    let typ = match extension {
+        "log" => FileType::Log,
+        "sst" => FileType::Table,
+        "dbtmp" => FileType::Temp,
+        _ => return None,
+    };
+    Some((number, typ))
+}
+
+pub(crate) fn create_file(path: &Path) -> crate::Result<File> {
+    Ok(OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?)
+}
+
+/// sync_dir makes the creation, rename and removal of files in `dir` durable.
+pub(crate) fn sync_dir(dir: &Path) -> crate::Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::filename::{
+        current_file_name, log_file_name, manifest_file_name, parse_file_name, table_file_name,
+        temp_file_name, FileType,
+    };
+
+    #[test]
+    fn test_file_names() {
+        let dir = Path::new("db");
+        assert_eq!(log_file_name(dir, 7), Path::new("db/000007.log"));
+        assert_eq!(table_file_name(dir, 1234567), Path::new("db/1234567.sst"));
+        assert_eq!(parse_file_name("000007.log"), Some((7, FileType::Log)));
+        assert_eq!(
+            parse_file_name("1234567.sst"),
+            Some((1234567, FileType::Table))
+        );
+        assert_eq!(
+            parse_file_name(
+                temp_file_name(dir, 8)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            ),
+            Some((8, FileType::Temp))
+        );
+        assert_eq!(manifest_file_name(dir, 3), Path::new("db/MANIFEST-000003"));
+        assert_eq!(
+            parse_file_name("MANIFEST-000003"),
+            Some((3, FileType::Manifest))
+        );
+        assert_eq!(parse_file_name("MANIFEST-"), None);
+        assert_eq!(current_file_name(dir), Path::new("db/CURRENT"));
+        assert_eq!(parse_file_name("CURRENT"), Some((0, FileType::Current)));
+        assert_eq!(parse_file_name("LOCK"), None);
+        assert_eq!(parse_file_name("abc.log"), None);
+        assert_eq!(parse_file_name("000007.tmp"), None);
+    }
+}
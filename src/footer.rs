@@ -0,0 +1,197 @@
+use std::ops::Range;
+
+use crate::codec::{decode_fixed32, decode_fixed64};
+use crate::table::{LATEST_FORMAT_VERSION, TABLE_MAGIC};
+use crate::{ensure, Error};
+
+/// FOOTER_SIZE is the size of the footer tables of format version 3 and later end with.
+pub(crate) const FOOTER_SIZE: usize = 4 * 8 + 4 + 4 + 8;
+/// FOOTER_FORMAT_VERSION is the first format version whose tables end with a footer.
+pub(crate) const FOOTER_FORMAT_VERSION: u32 = 3;
+pub(crate) const BLOCK_HANDLE_SIZE: usize = 16;
+
+fn bad_footer(msg: &str) -> Error {
+    Error::Corruption(format!("bad table footer: {}", msg))
+}
+
+/// BlockHandle locates a block within a table file.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) struct BlockHandle {
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+impl BlockHandle {
+    pub(crate) fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.offset.to_le_bytes());
+        dst.extend_from_slice(&self.size.to_le_bytes());
+    }
+
+    pub(crate) fn decode(src: &[u8]) -> crate::Result<Self> {
+        ensure!(
+            src.len() == BLOCK_HANDLE_SIZE,
+            Error::Corruption("bad block handle".to_string())
+        );
+        Ok(Self {
+            offset: decode_fixed64(&src[..8]),
+            size: decode_fixed64(&src[8..]),
+        })
+    }
+
+    /// range returns the bytes the block takes in a file of `len` bytes, or an error if it
+    /// doesn't fit.
+    pub(crate) fn range(&self, len: usize) -> crate::Result<Range<usize>> {
+        let end = self.offset.checked_add(self.size);
+        ensure!(
+            end.is_some_and(|end| end <= len as u64),
+            Error::Corruption(format!(
+                "block at {} of {} bytes runs past the end of the table",
+                self.offset, self.size
+            ))
+        );
+        Ok(self.offset as usize..(self.offset + self.size) as usize)
+    }
+}
+
+/// Footer is the fixed-size end of a table, which is read first to find everything else:
+///
+/// ```text
+/// metaindex | index | index crc: u32 | format_version: u32 | magic: u64
+/// ```
+///
+/// where both handles are encoded as `offset: u64 | size: u64`. The metaindex is a block
+/// mapping the names of meta blocks to their handles. Tables of earlier formats end with
+/// `index length: u32 | index crc: u32`, followed by the format version and the magic from
+/// version 1 on, and have no metaindex.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Footer {
+    pub(crate) metaindex: Option<BlockHandle>,
+    pub(crate) index: BlockHandle,
+    pub(crate) index_checksum: u32,
+    pub(crate) format_version: u32,
+}
+
+impl Footer {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(FOOTER_SIZE);
+        self.metaindex.unwrap_or_default().encode_to(&mut dst);
+        self.index.encode_to(&mut dst);
+        dst.extend_from_slice(&self.index_checksum.to_le_bytes());
+        dst.extend_from_slice(&self.format_version.to_le_bytes());
+        dst.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+        dst
+    }
+
+    /// decode reads the footer at the end of `file` and checks the blocks it locates lie
+    /// within the file.
+    pub(crate) fn decode(file: &[u8]) -> crate::Result<Self> {
+        let mut end = file.len();
+        let mut format_version = 0;
+        if end >= 12 && decode_fixed64(&file[end - 8..]) == TABLE_MAGIC {
+            format_version = decode_fixed32(&file[end - 12..end - 8]);
+            ensure!(
+                format_version > 0 && format_version <= LATEST_FORMAT_VERSION,
+                Error::Corruption(format!("unknown table format version {}", format_version))
+            );
+            end -= 12;
+        }
+        if format_version >= FOOTER_FORMAT_VERSION {
+            ensure!(file.len() >= FOOTER_SIZE, bad_footer("truncated"));
+            let footer = &file[file.len() - FOOTER_SIZE..];
+            let metaindex = BlockHandle::decode(&footer[..BLOCK_HANDLE_SIZE])?;
+            let index = BlockHandle::decode(&footer[BLOCK_HANDLE_SIZE..2 * BLOCK_HANDLE_SIZE])?;
+            let body_len = file.len() - FOOTER_SIZE;
+            metaindex.range(body_len)?;
+            index.range(body_len)?;
+            return Ok(Self {
+                metaindex: Some(metaindex),
+                index,
+                index_checksum: decode_fixed32(&footer[2 * BLOCK_HANDLE_SIZE..]),
+                format_version,
+            });
+        }
+
+        ensure!(end >= 8, bad_footer("truncated"));
+        let index_len = decode_fixed32(&file[end - 8..end - 4]) as usize;
+        ensure!(
+            index_len <= end - 8,
+            bad_footer("index length out of range")
+        );
+        Ok(Self {
+            metaindex: None,
+            index: BlockHandle {
+                offset: (end - 8 - index_len) as u64,
+                size: index_len as u64,
+            },
+            index_checksum: decode_fixed32(&file[end - 4..end]),
+            format_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::footer::{BlockHandle, Footer, FOOTER_SIZE};
+    use crate::table::LATEST_FORMAT_VERSION;
+    use crate::Error;
+
+    #[test]
+    fn test_footer() {
+        let footer = Footer {
+            metaindex: Some(BlockHandle {
+                offset: 100,
+                size: 20,
+            }),
+            index: BlockHandle {
+                offset: 128,
+                size: 72,
+            },
+            index_checksum: 0xdead_beef,
+            format_version: LATEST_FORMAT_VERSION,
+        };
+        let mut file = vec![0; 200];
+        file.extend_from_slice(&footer.encode());
+        assert_eq!(file.len(), 200 + FOOTER_SIZE);
+        assert_eq!(Footer::decode(&file).unwrap(), footer);
+
+        // handles past the end of the file
+        let mut file = vec![0; 199];
+        file.extend_from_slice(&footer.encode());
+        assert!(matches!(Footer::decode(&file), Err(Error::Corruption(_))));
+
+        // a newer format, a truncated footer and a file too short to have one
+        let mut file = vec![0; 200];
+        let mut newer = footer.clone();
+        newer.format_version += 1;
+        file.extend_from_slice(&newer.encode());
+        assert!(matches!(Footer::decode(&file), Err(Error::Corruption(_))));
+        let encoded = footer.encode();
+        assert!(matches!(
+            Footer::decode(&encoded[4..]),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(Footer::decode(b"abc"), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_legacy_footer() {
+        // version 0: the index, its length and checksum
+        let mut file = vec![0; 10];
+        file.extend_from_slice(&[1; 30]);
+        file.extend_from_slice(&30_u32.to_le_bytes());
+        file.extend_from_slice(&7_u32.to_le_bytes());
+        let footer = Footer::decode(&file).unwrap();
+        assert_eq!(footer.metaindex, None);
+        assert_eq!(
+            footer.index,
+            BlockHandle {
+                offset: 10,
+                size: 30
+            }
+        );
+        assert_eq!((footer.index_checksum, footer.format_version), (7, 0));
+
+        file[40] = 41;
+        assert!(matches!(Footer::decode(&file), Err(Error::Corruption(_))));
+    }
+}
@@ -1,3 +1,8 @@
+use std::cmp::Ordering;
+
+use crate::cmp::Comparator;
+use crate::types::{internal_key, ValueType, MAX_SEQUENCE};
+
 pub trait Iter {
     type Item;
     fn valid(&self) -> bool;
@@ -8,3 +13,73 @@ pub trait Iter {
     fn seek_to_first(&mut self);
     fn seek_to_last(&mut self);
 }
+
+/// InternalIterator walks entries by internal key, a user key followed by its tag, in
+/// `InternalKeyComparator` order, and hands out the current entry in place. It's what the
+/// memtables and tables are merged through to iterate the database.
+pub(crate) trait InternalIterator {
+    fn valid(&self) -> bool;
+    /// key is the internal key of the current entry. Only valid iterators have one.
+    fn key(&self) -> &[u8];
+    fn value(&self) -> &[u8];
+    fn next(&mut self);
+    fn prev(&mut self);
+    /// seek moves to the first entry at or after the internal key `target`.
+    fn seek(&mut self, target: &[u8]);
+    fn seek_to_first(&mut self);
+    fn seek_to_last(&mut self);
+    /// status returns the error that made the iterator invalid, if any.
+    fn status(&mut self) -> crate::Result<()>;
+}
+
+/// IterBounds keeps an iterator to the keys in `lower..upper`, either of which may be open.
+/// The bounds are keys in the format of the keys iterated.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IterBounds {
+    pub(crate) lower: Option<Vec<u8>>,
+    pub(crate) upper: Option<Vec<u8>>,
+}
+
+impl IterBounds {
+    /// for_user_keys bounds internal keys to the user keys in `lower..upper`. A bound is the
+    /// newest internal key of its user key, which sorts before every other one.
+    pub(crate) fn for_user_keys(lower: Option<&[u8]>, upper: Option<&[u8]>) -> Self {
+        let bound = |key: &[u8]| internal_key(key, MAX_SEQUENCE, ValueType::Value);
+        Self {
+            lower: lower.map(bound),
+            upper: upper.map(bound),
+        }
+    }
+
+    /// before_lower reports whether `key` sorts before the lower bound.
+    pub(crate) fn before_lower(&self, cmp: &dyn Comparator, key: &[u8]) -> crate::Result<bool> {
+        match &self.lower {
+            Some(lower) => Ok(cmp.cmp(key, lower)? == Ordering::Less),
+            None => Ok(false),
+        }
+    }
+
+    /// past_upper reports whether `key` sorts at or after the upper bound.
+    pub(crate) fn past_upper(&self, cmp: &dyn Comparator, key: &[u8]) -> crate::Result<bool> {
+        match &self.upper {
+            Some(upper) => Ok(cmp.cmp(key, upper)? != Ordering::Less),
+            None => Ok(false),
+        }
+    }
+
+    pub(crate) fn contains(&self, cmp: &dyn Comparator, key: &[u8]) -> crate::Result<bool> {
+        Ok(!self.before_lower(cmp, key)? && !self.past_upper(cmp, key)?)
+    }
+
+    /// clamp returns where a seek to `target` starts, which is no earlier than the lower bound.
+    pub(crate) fn clamp<'a>(
+        &'a self,
+        cmp: &dyn Comparator,
+        target: &'a [u8],
+    ) -> crate::Result<&'a [u8]> {
+        match &self.lower {
+            Some(lower) if self.before_lower(cmp, target)? => Ok(lower),
+            _ => Ok(target),
+        }
+    }
+}
@@ -1,34 +1,45 @@
-#![feature(slice_pattern)]
-#![feature(generic_associated_types)]
-#![feature(map_first_last)]
-#![feature(default_free_fn)]
-#![feature(build_hasher_simple_hash_one)]
-#![feature(let_else)]
-
-extern crate core;
-extern crate core;
-
-use crate::error::Error;
+pub use crate::block_cache::BlockCacheStats;
+pub use crate::db::{Snapshot, DB};
+pub use crate::db_iter::DBIterator;
+pub use crate::error::Error;
+pub use crate::options::{
+    CompactionStyle, Options, ReadOptions, WalRecoveryMode, WalSyncMode, WriteOptions,
+};
+pub use crate::table::{CompressionType, FilterMode, TableOptions};
+pub use crate::table_properties::TableProperties;
+pub use crate::write_batch::WriteBatch;
 
 mod block;
+mod block_cache;
 mod bloom;
 mod cmp;
 mod codec;
+mod compaction;
+mod compress;
 mod constant;
+mod db;
+mod db_iter;
 mod error;
+mod filename;
+mod footer;
 mod iter;
 mod log;
 mod memtable;
-mod memtable_cache;
-mod metadata;
+mod merging_iterator;
+mod options;
 mod skiplist;
 mod table;
 mod table_builder;
+mod table_cache;
+mod table_filter;
 mod table_index;
+mod table_properties;
 mod types;
+mod version_edit;
+mod version_set;
 mod env;
 mod disk_env;
-mod chunk;
+mod write_batch;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
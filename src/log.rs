@@ -1,17 +1,39 @@
 use std::hash::Hasher;
-use std::io::{BufReader, Read, Write};
+use std::io::{BufReader, ErrorKind, Read, Write};
 
+use crate::codec::decode_fixed32;
 use crate::Error;
 
 const BLOCK_SIZE: usize = 32 * 1024;
 const HEADER_SIZE: usize = 4 + 2 + 1;
+// the header of a recyclable record also holds the low 32 bits of the log number
+const RECYCLABLE_HEADER_SIZE: usize = HEADER_SIZE + 4;
 
+/// RecordType tells where a fragment belongs in its record. The recyclable types are stamped
+/// with the number of the log they were written to, so a log can be written over an old one
+/// and the records left over from the file's previous use are told apart when it's read.
 #[derive(Clone, Copy)]
 pub enum RecordType {
     Full = 1,
     First = 2,
     Middle = 3,
     Last = 4,
+    RecyclableFull = 5,
+    RecyclableFirst = 6,
+    RecyclableMiddle = 7,
+    RecyclableLast = 8,
+}
+
+impl RecordType {
+    fn recyclable(self) -> RecordType {
+        match self {
+            RecordType::Full => RecordType::RecyclableFull,
+            RecordType::First => RecordType::RecyclableFirst,
+            RecordType::Middle => RecordType::RecyclableMiddle,
+            RecordType::Last => RecordType::RecyclableLast,
+            t => t,
+        }
+    }
 }
 
 pub struct LogWriter<W: Write> {
@@ -19,6 +41,8 @@ pub struct LogWriter<W: Write> {
     digest: crc32fast::Hasher,
     current_block_offset: usize,
     block_size: usize,
+    // stamped into every record when the log is written in the recyclable format
+    log_number: Option<u32>,
 }
 
 impl<W: Write> LogWriter<W> {
@@ -29,6 +53,22 @@ impl<W: Write> LogWriter<W> {
             current_block_offset: 0,
             block_size: BLOCK_SIZE,
             digest,
+            log_number: None,
+        }
+    }
+
+    /// new_recyclable opens a writer for log `log_number` that writes the recyclable record
+    /// types. It may write over an old log file from its start.
+    pub(crate) fn new_recyclable(writer: W, log_number: u64) -> LogWriter<W> {
+        let mut w = LogWriter::new(writer);
+        w.log_number = Some(log_number as u32);
+        w
+    }
+
+    fn header_size(&self) -> usize {
+        match self.log_number {
+            Some(_) => RECYCLABLE_HEADER_SIZE,
+            None => HEADER_SIZE,
         }
     }
 
@@ -41,21 +81,22 @@ impl<W: Write> LogWriter<W> {
     }
 
     pub(crate) fn add_record(&mut self, r: &[u8]) -> crate::Result<usize> {
-        let mut record = &r[..];
+        let mut record = r;
         let mut first_frag = true;
         let mut result = Ok(0);
+        let header_size = self.header_size();
         while result.is_ok() && !record.is_empty() {
-            assert!(self.block_size > HEADER_SIZE);
+            assert!(self.block_size > header_size);
 
             let space_left = self.block_size - self.current_block_offset;
 
             // Fill up block; go to next block.
-            if space_left < HEADER_SIZE {
-                self.dst.write_all(&vec![0, 0, 0, 0, 0, 0][0..space_left])?;
+            if space_left < header_size {
+                self.dst.write_all(&[0; RECYCLABLE_HEADER_SIZE][0..space_left])?;
                 self.current_block_offset = 0;
             }
 
-            let avail_for_data = self.block_size - self.current_block_offset - HEADER_SIZE;
+            let avail_for_data = self.block_size - self.current_block_offset - header_size;
 
             let data_frag_len = if record.len() < avail_for_data {
                 record.len()
@@ -63,7 +104,7 @@ impl<W: Write> LogWriter<W> {
                 avail_for_data
             };
 
-            let recordtype;
+            let mut recordtype;
 
             if first_frag && data_frag_len == record.len() {
                 recordtype = RecordType::Full;
@@ -75,6 +116,9 @@ impl<W: Write> LogWriter<W> {
                 recordtype = RecordType::Middle;
             }
 
+            if self.log_number.is_some() {
+                recordtype = recordtype.recyclable();
+            }
             result = self.emit_record(recordtype, record, data_frag_len);
             record = &record[data_frag_len..];
             first_frag = false;
@@ -88,6 +132,9 @@ impl<W: Write> LogWriter<W> {
         self.digest.reset();
         let mut digest = self.digest.clone();
         digest.write(&[t as u8]);
+        if let Some(number) = self.log_number {
+            digest.write(&number.to_le_bytes());
+        }
         digest.write(&data[0..len]);
 
         let chksum = mask_crc(digest.finalize());
@@ -96,12 +143,19 @@ impl<W: Write> LogWriter<W> {
         s += self.dst.write(&chksum.to_le_bytes())?;
         s += self.dst.write(&(len as u16).to_le_bytes())?;
         s += self.dst.write(&[t as u8])?;
+        if let Some(number) = self.log_number {
+            s += self.dst.write(&number.to_le_bytes())?;
+        }
         s += self.dst.write(&data[0..len])?;
 
         self.current_block_offset += s;
         Ok(s)
     }
 
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.dst
+    }
+
     pub(crate) fn flush(&mut self) -> crate::Result<()> {
         self.dst.flush()?;
         Ok(())
@@ -114,8 +168,14 @@ pub(crate) struct LogReader<R: Read> {
     digest: crc32fast::Hasher,
     blk_off: usize,
     blocksize: usize,
-    head_scratch: [u8; 7],
+    head_scratch: [u8; RECYCLABLE_HEADER_SIZE],
     checksums: bool,
+    offset: usize,
+    record_end: usize,
+    // the number of the log being read, which recyclable records must be stamped with
+    log_number: Option<u32>,
+    // whether a recyclable record was seen, so the log may have been written over an old one
+    recycled: bool,
 }
 
 impl<R: Read> LogReader<R> {
@@ -125,74 +185,206 @@ impl<R: Read> LogReader<R> {
             blk_off: 0,
             blocksize: BLOCK_SIZE,
             checksums: chksum,
-            head_scratch: [0; 7],
+            head_scratch: [0; RECYCLABLE_HEADER_SIZE],
             digest: crc32fast::Hasher::new_with_initial(0xffffffff),
+            offset: 0,
+            record_end: 0,
+            log_number: None,
+            recycled: false,
         }
     }
 
-    /// EOF is signalled by Ok(0)
+    /// new_with_log_number opens a reader for log `log_number`, which may have been written
+    /// over an old log file. Recyclable records stamped with another number are left over from
+    /// the file's previous use and end the log.
+    pub(crate) fn new_with_log_number(src: R, chksum: bool, log_number: u64) -> LogReader<R> {
+        let mut r = LogReader::new(src, chksum);
+        r.log_number = Some(log_number as u32);
+        r
+    }
+
+    /// EOF is signalled by Ok(0). A record cut short by the end of the log, as left behind by a
+    /// crash in the middle of a write, is reported as `Error::Corruption`.
+    ///
+    /// Once a log turns out to be in the recyclable format, a fragment that fails to read ends
+    /// the log rather than being reported, as it can't be told apart from the bytes left over
+    /// from the file's previous use. A record cut short that way is still reported.
     pub(crate) fn read(&mut self, dst: &mut Vec<u8>) -> Result<usize, Error> {
         let mut checksum: u32;
         let mut length: u16;
         let mut typ: u8;
         let mut dst_offset: usize = 0;
+        let mut in_fragmented_record = false;
 
         dst.clear();
 
         loop {
             if self.blocksize - self.blk_off < HEADER_SIZE {
                 // skip to next block
-                self.src
-                    .read_exact(&mut self.head_scratch[0..self.blocksize - self.blk_off])?;
+                let trailer = self.blocksize - self.blk_off;
+                let bytes_read = read_full(&mut self.src, &mut self.head_scratch[0..trailer])?;
+                self.offset += bytes_read;
+                if bytes_read < trailer {
+                    return truncated_or_eof(in_fragmented_record);
+                }
                 self.blk_off = 0;
             }
 
-            let mut bytes_read = self.src.read(&mut self.head_scratch)?;
+            let mut bytes_read = read_full(&mut self.src, &mut self.head_scratch[..HEADER_SIZE])?;
+            self.offset += bytes_read;
+            self.blk_off += bytes_read;
 
             // EOF
             if bytes_read == 0 {
-                return Ok(0);
+                return truncated_or_eof(in_fragmented_record);
+            }
+            if bytes_read < HEADER_SIZE {
+                return truncated_or_eof(true);
             }
-
-            self.blk_off += bytes_read;
 
             checksum = unsafe { (self.head_scratch[0..4].as_ptr() as *const u32).read_unaligned() };
             length = unsafe { (self.head_scratch[4..6].as_ptr() as *const u16).read_unaligned() };
             typ = self.head_scratch[6];
 
+            // the zeros a recyclable log fills the end of a block with when no header fits
+            if self.blocksize - self.blk_off < RECYCLABLE_HEADER_SIZE - HEADER_SIZE
+                && self.head_scratch[..HEADER_SIZE] == [0; HEADER_SIZE]
+            {
+                let trailer = self.blocksize - self.blk_off;
+                let bytes_read = read_full(&mut self.src, &mut self.head_scratch[..trailer])?;
+                self.offset += bytes_read;
+                self.blk_off = 0;
+                continue;
+            }
+
+            let mut header_size = HEADER_SIZE;
+            if (RecordType::RecyclableFull as u8..=RecordType::RecyclableLast as u8).contains(&typ)
+            {
+                self.recycled = true;
+                bytes_read = read_full(
+                    &mut self.src,
+                    &mut self.head_scratch[HEADER_SIZE..RECYCLABLE_HEADER_SIZE],
+                )?;
+                self.offset += bytes_read;
+                self.blk_off += bytes_read;
+                if bytes_read < RECYCLABLE_HEADER_SIZE - HEADER_SIZE {
+                    return truncated_or_eof(true);
+                }
+                header_size = RECYCLABLE_HEADER_SIZE;
+                let number = decode_fixed32(&self.head_scratch[HEADER_SIZE..]);
+                if self.log_number.is_some_and(|n| n != number) {
+                    // left over from the file's previous use
+                    return truncated_or_eof(in_fragmented_record);
+                }
+                typ -= RecordType::RecyclableFull as u8 - RecordType::Full as u8;
+            }
+
+            if self.blk_off + length as usize > self.blocksize {
+                return self.bad_record(
+                    in_fragmented_record,
+                    Error::Corruption("bad record length".into()),
+                );
+            }
+
             dst.resize(dst_offset + length as usize, 0);
-            bytes_read = self
-                .src
-                .read(&mut dst[dst_offset..dst_offset + length as usize])?;
+            bytes_read = read_full(
+                &mut self.src,
+                &mut dst[dst_offset..dst_offset + length as usize],
+            )?;
+            self.offset += bytes_read;
             self.blk_off += bytes_read;
+            if bytes_read < length as usize {
+                if self.recycled {
+                    return truncated_or_eof(in_fragmented_record);
+                }
+                return truncated_or_eof(true);
+            }
 
+            let head = self.head_scratch;
             if self.checksums
-                && !self.check_integrity(typ, &dst[dst_offset..dst_offset + bytes_read], checksum)
+                && !self.check_integrity(
+                    &head[6..header_size],
+                    &dst[dst_offset..dst_offset + bytes_read],
+                    checksum,
+                )
             {
-                return Err(Error::Corruption("Invalid Checksum".into()));
+                return self.bad_record(
+                    in_fragmented_record,
+                    Error::Corruption("Invalid Checksum".into()),
+                );
             }
 
             dst_offset += length as usize;
 
-            if typ == RecordType::Full as u8 {
+            if typ == RecordType::Full as u8 || typ == RecordType::Last as u8 {
+                self.record_end = self.offset;
                 return Ok(dst_offset);
             } else if typ == RecordType::First as u8 || typ == RecordType::Middle as u8 {
+                in_fragmented_record = true;
                 continue;
-            } else if typ == RecordType::Last as u8 {
-                return Ok(dst_offset);
+            } else {
+                return self.bad_record(
+                    in_fragmented_record,
+                    Error::Corruption(format!("unknown record type {}", typ)),
+                );
             }
         }
     }
 
-    pub(crate) fn check_integrity(&mut self, typ: u8, data: &[u8], expected: u32) -> bool {
+    /// bad_record reports a fragment that failed to read, unless the log may have been written
+    /// over an old one, in which case the fragment ends the log.
+    fn bad_record(&self, in_record: bool, err: Error) -> crate::Result<usize> {
+        if self.recycled {
+            return truncated_or_eof(in_record);
+        }
+        Err(err)
+    }
+
+    /// record_end returns the offset just past the last complete record returned by `read`;
+    /// everything before it is intact.
+    pub(crate) fn record_end(&self) -> usize {
+        self.record_end
+    }
+
+    /// recycled reports whether a recyclable record was read, so the log may go on past its
+    /// last record with bytes left over from an old log.
+    pub(crate) fn recycled(&self) -> bool {
+        self.recycled
+    }
+
+    /// check_integrity checks the checksum of a fragment, which covers its header from the record
+    /// type on and its data.
+    pub(crate) fn check_integrity(&mut self, head: &[u8], data: &[u8], expected: u32) -> bool {
         self.digest.reset();
         let mut digest = self.digest.clone();
-        digest.write(&[typ]);
+        digest.write(head);
         digest.write(data);
         unmask_crc(expected) == digest.finalize()
     }
 }
 
+/// read_full fills `buf` from `src` unless the end of the input comes first, returning the
+/// number of bytes read.
+fn read_full<R: Read>(src: &mut R, buf: &mut [u8]) -> crate::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match src.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(read)
+}
+
+fn truncated_or_eof(in_record: bool) -> crate::Result<usize> {
+    if in_record {
+        return Err(Error::Corruption("truncated record".into()));
+    }
+    Ok(0)
+}
+
 const MASK_DELTA: u32 = 0xa282ead8;
 
 pub(crate) fn mask_crc(c: u32) -> u32 {
@@ -220,8 +412,8 @@ mod tests {
 
     #[test]
     fn test_crc_sanity() {
-        assert_eq!(420107693, crc32fast::hash(&[0 as u8; 32]));
-        assert_eq!(4285311755, crc32fast::hash(&[0xff as u8; 32]));
+        assert_eq!(420107693, crc32fast::hash(&[0_u8; 32]));
+        assert_eq!(4285311755, crc32fast::hash(&[0xff_u8; 32]));
     }
 
     #[test]
@@ -249,8 +441,7 @@ mod tests {
             "and my third",
         ];
 
-        let mut dst = Vec::new();
-        dst.resize(1024, 0 as u8);
+        let mut dst = vec![0; 1024];
 
         {
             let mut lw = LogWriter::new(Cursor::new(dst.as_mut_slice()));
@@ -276,7 +467,7 @@ mod tests {
 
     #[test]
     fn test_reader() {
-        let data = vec![
+        let data = [
             "abcdefghi".as_bytes().to_vec(),    // fits one block of 17
             "123456789012".as_bytes().to_vec(), // spans two blocks of 17
             "0101010101010101010101".as_bytes().to_vec(),
@@ -306,10 +497,10 @@ mod tests {
         loop {
             let r = lr.read(&mut dst);
 
-            if !r.is_ok() {
-                panic!("{}", r.unwrap_err());
-            } else if r.unwrap() == 0 {
-                break;
+            match r {
+                Err(err) => panic!("{}", err),
+                Ok(0) => break,
+                Ok(_) => {}
             }
 
             assert_eq!(dst, data[i]);
@@ -317,4 +508,129 @@ mod tests {
         }
         assert_eq!(i, data.len());
     }
+
+    #[test]
+    fn test_reader_truncated_tail() {
+        let data = [
+            "hello world. My first log entry.".as_bytes().to_vec(),
+            "and my second".as_bytes().to_vec(),
+        ];
+        let mut lw = LogWriter::new(Vec::new());
+        for e in data.iter() {
+            assert!(lw.add_record(e).is_ok());
+        }
+        let first_end = data[0].len() + super::HEADER_SIZE;
+
+        for cut in [1, data[1].len(), data[1].len() + 3] {
+            let log = &lw.dst[..lw.dst.len() - cut];
+            let mut lr = LogReader::new(log, true);
+            let mut dst = Vec::new();
+            assert_eq!(lr.read(&mut dst), Ok(data[0].len()));
+            assert_eq!(dst, data[0]);
+            assert_eq!(
+                lr.read(&mut dst),
+                Err(Error::Corruption("truncated record".into()))
+            );
+            assert_eq!(lr.record_end(), first_end);
+        }
+
+        let mut lr = LogReader::new(lw.dst.as_slice(), true);
+        let mut dst = Vec::new();
+        assert_eq!(lr.read(&mut dst), Ok(data[0].len()));
+        assert_eq!(lr.read(&mut dst), Ok(data[1].len()));
+        assert_eq!(lr.read(&mut dst), Ok(0));
+        assert_eq!(lr.record_end(), lw.dst.len());
+    }
+
+    #[test]
+    fn test_reader_spanning_blocks() {
+        let data: Vec<Vec<u8>> = (0..100_u8).map(|i| vec![i; 1000 + i as usize]).collect();
+        let mut lw = LogWriter::new(Vec::new());
+        for e in data.iter() {
+            assert!(lw.add_record(e).is_ok());
+        }
+
+        let mut lr = LogReader::new(lw.dst.as_slice(), true);
+        let mut dst = Vec::new();
+        for e in data.iter() {
+            assert_eq!(lr.read(&mut dst), Ok(e.len()));
+            assert_eq!(&dst, e);
+        }
+        assert_eq!(lr.read(&mut dst), Ok(0));
+    }
+
+    fn recyclable_log(records: &[Vec<u8>], log_number: u64, dst: &mut [u8]) {
+        let mut lw = LogWriter::new_recyclable(Cursor::new(dst), log_number);
+        lw.block_size = 1000;
+        for e in records {
+            assert!(lw.add_record(e).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_recycled_log() {
+        let old: Vec<Vec<u8>> = (0..20_u8).map(|i| vec![i; 100 + 50 * i as usize]).collect();
+        let mut old_log = vec![0; 16 << 10];
+        recyclable_log(&old, 3, &mut old_log);
+        let mut lr = LogReader::new_with_log_number(old_log.as_slice(), true, 3);
+        lr.blocksize = 1000;
+        let mut record = Vec::new();
+        for e in old.iter() {
+            assert_eq!(lr.read(&mut record), Ok(e.len()));
+            assert_eq!(&record, e);
+        }
+
+        // log 7 written over log 3 from its start
+        let new: Vec<Vec<u8>> = (0..5_u8).map(|i| vec![100 + i; 333]).collect();
+        let mut log = old_log.clone();
+        recyclable_log(&new, 7, &mut log);
+        let mut lr = LogReader::new_with_log_number(log.as_slice(), true, 7);
+        lr.blocksize = 1000;
+        for e in new.iter() {
+            assert_eq!(lr.read(&mut record), Ok(e.len()));
+            assert_eq!(&record, e);
+        }
+        assert_eq!(lr.read(&mut record), Ok(0));
+
+        // a record whose last fragment never made it over the old log is still reported
+        log[1000..].copy_from_slice(&old_log[1000..]);
+        let mut lr = LogReader::new_with_log_number(log.as_slice(), true, 7);
+        lr.blocksize = 1000;
+        assert_eq!(lr.read(&mut record), Ok(333));
+        assert_eq!(lr.read(&mut record), Ok(333));
+        assert_eq!(
+            lr.read(&mut record),
+            Err(Error::Corruption("truncated record".into()))
+        );
+
+        // damage in a recycled log ends it
+        let mut log = old_log.clone();
+        recyclable_log(&new, 7, &mut log);
+        log[400] ^= 1;
+        let mut lr = LogReader::new_with_log_number(log.as_slice(), true, 7);
+        lr.blocksize = 1000;
+        assert_eq!(lr.read(&mut record), Ok(333));
+        assert_eq!(lr.read(&mut record), Ok(0));
+    }
+
+    #[test]
+    fn test_recyclable_block_trailer() {
+        // each record leaves 8 bytes at the end of its block, too few for a recyclable header
+        let data: Vec<Vec<u8>> = (0..4_u8).map(|i| vec![i; 100 - 8 - 11]).collect();
+        let mut lw = LogWriter::new_recyclable(Vec::new(), 1);
+        lw.block_size = 100;
+        for e in data.iter() {
+            assert!(lw.add_record(e).is_ok());
+        }
+        assert_eq!(lw.dst.len(), 3 * 100 + 92);
+
+        let mut lr = LogReader::new_with_log_number(lw.dst.as_slice(), true, 1);
+        lr.blocksize = 100;
+        let mut record = Vec::new();
+        for e in data.iter() {
+            assert_eq!(lr.read(&mut record), Ok(e.len()));
+            assert_eq!(&record, e);
+        }
+        assert_eq!(lr.read(&mut record), Ok(0));
+    }
 }
@@ -1,22 +1,21 @@
-use std::cmp::Ordering;
+use std::ops::Range;
 use std::sync::Arc;
 
-use crate::cmp::{Comparator, DefaultComparator};
+use crate::cmp::{InternalKeyComparator, MemTableComparator};
 use crate::codec::Codec;
+use crate::iter::{InternalIterator, Iter, IterBounds};
 use crate::skiplist::SkipList;
-use crate::types::{MemEntry, ValueType};
+use crate::types::{parse_internal_key, split_mem_entry, MemEntry, ValueType};
+use crate::Error;
 
 pub(crate) struct MemTable {
-    cmp: Arc<Box<dyn Comparator>>,
     table: SkipList,
 }
 
 impl MemTable {
     pub(crate) fn new() -> Self {
-        let cmp = Arc::new(Box::new(DefaultComparator));
         Self {
-            cmp: cmp.clone(),
-            table: SkipList::new(cmp),
+            table: SkipList::new(Arc::new(Box::new(MemTableComparator))),
         }
     }
     pub(crate) fn add(
@@ -30,33 +29,226 @@ impl MemTable {
         self.table.insert(&entry)
     }
 
-    pub(crate) fn add_raw(
+    /// Returns the newest entry recorded for `key` up to `sequence`, which may be a deletion
+    /// marker, or `None` if the memtable holds no such version of the key.
+    pub(crate) fn get(
         &self,
-        raw_entry: &[u8],
-    ) -> crate::Result<()> {
-        self.table.insert(raw_entry)
+        key: &[u8],
+        sequence: u64,
+    ) -> crate::Result<Option<(ValueType, &[u8])>> {
+        // the version wanted is the first entry at or after the newest one it could be
+        let lookup = MemEntry::new(sequence, ValueType::Value, key, &[]).encode()?;
+        let mut iter = self.table.iter();
+        iter.seek(&lookup);
+        match iter.current() {
+            Some(raw_entry) => {
+                let entry = MemEntry::decode_from_slice(raw_entry)?;
+                Ok((entry.key() == key).then(|| (entry.value_type(), entry.value())))
+            }
+            None => Ok(None),
+        }
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> crate::Result<Option<&[u8]>> {
+    /// iter walks the encoded entries of the memtable by user key, newest version first.
+    pub(crate) fn iter(&self) -> Box<dyn Iter<Item = &[u8]> + '_> {
         let mut iter = self.table.iter();
-        iter.seek(key);
-
-        if !iter.valid() {
-            return Ok(None);
-        }
-        if let Some(raw_memkey) = iter.current() {
-            let memkey = MemEntry::decode_from_slice(raw_memkey)?;
-            if memkey.key().cmp(key) == Ordering::Equal {
-                return match memkey.value_type() {
-                    ValueType::Deletion => Ok(None),
-                    ValueType::Value => Ok(Some(memkey.value())),
-                };
-            }
+        iter.seek_to_first();
+        iter
+    }
+
+    /// internal_iter walks the entries of the memtable within `bounds` by internal key. It
+    /// holds on to the entries, so it may outlive the memtable.
+    pub(crate) fn internal_iter(&self, bounds: Arc<IterBounds>) -> MemTableIterator {
+        MemTableIterator {
+            iter: self.table.iter(),
+            key: 0..0,
+            value: 0..0,
+            bounds,
+            exhausted: false,
+            error: None,
         }
-        return Ok(None);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub(crate) fn approximate_memory_usage(&self) -> usize {
         self.table.allocated_bytes()
     }
 }
+
+/// MemTableIterator is the `InternalIterator` of a memtable. The memtable stores encoded
+/// entries, which hold the internal key and the value next to each other.
+pub(crate) struct MemTableIterator {
+    // the skiplist iterator owns the list, so its entries live as long as it does; they're
+    // only handed out borrowed from the iterator
+    iter: Box<dyn Iter<Item = &'static [u8]>>,
+    // where the internal key and the value are in the current entry
+    key: Range<usize>,
+    value: Range<usize>,
+    bounds: Arc<IterBounds>,
+    // whether the iterator stopped at an entry out of bounds
+    exhausted: bool,
+    error: Option<Error>,
+}
+
+impl MemTableIterator {
+    /// parse locates the key and value of the current entry, and stops the iterator if the
+    /// entry is out of bounds.
+    fn parse(&mut self) {
+        let Some(entry) = self.iter.current() else {
+            (self.key, self.value) = (0..0, 0..0);
+            return;
+        };
+        let result = split_mem_entry(entry).and_then(|(key, value)| {
+            let offset = |part: &[u8]| part.as_ptr() as usize - entry.as_ptr() as usize;
+            self.key = offset(key)..offset(key) + key.len();
+            self.value = offset(value)..offset(value) + value.len();
+            self.bounds.contains(&InternalKeyComparator, key)
+        });
+        match result {
+            Ok(in_bounds) => self.exhausted = !in_bounds,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// seek_entry moves to the first entry at or after the internal key `target`, whether it's
+    /// in bounds or not.
+    fn seek_entry(&mut self, target: &[u8]) -> crate::Result<()> {
+        // the memtable orders encoded entries, of which the lookup has the key and sequence;
+        // keys are never empty, so every entry sorts after an empty one
+        let (key, seq, vtype) = parse_internal_key(target)?;
+        if key.is_empty() {
+            self.iter.seek_to_first();
+        } else {
+            self.iter.seek(&MemEntry::new(seq, vtype, key, &[]).encode()?);
+        }
+        Ok(())
+    }
+
+    fn entry(&self) -> &[u8] {
+        self.iter.current().unwrap_or_default()
+    }
+}
+
+impl InternalIterator for MemTableIterator {
+    fn valid(&self) -> bool {
+        self.error.is_none() && !self.exhausted && self.iter.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entry()[self.key.clone()]
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.entry()[self.value.clone()]
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+        self.parse();
+    }
+
+    fn prev(&mut self) {
+        self.iter.prev();
+        self.parse();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.exhausted = false;
+        let bounds = self.bounds.clone();
+        match bounds
+            .clamp(&InternalKeyComparator, target)
+            .and_then(|target| self.seek_entry(target))
+        {
+            Ok(()) => self.parse(),
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        if let Some(lower) = self.bounds.clone().lower.as_deref() {
+            return self.seek(lower);
+        }
+        self.exhausted = false;
+        self.iter.seek_to_first();
+        self.parse();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.exhausted = false;
+        if let Some(upper) = self.bounds.clone().upper.as_deref() {
+            // the last entry before the first one at or after the upper bound
+            if let Err(err) = self.seek_entry(upper) {
+                self.error = Some(err);
+                return;
+            }
+            if self.iter.valid() {
+                self.iter.prev();
+            } else {
+                self.iter.seek_to_last();
+            }
+        } else {
+            self.iter.seek_to_last();
+        }
+        self.parse();
+    }
+
+    fn status(&mut self) -> crate::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::Codec;
+    use crate::memtable::MemTable;
+    use crate::types::{MemEntry, ValueType, MAX_SEQUENCE};
+
+    #[test]
+    fn test_get_newest_version() {
+        let mem = MemTable::new();
+        mem.add(3, ValueType::Value, b"ab", b"v3").unwrap();
+        mem.add(1, ValueType::Value, b"a", b"v1").unwrap();
+        mem.add(4, ValueType::Deletion, b"b", b"").unwrap();
+        mem.add(2, ValueType::Value, b"a", b"v2").unwrap();
+        mem.add(5, ValueType::Value, b"b", b"v5").unwrap();
+
+        let get = |key: &[u8], sequence| mem.get(key, sequence).unwrap();
+        assert_eq!(get(b"a", MAX_SEQUENCE), Some((ValueType::Value, b"v2".as_slice())));
+        assert_eq!(get(b"ab", MAX_SEQUENCE), Some((ValueType::Value, b"v3".as_slice())));
+        assert_eq!(get(b"b", MAX_SEQUENCE), Some((ValueType::Value, b"v5".as_slice())));
+        assert_eq!(get(b"aa", MAX_SEQUENCE), None);
+        assert_eq!(get(b"c", MAX_SEQUENCE), None);
+
+        // older versions are visible as of their sequence
+        assert_eq!(get(b"a", 1), Some((ValueType::Value, b"v1".as_slice())));
+        assert_eq!(get(b"b", 4), Some((ValueType::Deletion, b"".as_slice())));
+        assert_eq!(get(b"b", 3), None);
+        assert_eq!(get(b"ab", 2), None);
+
+        let mut found = Vec::new();
+        let mut iter = mem.iter();
+        while let Some(raw_entry) = iter.current() {
+            let entry = MemEntry::decode_from_slice(raw_entry).unwrap();
+            found.push((entry.key().to_vec(), entry.seq()));
+            iter.next();
+        }
+        let expected: Vec<(Vec<u8>, u64)> = vec![
+            (b"a".to_vec(), 2),
+            (b"a".to_vec(), 1),
+            (b"ab".to_vec(), 3),
+            (b"b".to_vec(), 5),
+            (b"b".to_vec(), 4),
+        ];
+        assert_eq!(found, expected);
+    }
+}
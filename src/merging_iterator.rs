@@ -0,0 +1,297 @@
+use std::cmp::Ordering;
+
+use crate::cmp::{Comparator, InternalKeyComparator};
+use crate::iter::InternalIterator;
+use crate::Error;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// MergingIterator merges any number of iterators over internal keys into one. The valid
+/// children are kept in a binary heap by their current key, the smallest on top when moving
+/// forward and the largest when moving backward, so each step costs a sift rather than a scan
+/// over every child.
+pub(crate) struct MergingIterator {
+    children: Vec<Box<dyn InternalIterator>>,
+    // indices of the valid children, ordered for `direction`
+    heap: Vec<usize>,
+    direction: Direction,
+    cmp: InternalKeyComparator,
+    error: Option<Error>,
+}
+
+impl MergingIterator {
+    /// new merges `children`, which is invalid until it's positioned.
+    pub(crate) fn new(children: Vec<Box<dyn InternalIterator>>) -> Self {
+        Self {
+            children,
+            heap: Vec::new(),
+            direction: Direction::Forward,
+            cmp: InternalKeyComparator,
+            error: None,
+        }
+    }
+
+    /// before reports whether child `a` comes out of the heap ahead of child `b`.
+    fn before(&self, a: usize, b: usize) -> crate::Result<bool> {
+        let order = self
+            .cmp
+            .cmp(self.children[a].key(), self.children[b].key())?;
+        Ok(match self.direction {
+            Direction::Forward => order == Ordering::Less,
+            Direction::Reverse => order == Ordering::Greater,
+        })
+    }
+
+    fn sift_up(&mut self, mut at: usize) -> crate::Result<()> {
+        while at > 0 {
+            let parent = (at - 1) / 2;
+            if !self.before(self.heap[at], self.heap[parent])? {
+                break;
+            }
+            self.heap.swap(at, parent);
+            at = parent;
+        }
+        Ok(())
+    }
+
+    fn sift_down(&mut self, mut at: usize) -> crate::Result<()> {
+        loop {
+            let mut first = at;
+            for child in [2 * at + 1, 2 * at + 2] {
+                if child < self.heap.len() && self.before(self.heap[child], self.heap[first])? {
+                    first = child;
+                }
+            }
+            if first == at {
+                return Ok(());
+            }
+            self.heap.swap(at, first);
+            at = first;
+        }
+    }
+
+    /// rebuild puts the valid children in the heap after every child was repositioned.
+    fn rebuild(&mut self, direction: Direction) -> crate::Result<()> {
+        self.direction = direction;
+        self.heap.clear();
+        for i in 0..self.children.len() {
+            self.children[i].status()?;
+            if self.children[i].valid() {
+                self.heap.push(i);
+                self.sift_up(self.heap.len() - 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// fix_top restores the heap after the child on top moved, dropping it once it's done.
+    fn fix_top(&mut self) -> crate::Result<()> {
+        let top = self.heap[0];
+        self.children[top].status()?;
+        if !self.children[top].valid() {
+            let last = self.heap.pop().unwrap();
+            if self.heap.is_empty() {
+                return Ok(());
+            }
+            self.heap[0] = last;
+        }
+        self.sift_down(0)
+    }
+
+    /// step moves the child on top with `f`, and first every other child to the same side of
+    /// the current key with `turn` if the direction changes.
+    fn step(
+        &mut self,
+        direction: Direction,
+        turn: impl Fn(&mut dyn InternalIterator, &[u8], &InternalKeyComparator),
+        f: impl FnOnce(&mut dyn InternalIterator),
+    ) -> crate::Result<()> {
+        if self.direction != direction {
+            let top = self.heap[0];
+            let key = self.children[top].key().to_vec();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i != top {
+                    turn(child.as_mut(), &key, &self.cmp);
+                }
+            }
+            self.rebuild(direction)?;
+        }
+        let top = self.heap[0];
+        f(self.children[top].as_mut());
+        self.fix_top()
+    }
+
+    fn record(&mut self, result: crate::Result<()>) {
+        if let Err(err) = result {
+            self.heap.clear();
+            self.error = Some(err);
+        }
+    }
+}
+
+impl InternalIterator for MergingIterator {
+    fn valid(&self) -> bool {
+        self.error.is_none() && !self.heap.is_empty()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.heap
+            .first()
+            .map_or(&[], |top| self.children[*top].key())
+    }
+
+    fn value(&self) -> &[u8] {
+        self.heap
+            .first()
+            .map_or(&[], |top| self.children[*top].value())
+    }
+
+    fn next(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let result = self.step(
+            Direction::Forward,
+            |child, key, cmp| {
+                // the first entry after the current one
+                child.seek(key);
+                if child.valid() && cmp.cmp(child.key(), key).ok() == Some(Ordering::Equal) {
+                    child.next();
+                }
+            },
+            |top| top.next(),
+        );
+        self.record(result);
+    }
+
+    fn prev(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let result = self.step(
+            Direction::Reverse,
+            |child, key, _| {
+                // the last entry before the current one
+                child.seek(key);
+                if child.valid() {
+                    child.prev();
+                } else {
+                    child.seek_to_last();
+                }
+            },
+            |top| top.prev(),
+        );
+        self.record(result);
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        for child in self.children.iter_mut() {
+            child.seek(target);
+        }
+        let result = self.rebuild(Direction::Forward);
+        self.record(result);
+    }
+
+    fn seek_to_first(&mut self) {
+        for child in self.children.iter_mut() {
+            child.seek_to_first();
+        }
+        let result = self.rebuild(Direction::Forward);
+        self.record(result);
+    }
+
+    fn seek_to_last(&mut self) {
+        for child in self.children.iter_mut() {
+            child.seek_to_last();
+        }
+        let result = self.rebuild(Direction::Reverse);
+        self.record(result);
+    }
+
+    fn status(&mut self) -> crate::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::iter::InternalIterator;
+    use crate::memtable::MemTable;
+    use crate::merging_iterator::MergingIterator;
+    use crate::types::{internal_key, parse_internal_key, ValueType};
+
+    fn current(iter: &MergingIterator) -> (Vec<u8>, u64) {
+        let (key, seq, _) = parse_internal_key(iter.key()).unwrap();
+        (key.to_vec(), seq)
+    }
+
+    #[test]
+    fn test_merge_both_directions() {
+        let mems = [MemTable::new(), MemTable::new(), MemTable::new()];
+        // the same key in several children, and a child without entries
+        mems[0].add(1, ValueType::Value, b"a", b"a1").unwrap();
+        mems[0].add(5, ValueType::Value, b"c", b"c5").unwrap();
+        mems[1].add(2, ValueType::Value, b"b", b"b2").unwrap();
+        mems[1].add(6, ValueType::Deletion, b"c", b"").unwrap();
+        mems[1].add(4, ValueType::Value, b"d", b"d4").unwrap();
+        mems[0].add(3, ValueType::Value, b"d", b"d3").unwrap();
+        let empty = MemTable::new();
+        let mut iter = MergingIterator::new(
+            mems.iter()
+                .chain([&empty])
+                .map(|m| Box::new(m.internal_iter(Arc::default())) as Box<dyn InternalIterator>)
+                .collect(),
+        );
+        let expected: Vec<(Vec<u8>, u64)> =
+            [("a", 1), ("b", 2), ("c", 6), ("c", 5), ("d", 4), ("d", 3)]
+                .iter()
+                .map(|(k, s)| (k.as_bytes().to_vec(), *s))
+                .collect();
+
+        iter.seek_to_first();
+        let mut forward = Vec::new();
+        while iter.valid() {
+            forward.push(current(&iter));
+            iter.next();
+        }
+        assert_eq!(forward, expected);
+
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while iter.valid() {
+            backward.push(current(&iter));
+            iter.prev();
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+        assert!(iter.status().is_ok());
+
+        // switching directions in the middle of entries of one key
+        iter.seek(&internal_key(b"c", 5, ValueType::Value));
+        assert_eq!(current(&iter), (b"c".to_vec(), 5));
+        assert_eq!(iter.value(), b"c5");
+        iter.prev();
+        assert_eq!(current(&iter), (b"c".to_vec(), 6));
+        iter.prev();
+        assert_eq!(current(&iter), (b"b".to_vec(), 2));
+        iter.next();
+        assert_eq!(current(&iter), (b"c".to_vec(), 6));
+        iter.next();
+        iter.next();
+        assert_eq!(current(&iter), (b"d".to_vec(), 4));
+        iter.prev();
+        assert_eq!(current(&iter), (b"c".to_vec(), 5));
+
+        iter.seek(&internal_key(b"e", 10, ValueType::Value));
+        assert!(!iter.valid());
+    }
+}
@@ -1,3 +0,0 @@
-pub(crate) struct Metadata {
-    key_offsets: Vec<u64>,
-}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use crate::db::Snapshot;
+use crate::table::TableOptions;
+
+/// WalRecoveryMode decides what happens when the last record of a write-ahead log was only
+/// partially written, which is what a crash in the middle of a write leaves behind.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WalRecoveryMode {
+    /// Drop the torn record and truncate the log after the last intact record.
+    TruncateTail,
+    /// Refuse to open the database with `Error::Corruption`.
+    Fail,
+}
+
+/// WalSyncMode decides when the write-ahead log is synced to disk for the writes that don't
+/// ask for it with `WriteOptions::sync`. Unsynced writes survive a crash of the process but
+/// may be lost when the machine goes down.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WalSyncMode {
+    /// Leave it to the operating system.
+    NoSync,
+    /// Sync in the background every interval the log was written to.
+    Interval(Duration),
+    /// Sync in the background once this many bytes were written since the last sync.
+    Bytes(u64),
+}
+
+/// CompactionStyle decides how tables are merged in the background as they accumulate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompactionStyle {
+    /// Keep every level below 0 a single sorted run, each one larger than the one above it by
+    /// a fixed factor. Reads and space stay cheap at the price of rewriting data more often.
+    Leveled,
+    /// Let sorted runs pile up and merge runs of similar size. Data is rewritten less often,
+    /// which suits write heavy workloads, but reads may have more runs to check.
+    Tiered,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub create_if_missing: bool,
+    pub error_if_exists: bool,
+    pub wal_recovery_mode: WalRecoveryMode,
+    pub wal_sync_mode: WalSyncMode,
+    /// Number of obsolete write-ahead logs kept to be written over by the logs of later
+    /// memtables, which spares the file system allocating new files. Logs are then written in a
+    /// format that tells their records apart from those left over in the file.
+    pub recycle_log_file_num: usize,
+    /// Size in bytes the memtable grows to before it's frozen and flushed to a table in the
+    /// background.
+    pub write_buffer_size: usize,
+    /// Number of level 0 tables that starts a compaction into level 1.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Total table size of level 1 above which it's compacted into level 2. Each deeper level
+    /// may hold `max_bytes_for_level_multiplier` times more than the one above it.
+    pub max_bytes_for_level_base: u64,
+    pub max_bytes_for_level_multiplier: u64,
+    pub compaction_style: CompactionStyle,
+    /// With tiered compaction, how much larger in percent, by size and by number of keys, a run
+    /// may be than the newer runs before it to still be merged with them.
+    pub tiered_size_ratio: u64,
+    /// With tiered compaction, the number of sorted runs above which runs are merged.
+    pub tiered_max_run_count: usize,
+    /// Number of tables the table cache keeps open, and mapped, at once. Other tables are
+    /// opened as they're read. Tables that iterators or cached blocks still reference stay
+    /// mapped on top of these until they're dropped.
+    pub max_open_files: usize,
+    pub table_options: TableOptions,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            create_if_missing: true,
+            error_if_exists: false,
+            wal_recovery_mode: WalRecoveryMode::TruncateTail,
+            wal_sync_mode: WalSyncMode::NoSync,
+            recycle_log_file_num: 0,
+            write_buffer_size: 4 << 20,
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base: 10 << 20,
+            max_bytes_for_level_multiplier: 10,
+            compaction_style: CompactionStyle::Leveled,
+            tiered_size_ratio: 1,
+            tiered_max_run_count: 4,
+            max_open_files: 1000,
+            table_options: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions<'a> {
+    /// Read as of this snapshot instead of the latest state.
+    pub snapshot: Option<&'a Snapshot>,
+    /// Iterators only see keys at or after this one, and seeks to earlier keys land on it.
+    pub lower_bound: Option<&'a [u8]>,
+    /// Iterators only see keys before this one. Tables stop being read at the first block past
+    /// it.
+    pub upper_bound: Option<&'a [u8]>,
+    /// An iterator positioned with `DBIterator::seek` only sees the keys that start with the
+    /// seek target, which makes seeking to a prefix a scan of it.
+    pub prefix_same_as_start: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Sync the write-ahead log to disk before the write returns, so the write survives the
+    /// machine going down. Writes grouped with it are synced along.
+    pub sync: bool,
+}
@@ -1,9 +1,9 @@
-use crate::cmp::{Comparator, DefaultComparator};
+use crate::cmp::Comparator;
 use crate::error::Error;
 use crate::iter::Iter;
 use bumpalo::Bump;
-use rand::prelude::StdRng;
-use rand::{RngCore, SeedableRng};
+use parking_lot::Mutex;
+use rand::Rng;
 use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Formatter};
 use std::marker::PhantomData;
@@ -15,24 +15,24 @@ const BRANCHING_FACTOR: u32 = 4;
 #[derive(Debug)]
 struct Node {
     key: *const [u8],
-    skips: *const Vec<AtomicPtr<Node>>,
+    skips: *const [AtomicPtr<Node>],
 }
 
 impl Node {
     fn next(&self, n: usize) -> *const Node {
-        unsafe { (*self.skips)[n].load(MemoryOrdering::Acquire) }
+        unsafe { (&*self.skips)[n].load(MemoryOrdering::Acquire) }
     }
 
     fn set_next(&self, n: usize, node: *const Node) {
-        unsafe { (*self.skips)[n].store(node as *mut Node, MemoryOrdering::Release) }
+        unsafe { (&*self.skips)[n].store(node as *mut Node, MemoryOrdering::Release) }
     }
 
     fn nb_next(&self, n: usize) -> *const Node {
-        unsafe { (*self.skips)[n].load(MemoryOrdering::Relaxed) }
+        unsafe { (&*self.skips)[n].load(MemoryOrdering::Relaxed) }
     }
 
     fn nb_set_next(&self, n: usize, node: *const Node) {
-        unsafe { (*self.skips)[n].store(node as *mut Node, MemoryOrdering::Relaxed) }
+        unsafe { (&*self.skips)[n].store(node as *mut Node, MemoryOrdering::Relaxed) }
     }
 
     fn key(&self) -> &[u8] {
@@ -42,12 +42,21 @@ impl Node {
 
 pub struct InnerSkipList {
     cmp: Arc<Box<dyn Comparator>>,
-    arena: Bump,
+    // nodes are allocated under the arena lock, which an insert holds throughout, so inserts
+    // are serialized while readers traverse the list without it
+    arena: Mutex<Bump>,
     head: Box<Node>,
     max_height: AtomicUsize,
     len: AtomicUsize,
 }
 
+// Nodes are only ever linked in, never freed, until the arena is dropped, and inserts are
+// serialized by the arena lock, so readers may traverse the list from any thread while one
+// thread at a time links in new nodes.
+unsafe impl Send for InnerSkipList {}
+
+unsafe impl Sync for InnerSkipList {}
+
 impl Debug for InnerSkipList {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut list = f.debug_map();
@@ -83,7 +92,7 @@ impl Display for InnerSkipList {
                         "{:?} {:?} - {:?}",
                         current,
                         (*current).key,
-                        (*(*current).skips)
+                        &(*(*current).skips)
                     )?;
                 } else {
                     break;
@@ -96,36 +105,32 @@ impl Display for InnerSkipList {
 
 impl InnerSkipList {
     pub fn new(arena: Bump, cmp: Arc<Box<dyn Comparator>>) -> InnerSkipList {
-        let skips = arena.alloc(Vec::with_capacity(MAX_HEIGHT));
-        for _ in 0..MAX_HEIGHT {
-            skips.push(AtomicPtr::default())
-        }
+        let skips = arena.alloc_slice_fill_with(MAX_HEIGHT, |_| AtomicPtr::default());
         let head = Box::new(Node {
             key: arena.alloc_slice_copy(&[]),
             skips,
         });
         InnerSkipList {
             cmp,
-            arena,
+            arena: Mutex::new(arena),
             head,
             max_height: AtomicUsize::new(1),
             len: AtomicUsize::default(),
         }
     }
 
-    fn new_node(&self, height: usize, key: &[u8]) -> *mut Node {
-        let key = self.arena.alloc_slice_copy(key);
-        let skips = self.arena.alloc(Vec::with_capacity(height));
-        for _ in 0..height {
-            skips.push(AtomicPtr::default())
-        }
-        self.arena.alloc(Node { key, skips })
+    fn new_node(arena: &Bump, height: usize, key: &[u8]) -> *mut Node {
+        let key = arena.alloc_slice_copy(key);
+        // The arena never runs destructors, so the links live in the arena as well rather than
+        // in a heap allocated Vec that would leak with every node.
+        let skips = arena.alloc_slice_fill_with(height, |_| AtomicPtr::default());
+        arena.alloc(Node { key, skips })
     }
 
     fn random_height(&self) -> usize {
         let mut height = 1;
-        let mut rand = StdRng::seed_from_u64(0xdeadbeef);
-        while height < MAX_HEIGHT && rand.next_u32() % BRANCHING_FACTOR == 0 {
+        let mut rand = rand::thread_rng();
+        while height < MAX_HEIGHT && rand.gen::<u32>() % BRANCHING_FACTOR == 0 {
             height += 1;
         }
         height
@@ -157,18 +162,11 @@ impl InnerSkipList {
                 }
 
                 if level == 0 {
-                    break;
+                    // every node is before `key`
+                    return Ok(None);
                 }
                 level -= 1;
             }
-
-            Ok(
-                if current == self.head.as_ref() || (*(*current).key).lt(key) {
-                    None
-                } else {
-                    current.as_ref()
-                },
-            )
         }
     }
 
@@ -178,11 +176,9 @@ impl InnerSkipList {
         unsafe {
             loop {
                 let next = (*current).next(level);
-                if !next.is_null() {
-                    if (*next).key() < key {
-                        current = next;
-                        continue;
-                    }
+                if !next.is_null() && self.cmp.cmp((*next).key(), key)? == Ordering::Less {
+                    current = next;
+                    continue;
                 }
 
                 if level == 0 {
@@ -228,6 +224,8 @@ impl InnerSkipList {
     }
 
     fn insert(&self, key: &[u8]) -> crate::Result<()> {
+        // the predecessors found below stay valid only while no other insert links in nodes
+        let arena = self.arena.lock();
         let mut prevs = std::vec![std::ptr::null(); MAX_HEIGHT];
         let mut current = self.head.as_ref() as *const Node;
         let mut level = self.max_height() - 1;
@@ -265,7 +263,7 @@ impl InnerSkipList {
             self.max_height.store(height, MemoryOrdering::Relaxed);
         }
 
-        current = self.new_node(height, key);
+        current = Self::new_node(&arena, height, key);
         for (i, prev) in prevs.into_iter().enumerate() {
             unsafe {
                 (*current).nb_set_next(i, (*prev).nb_next(i));
@@ -277,12 +275,13 @@ impl InnerSkipList {
         Ok(())
     }
 
+    #[cfg(test)]
     fn contains(&self, key: &[u8]) -> crate::Result<bool> {
         if let Some(node) = self.find_greater_or_equal(key)? {
             unsafe {
                 return self
                     .cmp
-                    .cmp(&(*(*node).key), key)
+                    .cmp(&(*node.key), key)
                     .map(|ord| ord == Ordering::Equal);
             }
         }
@@ -306,30 +305,21 @@ impl SkipList {
         }
     }
 
-    pub(crate) fn new_in_arena(arena: Bump, cmp: Arc<Box<dyn Comparator>>) -> Self {
-        Self {
-            inner: Arc::new(InnerSkipList::new(arena, cmp)),
-        }
-    }
-
+    #[cfg(test)]
     pub(crate) fn default() -> Self {
-        Self::new(Arc::new(Box::new(DefaultComparator)))
+        Self::new(Arc::new(Box::new(crate::cmp::DefaultComparator)))
     }
 
     pub(crate) fn insert(&self, key: &[u8]) -> crate::Result<()> {
         self.inner.insert(key)
     }
 
-    pub(crate) fn contains(&self, key: &[u8]) -> crate::Result<bool> {
-        self.inner.contains(key)
-    }
-
     pub(crate) fn len(&self) -> usize {
         self.inner.len()
     }
 
     pub(crate) fn allocated_bytes(&self) -> usize {
-        self.inner.arena.allocated_bytes()
+        self.inner.arena.lock().allocated_bytes()
     }
 
     pub(crate) fn iter<'a>(&self) -> Box<dyn 'a + Iter<Item = &'a [u8]>> {
@@ -347,15 +337,6 @@ struct SkipMapIterator<'a> {
     data_: PhantomData<&'a ()>,
 }
 
-impl<'a> SkipMapIterator<'a> {
-    fn is_valid(&self) -> crate::Result<()> {
-        if self.node.is_null() {
-            return Err(Error::InvalidIterator);
-        }
-        Ok(())
-    }
-}
-
 impl<'a> Iter for SkipMapIterator<'a> {
     type Item = &'a [u8];
 
@@ -470,40 +451,40 @@ mod tests {
             "abf".as_bytes()
         );
         assert!(skm
-            .find_greater_or_equal(&"ab{".as_bytes().to_vec())
+            .find_greater_or_equal("ab{".as_bytes())
             .unwrap()
             .is_none());
         assert_eq!(
-            skm.find_greater_or_equal(&"aaa".as_bytes().to_vec())
+            skm.find_greater_or_equal("aaa".as_bytes())
                 .unwrap()
                 .unwrap()
                 .key(),
             "aba".as_bytes()
         );
         assert_eq!(
-            skm.find_greater_or_equal(&"ab".as_bytes())
+            skm.find_greater_or_equal("ab".as_bytes())
                 .unwrap()
                 .unwrap()
                 .key(),
             "aba".as_bytes()
         );
         assert_eq!(
-            skm.find_greater_or_equal(&"abc".as_bytes())
+            skm.find_greater_or_equal("abc".as_bytes())
                 .unwrap()
                 .unwrap()
                 .key(),
             "abc".as_bytes()
         );
-        assert!(skm.find_less_than(&"ab0".as_bytes()).unwrap().is_none());
+        assert!(skm.find_less_than("ab0".as_bytes()).unwrap().is_none());
         assert_eq!(
-            skm.find_less_than(&"abd".as_bytes())
+            skm.find_less_than("abd".as_bytes())
                 .unwrap()
                 .unwrap()
                 .key(),
             "abc".as_bytes()
         );
         assert_eq!(
-            skm.find_less_than(&"ab{".as_bytes())
+            skm.find_less_than("ab{".as_bytes())
                 .unwrap()
                 .unwrap()
                 .key(),
@@ -514,7 +495,7 @@ mod tests {
     #[test]
     fn test_contains() {
         let skm = make_skipmap();
-        println!("Allocated {}", skm.arena.allocated_bytes());
+        println!("Allocated {}", skm.arena.lock().allocated_bytes());
         assert!(skm.contains("aby".as_bytes()).unwrap());
         assert!(skm.contains("abc".as_bytes()).unwrap());
         assert!(skm.contains("abz".as_bytes()).unwrap());
@@ -530,14 +511,14 @@ mod tests {
         let mut iter = skm.iter();
         iter.next();
         assert!(iter.valid());
-        assert_eq!(current_key_val(&iter).unwrap(), "aba".as_bytes());
-        iter.seek(&"abz".as_bytes());
-        assert_eq!(current_key_val(&iter).unwrap(), "abz".as_bytes());
+        assert_eq!(current_key_val(&*iter).unwrap(), "aba".as_bytes());
+        iter.seek("abz".as_bytes());
+        assert_eq!(current_key_val(&*iter).unwrap(), "abz".as_bytes());
         // go back to beginning
-        iter.seek(&"aba".as_bytes());
-        assert_eq!(current_key_val(&iter).unwrap(), "aba".as_bytes());
+        iter.seek("aba".as_bytes());
+        assert_eq!(current_key_val(&*iter).unwrap(), "aba".as_bytes());
 
-        iter.seek(&"".as_bytes());
+        iter.seek("".as_bytes());
         assert!(iter.valid());
         iter.prev();
         assert!(!iter.valid());
@@ -547,10 +528,38 @@ mod tests {
         }
         assert!(!iter.valid());
         iter.prev();
-        assert_eq!(current_key_val(&iter), None);
+        assert_eq!(current_key_val(&*iter), None);
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let skm = Arc::new(make_skipmap_t());
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let skm = skm.clone();
+                std::thread::spawn(move || {
+                    for i in 0..500 {
+                        skm.insert(format!("key{}-{:03}", t, i).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(skm.len(), 26 + 4 * 500);
+        let mut iter = skm.iter();
+        iter.seek_to_first();
+        let mut keys = Vec::new();
+        while let Some(key) = iter.current() {
+            keys.push(key.to_vec());
+            iter.next();
+        }
+        assert_eq!(keys.len(), 26 + 4 * 500);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
     }
 
-    fn current_key_val<'a>(iter: &'a Box<dyn 'a + Iter<Item = &[u8]>>) -> Option<&'a [u8]> {
+    fn current_key_val<'a>(iter: &'a (dyn 'a + Iter<Item = &[u8]>)) -> Option<&'a [u8]> {
         iter.current()
     }
 }
@@ -1,45 +1,89 @@
-use core::slice::SlicePattern;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 use memmap2::Mmap;
 
-use crate::{codec, Error};
+use crate::{ensure, Error};
 use crate::block::{Block, BlockIterator};
+use crate::block_cache::BlockCache;
 use crate::bloom::BloomFilterPolicy;
-use crate::codec::decode_fixed32;
-use crate::constant::BLOCK_ENTRY_HEADER_SIZE;
-use crate::iter::Iter;
-use crate::table_index::TableIndexReader;
-
-pub(crate) fn decode_key(data: &[u8]) -> &[u8] {
-    //TODO: Handle index errors
-    let key_size = decode_fixed32(&data[..4]) as usize;
-    &data[BLOCK_ENTRY_HEADER_SIZE..BLOCK_ENTRY_HEADER_SIZE + key_size]
+use crate::cmp::{Comparator, DefaultComparator};
+use crate::footer::{BlockHandle, Footer};
+use crate::iter::{InternalIterator, Iter, IterBounds};
+use crate::table_filter::TableFilter;
+use crate::table_index::{BlockIndex, TableIndexReader};
+use crate::table_properties::{TableProperties, PROPERTIES_BLOCK};
+
+/// LATEST_FORMAT_VERSION is the table format written by default. Version 0 tables store every
+/// key in full and end with the index; later versions prefix-compress keys within blocks and
+/// end with the format version and `TABLE_MAGIC`. Version 2 encodes entry lengths as varints
+/// and version 3 ends tables with a `Footer` that also locates a metaindex block.
+pub(crate) const LATEST_FORMAT_VERSION: u32 = 3;
+pub(crate) const TABLE_MAGIC: u64 = 0x8b1f_3a5d_0c62_e4b7;
+
+/// CompressionType is how the blocks of a table are compressed. Each block records its own
+/// type, so tables written with different types can be read alike.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum CompressionType {
+    #[default]
+    None = 0,
+    Snappy = 1,
+    Zstd = 2,
+    Lz4 = 3,
 }
 
-pub(crate) fn decode_key_value(data: &[u8]) -> (&[u8], &[u8]) {
-    //TODO: Handle index errors
-    let key_size = decode_fixed32(&data[..4]) as usize;
-    let value_size = decode_fixed32(&data[4..8]) as usize;
-    let key = &data[BLOCK_ENTRY_HEADER_SIZE..BLOCK_ENTRY_HEADER_SIZE + key_size];
-    let value =
-        &data[BLOCK_ENTRY_HEADER_SIZE + key_size..BLOCK_ENTRY_HEADER_SIZE + key_size + value_size];
-    (key, value)
+impl TryFrom<u8> for CompressionType {
+    type Error = Error;
+
+    fn try_from(typ: u8) -> crate::Result<Self> {
+        match typ {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Snappy),
+            2 => Ok(CompressionType::Zstd),
+            3 => Ok(CompressionType::Lz4),
+            _ => Err(Error::Corruption(format!("unknown compression type {}", typ))),
+        }
+    }
 }
 
-pub(crate) enum CompressionType {
-    Snappy,
-    Zstd,
-    Lz4,
+/// FilterMode is how the bloom filters of a table are laid out.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FilterMode {
+    /// A filter for each block, kept in the index next to the block.
+    PerBlock,
+    /// A single filter for the whole table, checked before the index is read.
+    FullTable,
+    /// A filter for each run of blocks whose keys fill `filter_partition_size` bytes of
+    /// filter, located through a filter index that's checked before the index is read. Suits
+    /// large tables, whose lookups then only read one small filter.
+    Partitioned,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TableOptions {
     pub(crate) block_size: usize,
     pub(crate) table_size: usize,
-    pub(crate) table_capacity: usize,
     pub(crate) checksum: bool,
     pub(crate) policy: BloomFilterPolicy,
+    pub(crate) comparator: Arc<Box<dyn Comparator>>,
+    /// Compression applied to each block.
+    pub compression: CompressionType,
+    /// Fraction of its size compression has to save for a block to be stored compressed;
+    /// blocks that compress worse are stored raw.
+    pub min_compression_saving: f64,
+    /// Number of entries between the keys stored in full within a block. Keys in between only
+    /// store what they don't share with the key before them.
+    pub block_restart_interval: usize,
+    /// Format new tables are written in. Tables of older formats remain readable.
+    pub format_version: u32,
+    /// Filters other than per block need format version 3 or later.
+    pub filter_mode: FilterMode,
+    pub filter_partition_size: usize,
+    /// Size in bytes of the cache of decoded blocks shared by the tables of a database. 0
+    /// disables the cache.
+    pub block_cache_size: usize,
+    pub(crate) block_cache: Option<Arc<BlockCache>>,
 }
 
 impl Default for TableOptions {
@@ -47,109 +91,215 @@ impl Default for TableOptions {
         Self {
             block_size: 4 * 1024,
             table_size: 2 << 20,
-            table_capacity: ((2_u64 << 20_u64) as f64 / 0.9) as usize,
             checksum: true,
             policy: BloomFilterPolicy::new(10),
+            comparator: Arc::new(Box::new(DefaultComparator)),
+            compression: CompressionType::None,
+            min_compression_saving: 0.125,
+            block_restart_interval: 16,
+            format_version: LATEST_FORMAT_VERSION,
+            filter_mode: FilterMode::PerBlock,
+            filter_partition_size: 4 * 1024,
+            block_cache_size: 8 << 20,
+            block_cache: None,
         }
     }
 }
 
+#[derive(Debug)]
 pub(crate) struct InnerTable {
-    data: Mmap,
+    data: Arc<Mmap>,
     id: u64,
-    index_start: usize,
-    index_len: usize,
+    index: Range<usize>,
+    filter: TableFilter,
+    properties: Option<TableProperties>,
+    format_version: u32,
     opts: Arc<TableOptions>,
 }
 
 impl InnerTable {
-    fn open(id: u64, file: Mmap, opts: Arc<TableOptions>) -> InnerTable {
-        let s = &file[file.as_slice().len() - 8..file.as_slice().len() - 4];
-        let index_len = codec::decode_fixed32(s) as usize;
-        let index_start = file.as_slice().len() - index_len - 8;
-        InnerTable {
-            data: file,
+    fn open(id: u64, file: Mmap, opts: Arc<TableOptions>) -> crate::Result<InnerTable> {
+        let footer = Footer::decode(&file)?;
+        let index = footer.index.range(file.len())?;
+        if opts.checksum {
+            ensure!(
+                crc32fast::hash(&file[index.clone()]) == footer.index_checksum,
+                Error::Corruption("index checksum failed".to_string())
+            );
+        }
+        // validated once, so lookups can read the index unchecked
+        TableIndexReader::open(&file[index.clone()], &opts)?;
+        let mut meta_blocks = BTreeMap::new();
+        if let Some(handle) = footer.metaindex {
+            let range = handle.range(file.len())?;
+            let block = Block::open(range.start, &file[range], footer.format_version, &opts)?;
+            let mut iter = block.into_iter();
+            while let Some((name, handle)) = iter.current() {
+                meta_blocks.insert(name.to_vec(), BlockHandle::decode(handle)?);
+                iter.next();
+            }
+            iter.status()?;
+        }
+        let properties = match meta_blocks.get(PROPERTIES_BLOCK) {
+            Some(handle) => {
+                let range = handle.range(file.len())?;
+                let block = Block::open(range.start, &file[range], footer.format_version, &opts)?;
+                Some(TableProperties::decode(block.into_iter())?)
+            }
+            None => None,
+        };
+        let filter = TableFilter::open(&file, &meta_blocks, footer.format_version, &opts)?;
+        Ok(InnerTable {
+            data: Arc::new(file),
             id,
-            index_start,
-            index_len,
+            index,
+            filter,
+            properties,
+            format_version: footer.format_version,
             opts,
-        }
+        })
+    }
+
+    fn index(&self) -> TableIndexReader<'_> {
+        // SAFETY: open validated the index, and the mapped file doesn't change
+        unsafe { TableIndexReader::open_unchecked(&self.data[self.index.clone()], &self.opts) }
     }
 
-    fn index(&self) -> crate::Result<TableIndexReader> {
-        TableIndexReader::open(
-            &self.data[self.index_start..(self.index_start + self.index_len)],
-            self.opts.clone(),
+    /// block_data returns the bytes of the block at `block_index`, which the index may only
+    /// place before itself.
+    fn block_data(&self, block_index: &BlockIndex) -> crate::Result<&[u8]> {
+        ensure!(
+            block_index.offset_start <= block_index.offset_end
+                && block_index.offset_end <= self.index.start,
+            Error::Corruption("block offsets out of range".to_string())
+        );
+        Ok(&self.data[block_index.offset_start..block_index.offset_end])
+    }
+
+    fn open_block(&self, block_index: &BlockIndex) -> crate::Result<Block<'_>> {
+        Block::open(
+            block_index.offset_start,
+            self.block_data(block_index)?,
+            self.format_version,
+            self.opts.as_ref(),
         )
     }
 
-    fn get_block(&self, index: usize) -> crate::Result<Option<Block>> {
-        let index_reader = self.index()?;
-        let block_index = match index_reader.get_block_index(index) {
-            None => return Ok(None),
-            Some(index) => index,
-        };
-        let raw_block = &self.data[block_index.offset_start..block_index.offset_end];
-        let block = Block::open(block_index.offset_start, raw_block, self.opts.as_ref())?;
-        return Ok(Some(block));
+    /// read_block returns the block at `block_index` from the block cache, or reads it and
+    /// offers it to the cache. Uncompressed blocks aren't copied, they keep the file mapped.
+    fn read_block(&self, block_index: &BlockIndex) -> crate::Result<Arc<Block<'static>>> {
+        let cache = self.opts.block_cache.as_ref();
+        if let Some(block) = cache.and_then(|c| c.get(self.id, block_index.offset_start)) {
+            return Ok(block);
+        }
+        let block = Arc::new(self.open_block(block_index)?.into_shared(&self.data));
+        if let Some(cache) = cache {
+            cache.insert(self.id, block_index.offset_start, block.clone());
+        }
+        Ok(block)
     }
 
-    fn get(&self, key: &[u8]) -> crate::Result<Option<&[u8]>> {
-        let index = self.index()?;
-        let block_index = match index.find_key_block(key) {
-            None => return Ok(None),
-            Some(index) => index,
-        };
-        let raw_block = &self.data[block_index.offset_start..block_index.offset_end];
-        let block = Block::open(block_index.offset_start, raw_block, self.opts.as_ref())?;
-        if let Some((start, end)) = block.get_value_offset_abs(key) {
-            return Ok(Some(&self.data[start..end]));
+    fn get_block(&self, index: usize) -> crate::Result<Option<Arc<Block<'static>>>> {
+        match self.index().get_block_index(index) {
+            None => Ok(None),
+            Some(block_index) => Ok(Some(self.read_block(&block_index)?)),
         }
-        return Ok(None);
     }
 
-    fn block_count(&self) -> usize {
-        match self.index() {
-            Ok(index) => index.blocks_count(),
-            Err(_) => 0,
+    /// get returns the first entry at or after `key`, or `None` if the filters rule out the user
+    /// key of `key`. Blocks are indexed by their first key, so the entry is either in the block
+    /// `key` falls in or, when `key` sorts after everything in it, first in the next one.
+    fn get(&self, key: &[u8]) -> crate::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if !self.filter.may_contain(&self.data, key, &self.opts)? {
+            return Ok(None);
+        }
+        let index = self.index();
+        let first = index.find_target_key_block(key)?;
+        for at in first..first + 2 {
+            let block_index = match index.get_block_index(at) {
+                None => break,
+                Some(index) => index,
+            };
+            if !index.may_contain(&block_index, key) {
+                continue;
+            }
+            // without a cache, the block is read in place rather than copied out
+            let found = match self.opts.block_cache {
+                Some(_) => self.read_block(&block_index)?.get(key)?.map(|(k, v)| (k, v.to_vec())),
+                None => self.open_block(&block_index)?.get(key)?.map(|(k, v)| (k, v.to_vec())),
+            };
+            if found.is_some() {
+                return Ok(found);
+            }
         }
+        Ok(None)
+    }
+
+    fn block_count(&self) -> usize {
+        self.index().blocks_count()
     }
 }
 
+#[derive(Debug, Clone)]
 pub(crate) struct Table {
     inner: Arc<InnerTable>,
 }
 
 impl Table {
-    pub(crate) fn open(id: u64, file: Mmap, opts: Arc<TableOptions>) -> Self {
-        Self {
-            inner: Arc::new(InnerTable::open(id, file, opts)),
-        }
+    /// open reads the table in `file`, failing with `Error::Corruption` if it isn't a valid
+    /// table.
+    pub(crate) fn open(id: u64, file: Mmap, opts: Arc<TableOptions>) -> crate::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(InnerTable::open(id, file, opts)?),
+        })
     }
 
-    pub(crate) fn index(&self) -> crate::Result<TableIndexReader> {
+    pub(crate) fn index(&self) -> TableIndexReader<'_> {
         self.inner.index()
     }
 
-    pub(crate) fn get_block(&self, index: usize) -> crate::Result<Option<Block>> {
+    pub(crate) fn get_block(&self, index: usize) -> crate::Result<Option<Arc<Block<'static>>>> {
         self.inner.get_block(index)
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> crate::Result<Option<&[u8]>> {
+    pub(crate) fn get(&self, key: &[u8]) -> crate::Result<Option<(Vec<u8>, Vec<u8>)>> {
         self.inner.get(key)
     }
 
+    /// properties returns the statistics the table was written with, or `None` for tables
+    /// written before tables had properties.
+    pub(crate) fn properties(&self) -> Option<&TableProperties> {
+        self.inner.properties.as_ref()
+    }
+
     pub(crate) fn iter(&self) -> crate::Result<TableIterator> {
-        let current = match self.get_block(0) {
-            Ok(Some(c)) => Box::new(c.into_iter()),
-            _ => return Err(Error::InvalidIterator),
+        self.iter_with_bounds(Arc::default())
+    }
+
+    /// iter_with_bounds returns an iterator over the entries of the table within `bounds`, at
+    /// the first of them.
+    pub(crate) fn iter_with_bounds(&self, bounds: Arc<IterBounds>) -> crate::Result<TableIterator> {
+        let cursor = match &bounds.lower {
+            Some(lower) => self.index().find_target_key_block(lower)?,
+            None => 0,
         };
-        Ok(TableIterator {
-            cursor: 0,
+        let current = match self.get_block(cursor)? {
+            Some(c) => Box::new(BlockIterator::new(c)),
+            None => return Err(Error::InvalidIterator),
+        };
+        let mut iter = TableIterator {
+            cursor: cursor as isize,
             table: self.inner.clone(),
             current,
+            bounds,
+            exhausted: false,
             error: None,
-        })
+        };
+        if let Some(lower) = iter.bounds.clone().lower.as_deref() {
+            iter.seek_in_block(lower);
+        }
+        iter.check_bounds();
+        Ok(iter)
     }
 }
 
@@ -157,20 +307,113 @@ pub(crate) struct TableIterator {
     cursor: isize,
     table: Arc<InnerTable>,
     current: Box<BlockIterator>,
+    bounds: Arc<IterBounds>,
+    // whether the iterator stopped at an entry or block out of bounds
+    exhausted: bool,
     error: Option<Error>,
 }
 
 impl TableIterator {
     fn reset(&mut self) {
-        if !self.valid() {
+        if !Iter::valid(self) {
             return;
         }
         match self.table.get_block(self.cursor as usize) {
             Ok(Some(c)) => {
-                self.current = Box::new(c.into_iter());
+                *self.current = BlockIterator::new(c);
+                if let Err(err) = self.current.status() {
+                    self.error = Some(err);
+                }
             }
-            _ => self.error = Some(Error::InvalidIterator),
+            Ok(None) => self.error = Some(Error::InvalidIterator),
+            Err(err) => self.error = Some(err),
+        };
+    }
+
+    fn cmp(&self) -> &dyn Comparator {
+        &**self.table.opts.comparator
+    }
+
+    /// base_key returns the first key of block `at` from the index, without reading the block.
+    fn base_key(&self, at: isize) -> Option<&[u8]> {
+        let block_index = self.table.index().get_block_index(usize::try_from(at).ok()?)?;
+        Some(block_index.base_key)
+    }
+
+    /// next_block moves to the first entry of the next block, unless the block starts past the
+    /// upper bound, in which case it isn't read.
+    fn next_block(&mut self) {
+        self.cursor += 1;
+        let past_upper = match self.base_key(self.cursor) {
+            Some(base_key) => self.bounds.past_upper(self.cmp(), base_key),
+            None => Ok(false),
         };
+        match past_upper {
+            Ok(false) => self.reset(),
+            Ok(true) => self.exhausted = true,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// prev_block moves to the last entry of the previous block, unless the current one starts
+    /// before the lower bound, which leaves everything before it out of bounds.
+    fn prev_block(&mut self) {
+        let before_lower = match self.base_key(self.cursor) {
+            Some(base_key) => self.bounds.before_lower(self.cmp(), base_key),
+            None => Ok(false),
+        };
+        match before_lower {
+            Ok(false) => {
+                self.cursor -= 1;
+                self.reset();
+                self.current.seek_to_last();
+            }
+            Ok(true) => self.exhausted = true,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// seek_in_block moves to the first entry at or after `target` from the block at the
+    /// cursor, which is the last one starting at or before it.
+    fn seek_in_block(&mut self, target: &[u8]) {
+        if let Err(err) = self.current.seek(target) {
+            self.error = Some(err);
+            return;
+        }
+        if !self.current.valid() {
+            // everything in the block sorts before target, which makes it the first entry of
+            // the next block
+            self.next_block();
+        }
+    }
+
+    /// check_bounds stops the iterator at an entry out of bounds.
+    fn check_bounds(&mut self) {
+        let Some((key, _)) = self.entry() else {
+            return;
+        };
+        match self.bounds.contains(self.cmp(), key) {
+            Ok(in_bounds) => self.exhausted = !in_bounds,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// status returns the error that stopped the iterator, if any. The iterator is invalid
+    /// after an error, which is otherwise indistinguishable from reaching the end.
+    pub(crate) fn status(&mut self) -> crate::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// entry returns the current entry in place, where `current` copies it. The value is read
+    /// from the mapped table, or from the block it was decompressed into.
+    pub(crate) fn entry(&self) -> Option<(&[u8], &[u8])> {
+        if !Iter::valid(self) {
+            return None;
+        }
+        self.current.current()
     }
 }
 
@@ -179,7 +422,8 @@ impl Iter for TableIterator {
 
     fn valid(&self) -> bool {
         if self.cursor < 0
-            || self.cursor > self.table.block_count() as isize - 1
+            || self.cursor >= self.table.block_count() as isize
+            || self.exhausted
             || self.error.is_some()
         {
             return false;
@@ -190,70 +434,166 @@ impl Iter for TableIterator {
     fn prev(&mut self) {
         self.current.prev();
         if !self.current.valid() {
-            self.cursor -= 1;
-            self.reset();
-            return;
+            if let Err(err) = self.current.status() {
+                self.error = Some(err);
+                return;
+            }
+            self.prev_block();
         }
+        self.check_bounds();
     }
 
     fn next(&mut self) {
         self.current.next();
         if !self.current.valid() {
-            self.cursor += 1;
-            self.reset();
-            return;
+            if let Err(err) = self.current.status() {
+                self.error = Some(err);
+                return;
+            }
+            self.next_block();
         }
+        self.check_bounds();
     }
 
     fn current(&self) -> Option<Self::Item> {
-        self.current
-            .current()
+        self.entry()
             .map(|(k, v)| (k.to_vec().into_boxed_slice(), v.to_vec().into_boxed_slice()))
     }
 
+    /// seek moves to the first entry at or after `target`, or the lower bound if it's later.
     fn seek(&mut self, target: &[u8]) {
-        let index = match self.table.index() {
-            Ok(index) => index,
+        self.exhausted = false;
+        let bounds = self.bounds.clone();
+        let target = match bounds.clamp(self.cmp(), target) {
+            Ok(target) => target,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        self.cursor = match self.table.index().find_target_key_block(target) {
+            Ok(cursor) => cursor as isize,
             Err(err) => {
                 self.error = Some(err);
                 return;
             }
         };
-        self.cursor = index.find_target_key_block(target) as isize;
         self.reset();
-        self.current.seek(target)
+        self.seek_in_block(target);
+        self.check_bounds();
     }
 
     fn seek_to_first(&mut self) {
+        if let Some(lower) = self.bounds.clone().lower.as_deref() {
+            return Iter::seek(self, lower);
+        }
+        self.exhausted = false;
         self.cursor = 0;
         self.reset();
+        self.check_bounds();
     }
 
     fn seek_to_last(&mut self) {
-        self.cursor = (self.table.block_count() - 1) as isize;
-        self.reset()
+        self.exhausted = false;
+        let bounds = self.bounds.clone();
+        let Some(upper) = bounds.upper.as_deref() else {
+            self.cursor = self.table.block_count() as isize - 1;
+            self.reset();
+            self.current.seek_to_last();
+            self.check_bounds();
+            return;
+        };
+        // the last entry before the first one at or after the upper bound, which is in the
+        // last block starting at or before it or the one before
+        self.cursor = match self.table.index().find_target_key_block(upper) {
+            Ok(cursor) => cursor as isize,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        self.reset();
+        if let Err(err) = self.current.seek(upper) {
+            self.error = Some(err);
+            return;
+        }
+        if self.current.valid() {
+            Iter::prev(self);
+        } else {
+            self.current.seek_to_last();
+            self.check_bounds();
+        }
+    }
+}
+
+impl InternalIterator for TableIterator {
+    fn valid(&self) -> bool {
+        Iter::valid(self)
+    }
+
+    fn key(&self) -> &[u8] {
+        self.entry().map_or(&[], |(key, _)| key)
+    }
+
+    fn value(&self) -> &[u8] {
+        self.entry().map_or(&[], |(_, value)| value)
+    }
+
+    fn next(&mut self) {
+        Iter::next(self)
+    }
+
+    fn prev(&mut self) {
+        Iter::prev(self)
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        Iter::seek(self, target)
+    }
+
+    fn seek_to_first(&mut self) {
+        Iter::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) {
+        Iter::seek_to_last(self)
+    }
+
+    fn status(&mut self) -> crate::Result<()> {
+        TableIterator::status(self)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Write;
     use std::sync::Arc;
 
     use memmap2::Mmap;
     use tempfile::tempfile;
 
+    use crate::block::BlockIterator;
     use crate::bloom::BloomFilterPolicy;
-    use crate::iter::Iter;
-    use crate::table::{InnerTable, Table, TableOptions};
+    use crate::cmp::InternalKeyComparator;
+    use crate::footer::Footer;
+    use crate::block_cache::BlockCache;
+    use crate::iter::{Iter, IterBounds};
+    use crate::table::{
+        CompressionType, FilterMode, InnerTable, Table, TableIterator, TableOptions,
+        LATEST_FORMAT_VERSION, TABLE_MAGIC,
+    };
+    use crate::table_filter::TableFilter;
     use crate::table_builder::TableBuilder;
+    use crate::types::{internal_key, parse_internal_key, ValueType, MAX_SEQUENCE};
+    use crate::Error;
 
     fn table_opts() -> Arc<TableOptions> {
         Arc::new(TableOptions {
             block_size: 11 * 5,
             table_size: 2 << 20,
-            table_capacity: ((2_u64 << 20_u64) as f64 / 0.9) as usize,
             checksum: true,
             policy: BloomFilterPolicy::new(10),
+            ..Default::default()
         })
     }
 
@@ -278,35 +618,166 @@ mod test {
         //file.sync_all().unwrap();
 
         let data = unsafe { Mmap::map(&file).unwrap() };
-        let table = InnerTable::open(12, data, opts.clone());
-        let index = table.index().unwrap();
-        println!("block index {:?}", index.find_key_block(&[97, 98, 113]));
+        let table = InnerTable::open(12, data, opts.clone()).unwrap();
         println!("---------------------------------------------------------------");
-        println!("block index {:?}", index.find_key_block(b"abr"));
-        println!("Value {:?}", table.get(b"abr"));
+        assert_eq!(
+            table.get(b"abr").unwrap(),
+            Some((b"abr".to_vec(), b"abr".to_vec()))
+        );
         println!("---------------------------------------------------------------");
-        println!("block index {:?}", index.find_key_block(b"aba"));
-        println!("Value {:?}", table.get(b"aba"));
+        assert_eq!(
+            table.get(b"aba").unwrap(),
+            Some((b"aba".to_vec(), b"aba".to_vec()))
+        );
         println!("---------------------------------------------------------------");
-        println!("block index {:?}", index.find_key_block(b"zzz"));
 
         println!("---------------------------------------------------------------");
         let block = table.get_block(0).unwrap().unwrap();
         println!("iter block {:?}", block);
-        let mut block_iter = block.into_iter();
+        let mut block_iter = BlockIterator::new(block);
         while block_iter.valid() {
             block_iter.next();
             println!("{:?}", block_iter.current())
         }
     }
 
+    #[test]
+    fn test_get_internal_keys() {
+        let mut file = tempfile().unwrap();
+        let opts = Arc::new(TableOptions {
+            block_size: 64,
+            comparator: Arc::new(Box::new(InternalKeyComparator)),
+            ..Default::default()
+        });
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        for i in 0..50_u32 {
+            let key = format!("key{:03}", i);
+            // two versions of every key, the newer one first
+            for seq in [i * 2 + 1, i * 2] {
+                let ikey = internal_key(key.as_bytes(), seq as u64, ValueType::Value);
+                builder.add(&ikey, format!("{}", seq).as_bytes()).unwrap();
+            }
+        }
+        builder.finish().unwrap();
+
+        let table = Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap();
+        assert!(table.index().blocks_count() > 1);
+        for i in 0..50_u32 {
+            let key = format!("key{:03}", i);
+            let lookup = internal_key(key.as_bytes(), MAX_SEQUENCE, ValueType::Value);
+            let (ikey, value) = table.get(&lookup).unwrap().unwrap();
+            assert_eq!(
+                parse_internal_key(&ikey).unwrap(),
+                (key.as_bytes(), (i * 2 + 1) as u64, ValueType::Value)
+            );
+            assert_eq!(value, format!("{}", i * 2 + 1).as_bytes());
+
+            let lookup = internal_key(key.as_bytes(), (i * 2) as u64, ValueType::Value);
+            let (_, value) = table.get(&lookup).unwrap().unwrap();
+            assert_eq!(value, format!("{}", i * 2).as_bytes());
+        }
+        let lookup = internal_key(b"key999", MAX_SEQUENCE, ValueType::Value);
+        assert_eq!(table.get(&lookup).unwrap(), None);
+    }
+
+    #[test]
+    fn test_iter_backward_across_blocks() {
+        let mut file = tempfile().unwrap();
+        let opts = Arc::new(TableOptions {
+            block_size: 64,
+            ..Default::default()
+        });
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        let keys: Vec<String> = (0..50).map(|i| format!("key{:03}", i)).collect();
+        for key in &keys {
+            builder.add(key.as_bytes(), b"value").unwrap();
+        }
+        builder.finish().unwrap();
+
+        let table = Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap();
+        assert!(table.index().blocks_count() > 1);
+        let mut iter = table.iter().unwrap();
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while let Some((key, _)) = iter.entry() {
+            backward.push(String::from_utf8(key.to_vec()).unwrap());
+            iter.prev();
+        }
+        backward.reverse();
+        assert_eq!(backward, keys);
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn test_iter_with_bounds() {
+        let mut file = tempfile().unwrap();
+        let opts = Arc::new(TableOptions {
+            block_size: 64,
+            block_cache: Some(Arc::new(BlockCache::new(1 << 20, 64))),
+            ..Default::default()
+        });
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        let keys: Vec<String> = (0..50).map(|i| format!("key{:03}", i)).collect();
+        for key in &keys {
+            builder.add(key.as_bytes(), b"value").unwrap();
+        }
+        builder.finish().unwrap();
+        let table = Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts.clone()).unwrap();
+        let collect = |iter: &mut TableIterator, forward: bool| {
+            let mut found = Vec::new();
+            while let Some((key, _)) = iter.entry() {
+                found.push(String::from_utf8(key.to_vec()).unwrap());
+                if forward {
+                    iter.next();
+                } else {
+                    iter.prev();
+                }
+            }
+            assert!(iter.status().is_ok());
+            found
+        };
+
+        let bounds = Arc::new(IterBounds {
+            lower: Some(b"key010".to_vec()),
+            upper: Some(b"key030".to_vec()),
+        });
+        let mut iter = table.iter_with_bounds(bounds).unwrap();
+        assert_eq!(collect(&mut iter, true), keys[10..30]);
+        iter.seek_to_last();
+        let mut backward = collect(&mut iter, false);
+        backward.reverse();
+        assert_eq!(backward, keys[10..30]);
+        // seeks clamp to the lower bound, and stop at the upper one
+        iter.seek(b"key000");
+        assert_eq!(iter.entry().unwrap().0, b"key010");
+        iter.seek(b"key030");
+        assert!(iter.entry().is_none());
+
+        // a block starting at the upper bound isn't read
+        assert!(table.index().blocks_count() > 3);
+        let upper = table.index().get_block_index(2).unwrap().base_key.to_vec();
+        let reads = || {
+            let stats = opts.block_cache.as_ref().unwrap().stats();
+            stats.hits + stats.misses
+        };
+        let before = reads();
+        let bounds = Arc::new(IterBounds {
+            lower: None,
+            upper: Some(upper.clone()),
+        });
+        let mut iter = table.iter_with_bounds(bounds).unwrap();
+        let found = collect(&mut iter, true);
+        assert_eq!(keys[found.len()].as_bytes(), upper.as_slice());
+        assert_eq!(reads() - before, 2);
+    }
+
     fn table_opts_mid() -> Arc<TableOptions> {
         Arc::new(TableOptions {
             block_size: 11 * 20,
             table_size: 2 << 20,
-            table_capacity: ((2_u64 << 20_u64) as f64 / 0.9) as usize,
             checksum: true,
             policy: BloomFilterPolicy::new(10),
+            ..Default::default()
         })
     }
 
@@ -331,7 +802,7 @@ mod test {
         //file.sync_all().unwrap();
 
         let data = unsafe { Mmap::map(&file).unwrap() };
-        let table = Table::open(12, data, opts.clone());
+        let table = Table::open(12, data, opts.clone()).unwrap();
         let mut table_iter = table.iter().unwrap();
 
         // table_iter.next();
@@ -354,4 +825,265 @@ mod test {
             table_iter.prev();
         }
     }
+
+    #[test]
+    fn test_compressed_blocks() {
+        let build = |compression, min_compression_saving| {
+            let mut file = tempfile().unwrap();
+            let opts = Arc::new(TableOptions {
+                block_size: 1024,
+                compression,
+                min_compression_saving,
+                ..Default::default()
+            });
+            let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+            for i in 0..500_u32 {
+                let key = format!("key{:05}", i);
+                builder.add(key.as_bytes(), key.repeat(8).as_bytes()).unwrap();
+            }
+            builder.finish().unwrap();
+            let len = file.metadata().unwrap().len();
+            (Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap(), len)
+        };
+        let (_, raw_len) = build(CompressionType::None, 0.125);
+        for compression in [
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let (table, len) = build(compression, 0.125);
+            assert!(len < raw_len / 2, "{:?}", compression);
+            for i in (0..500_u32).step_by(7) {
+                let key = format!("key{:05}", i);
+                assert_eq!(
+                    table.get(key.as_bytes()).unwrap(),
+                    Some((key.as_bytes().to_vec(), key.repeat(8).into_bytes()))
+                );
+            }
+            let mut iter = table.iter().unwrap();
+            let mut count = 0;
+            while iter.valid() {
+                count += 1;
+                iter.next();
+            }
+            assert_eq!(count, 500);
+
+            // blocks that don't compress well enough are stored raw
+            let (table, len) = build(compression, 0.99);
+            assert_eq!(len, raw_len);
+            assert!(table.get(b"key00042").unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_iter_borrows_file() {
+        let mut file = tempfile().unwrap();
+        let opts = table_opts_mid();
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        for i in 0..100_u32 {
+            let key = format!("key{:03}", i);
+            builder.add(key.as_bytes(), key.repeat(4).as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+        let table = Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap();
+        let mapped = table.inner.data.as_ptr_range();
+
+        // values of uncompressed blocks are read in place, across blocks
+        let mut iter = table.iter().unwrap();
+        let mut count = 0;
+        while let Some((key, value)) = iter.entry() {
+            assert_eq!(value, key.repeat(4).as_slice());
+            assert!(mapped.contains(&value.as_ptr()));
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 100);
+        assert!(table.index().blocks_count() > 1);
+
+        // the iterator holds on to the file after the table is dropped
+        let mut iter = table.iter().unwrap();
+        drop(table);
+        iter.seek(b"key050");
+        let (key, value) = iter.entry().unwrap();
+        assert_eq!((key, value), (&b"key050"[..], &b"key050".repeat(4)[..]));
+    }
+
+    #[test]
+    fn test_format_versions() {
+        let keys: Vec<String> = (0..300_u32)
+            .map(|i| format!("tenant/{:03}/user/{:06}", i % 3, i))
+            .collect();
+        let mut sizes = Vec::new();
+        for format_version in [0, 1, 2, 3] {
+            let mut file = tempfile().unwrap();
+            let opts = Arc::new(TableOptions {
+                block_size: 512,
+                format_version,
+                ..Default::default()
+            });
+            let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+            let mut sorted = keys.clone();
+            sorted.sort();
+            for key in sorted.iter() {
+                builder.add(key.as_bytes(), b"v").unwrap();
+            }
+            builder.finish().unwrap();
+            let data = unsafe { Mmap::map(&file).unwrap() };
+            sizes.push(data.len());
+            // tables of version 0 end with the index like before versions existed
+            let magic = u64::from_le_bytes(data[data.len() - 8..].try_into().unwrap());
+            assert_eq!(magic == TABLE_MAGIC, format_version > 0);
+
+            // read with options for the latest format, as a database opened after an upgrade
+            let table = Table::open(1, data, Arc::new(TableOptions::default())).unwrap();
+            for key in keys.iter() {
+                assert_eq!(
+                    table.get(key.as_bytes()).unwrap().unwrap().0,
+                    key.as_bytes()
+                );
+            }
+            let mut iter = table.iter().unwrap();
+            for key in sorted.iter() {
+                assert_eq!(&*iter.current().unwrap().0, key.as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            assert!(iter.status().is_ok());
+        }
+        assert!(sizes[2] < sizes[1] && sizes[1] < sizes[0]);
+    }
+
+    #[test]
+    fn test_properties() {
+        let build = |format_version| {
+            let mut file = tempfile().unwrap();
+            let opts = Arc::new(TableOptions {
+                block_size: 256,
+                comparator: Arc::new(Box::new(InternalKeyComparator)),
+                compression: CompressionType::Snappy,
+                format_version,
+                ..Default::default()
+            });
+            let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+            for i in 0..100_u64 {
+                let key = format!("key{:03}", i);
+                let (vtype, value) = match i % 10 {
+                    0 => (ValueType::Deletion, String::new()),
+                    _ => (ValueType::Value, format!("value{}", i)),
+                };
+                let ikey = internal_key(key.as_bytes(), 1000 - i, vtype);
+                builder.add(&ikey, value.as_bytes()).unwrap();
+            }
+            builder.finish().unwrap();
+            Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap()
+        };
+
+        let table = build(LATEST_FORMAT_VERSION);
+        let props = table.properties().unwrap();
+        assert_eq!(props.entry_count, 100);
+        assert_eq!(props.deletion_count, 10);
+        assert_eq!(props.raw_key_size, 100 * (6 + 8));
+        assert_eq!(props.raw_value_size, 9 * 6 + 81 * 7);
+        assert_eq!((props.min_sequence, props.max_sequence), (901, 1000));
+        assert_eq!(
+            props.smallest_key,
+            internal_key(b"key000", 1000, ValueType::Deletion)
+        );
+        assert_eq!(
+            props.largest_key,
+            internal_key(b"key099", 901, ValueType::Value)
+        );
+        assert_eq!(props.compression, CompressionType::Snappy);
+        assert_eq!(props.comparator, "beardb.InternalKeyComparator");
+        assert!(props.data_size > 0 && props.data_size < props.raw_key_size + props.raw_value_size);
+        assert!(props.creation_time > 0);
+
+        // tables from before properties have none
+        assert!(build(2).properties().is_none());
+    }
+
+    #[test]
+    fn test_filter_modes() {
+        for filter_mode in [FilterMode::PerBlock, FilterMode::FullTable, FilterMode::Partitioned] {
+            let mut file = tempfile().unwrap();
+            let opts = Arc::new(TableOptions {
+                block_size: 256,
+                comparator: Arc::new(Box::new(InternalKeyComparator)),
+                filter_mode,
+                filter_partition_size: 64,
+                ..Default::default()
+            });
+            let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+            for i in 0..1000_u64 {
+                let key = format!("key{:05}", i * 2);
+                let ikey = internal_key(key.as_bytes(), i + 1, ValueType::Value);
+                builder.add(&ikey, key.as_bytes()).unwrap();
+            }
+            builder.finish().unwrap();
+            let table = Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts).unwrap();
+            match (&table.inner.filter, filter_mode) {
+                (TableFilter::PerBlock, FilterMode::PerBlock) => {}
+                (TableFilter::Full(_), FilterMode::FullTable) => {}
+                (TableFilter::Partitioned(partitions), FilterMode::Partitioned) => {
+                    assert!(partitions.len() > 10)
+                }
+                (filter, _) => panic!("{:?} table has {:?}", filter_mode, filter),
+            }
+
+            let lookup = |key: &str| internal_key(key.as_bytes(), MAX_SEQUENCE, ValueType::Value);
+            let mut false_positives = 0;
+            for i in 0..1000 {
+                let key = format!("key{:05}", i * 2);
+                let (found, value) = table.get(&lookup(&key)).unwrap().unwrap();
+                assert_eq!(parse_internal_key(&found).unwrap().0, key.as_bytes());
+                assert_eq!(value, key.as_bytes());
+
+                let missing = lookup(&format!("key{:05}", i * 2 + 1));
+                let inner = &table.inner;
+                if inner.filter.may_contain(&inner.data, &missing, &inner.opts).unwrap() {
+                    false_positives += 1;
+                }
+            }
+            match filter_mode {
+                FilterMode::PerBlock => assert_eq!(false_positives, 1000),
+                _ => assert!(false_positives < 50, "{:?}: {}", filter_mode, false_positives),
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_corrupt() {
+        let mut file = tempfile().unwrap();
+        let opts = table_opts();
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        for i in 0..100_u32 {
+            let key = format!("key{:05}", i);
+            builder.add(key.as_bytes(), key.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+        let data = unsafe { Mmap::map(&file).unwrap() }.to_vec();
+
+        let open = |bytes: &[u8]| {
+            let mut file = tempfile().unwrap();
+            file.write_all(bytes).unwrap();
+            Table::open(1, unsafe { Mmap::map(&file).unwrap() }, opts.clone())
+        };
+        assert!(open(&data).is_ok());
+        // truncated, foreign and empty files
+        for len in [0, 1, 7, 20, data.len() / 2, data.len() - 1] {
+            assert!(matches!(open(&data[..len]), Err(Error::Corruption(_))), "{}", len);
+        }
+        let foreign: Vec<u8> = (0..4096_u32).map(|i| ((i * 7919) >> 3) as u8).collect();
+        assert!(matches!(open(&foreign), Err(Error::Corruption(_))));
+        // a flipped bit in the index
+        let footer = Footer::decode(&data).unwrap();
+        let mut flipped = data.clone();
+        flipped[footer.index.offset as usize + 10] ^= 1;
+        assert!(matches!(open(&flipped), Err(Error::Corruption(_))));
+        // a table that claims a newer format
+        let mut newer = data.clone();
+        let at = newer.len() - 12;
+        newer[at..at + 4].copy_from_slice(&(LATEST_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(open(&newer), Err(Error::Corruption(_))));
+    }
 }
@@ -1,11 +1,18 @@
 use std::io::Write;
 use std::sync::Arc;
 
+use rkyv::AlignedVec;
+
 use crate::bloom;
-use crate::block::BlockBuilder;
-use crate::constant::{BLOCK_ENTRY_HEADER_SIZE, BLOCK_META_SIZE, CHECKSUM_SIZE};
-use crate::table::TableOptions;
+use crate::block::{write_block, BlockBuilder};
+use crate::constant::{
+    BLOCK_ENTRY_HEADER_SIZE, BLOCK_META_SIZE, CHECKSUM_SIZE, COMPRESSION_TYPE_SIZE,
+};
+use crate::footer::{BlockHandle, Footer, FOOTER_FORMAT_VERSION};
+use crate::table::{CompressionType, FilterMode, TableOptions, TABLE_MAGIC};
+use crate::table_filter::{FILTER_PARTITIONS_BLOCK, FULL_FILTER_BLOCK};
 use crate::table_index::TableIndexBuilder;
+use crate::table_properties::{TableProperties, PROPERTIES_BLOCK};
 
 pub(crate) struct TableBuilder<W> {
     dst: W,
@@ -14,28 +21,39 @@ pub(crate) struct TableBuilder<W> {
     blocks: Vec<u8>,
     filters: Vec<Vec<u8>>,
     index: Vec<u32>,
-    entries_offset: u32,
+    // the first key of each block, which the index locates blocks by
+    base_keys: Vec<Vec<u8>>,
     blocks_offset: u32,
     key_count: u32,
+    properties: TableProperties,
+    filter_mode: FilterMode,
+    // key hashes of the table or the current partition, with filters outside the index
+    filter_hashes: Vec<u32>,
+    // the last key and filter of each finished partition
+    filter_partitions: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
 impl<W> TableBuilder<W>
 where
     W: Write,
 {
-    pub(crate) fn new(dst: W) -> TableBuilder<W> {
-        let opt = Default::default();
-        TableBuilder::new_with_options(dst, opt)
-    }
-
     pub(crate) fn new_with_options(dst: W, opt: Arc<TableOptions>) -> TableBuilder<W> {
+        // older formats have no meta blocks to keep other filters in
+        let filter_mode = match opt.format_version >= FOOTER_FORMAT_VERSION {
+            true => opt.filter_mode,
+            false => FilterMode::PerBlock,
+        };
         Self {
             dst,
+            properties: TableProperties::new(&opt),
+            filter_mode,
+            filter_hashes: vec![],
+            filter_partitions: vec![],
             opt,
             current: Default::default(),
             blocks: vec![],
             index: vec![],
-            entries_offset: 0,
+            base_keys: vec![],
             blocks_offset: 0,
             filters: vec![],
             key_count: 0,
@@ -44,33 +62,77 @@ where
 
     pub(crate) fn add<'a>(&mut self, key: &'a [u8], value: &'a [u8]) -> crate::Result<()> {
         if self.should_finish_block(key, value) {
-            self.index.push(self.blocks_offset);
+            self.finish_block()?;
+        }
+        self.add_internal(key, value)
+    }
+
+    fn finish_block(&mut self) -> crate::Result<()> {
+        self.index.push(self.blocks_offset);
+        if self.filter_mode == FilterMode::PerBlock {
             self.filters.push(
                 self.opt
                     .policy
                     .create_filter_from_hashes(self.current.key_hashes.as_slice()),
             );
-            self.blocks_offset += self.current.finish(&mut self.blocks)? as u32;
+        } else {
+            self.filters.push(Vec::new());
+            self.filter_hashes
+                .extend_from_slice(&self.current.key_hashes);
+            let filter_len = self.opt.policy.filter_len(self.filter_hashes.len());
+            if self.filter_mode == FilterMode::Partitioned
+                && filter_len >= self.opt.filter_partition_size
+            {
+                self.finish_filter_partition();
+            }
         }
-        self.add_internal(key, value)
+        self.base_keys
+            .push(std::mem::take(&mut self.current.base_key));
+        self.blocks_offset += self.current.finish(&mut self.blocks, &self.opt)? as u32;
+        Ok(())
+    }
+
+    /// finish_filter_partition builds the filter of the blocks since the last partition, which
+    /// ends with the key added last.
+    fn finish_filter_partition(&mut self) {
+        let filter = self
+            .opt
+            .policy
+            .create_filter_from_hashes(&self.filter_hashes);
+        self.filter_hashes.clear();
+        self.filter_partitions
+            .push((self.properties.largest_key.clone(), filter));
+    }
+
+    /// write_meta_block appends a block of raw `data` after the blocks so far.
+    fn write_meta_block(&mut self, data: &[u8]) -> crate::Result<BlockHandle> {
+        let offset = self.blocks.len() as u64;
+        // filters are read in place, so they're never compressed
+        let size = write_block(&mut self.blocks, data, CompressionType::None, &self.opt)?;
+        Ok(BlockHandle {
+            offset,
+            size: size as u64,
+        })
+    }
+
+    fn finish_meta_block(&mut self, mut block: BlockBuilder) -> crate::Result<BlockHandle> {
+        let offset = self.blocks.len() as u64;
+        let size = block.finish(&mut self.blocks, &self.opt)?;
+        Ok(BlockHandle {
+            offset,
+            size: size as u64,
+        })
     }
 
     fn add_internal<'a>(&mut self, key: &'a [u8], value: &'a [u8]) -> crate::Result<()> {
         let block = &mut self.current;
-        let mut written_bytes = 0_usize;
-        block.entry_offsets.push(block.entries_offset);
-        written_bytes += block
-            .data
-            .write((key.len() as u32).to_le_bytes().as_slice())?;
-        written_bytes += block
-            .data
-            .write((value.len() as u32).to_le_bytes().as_slice())?;
-        written_bytes += block.data.write(key)?;
-        written_bytes += block.data.write(value)?;
-        block.key_hashes.push(bloom::bloom_hash(key));
-        block.entry_count += 1;
-        block.entries_offset += written_bytes as u32;
+        block.add(key, value, &self.opt);
+        block
+            .key_hashes
+            .push(bloom::bloom_hash(self.opt.comparator.user_key(key)));
         self.key_count += 1;
+        self.properties
+            .add(key, value, self.opt.comparator.tag(key));
         Ok(())
     }
 
@@ -83,33 +145,103 @@ where
             + BLOCK_ENTRY_HEADER_SIZE
             + key.len()
             + value.len()
-            + (block.entry_offsets.len() * 4)
+            + (block.restarts.len() * 4)
             + BLOCK_META_SIZE
+            + COMPRESSION_TYPE_SIZE
             + CHECKSUM_SIZE;
         est_block_size > self.opt.block_size
     }
 
-    pub(crate) fn finish(mut self) -> crate::Result<()> {
+    /// estimated_size is the size of the table if it were finished now.
+    pub(crate) fn estimated_size(&self) -> usize {
+        self.blocks.len() + self.current.data.len()
+    }
+
+    pub(crate) fn finish(mut self) -> crate::Result<W> {
         if !self.current.data.is_empty() {
-            self.index.push(self.blocks_offset);
-            self.filters.push(
-                self.opt
-                    .policy
-                    .create_filter_from_hashes(self.current.key_hashes.as_slice()),
-            );
-            let _ = self.current.finish(&mut self.blocks)?;
+            self.finish_block()?;
         }
-        let index = TableIndexBuilder::new(&self.blocks, &self.index, self.filters, self.key_count);
+        if self.filter_mode == FilterMode::Partitioned && !self.filter_hashes.is_empty() {
+            self.finish_filter_partition();
+        }
+        let index = TableIndexBuilder::new(
+            self.blocks.len(),
+            &self.index,
+            std::mem::take(&mut self.base_keys),
+            std::mem::take(&mut self.filters),
+            self.key_count,
+        );
         let index_block = index.finish()?;
         let index_block_size = index_block.len() as u32;
         let crc = crc32fast::hash(&index_block);
+        let mut metaindex = None;
+        if self.opt.format_version >= FOOTER_FORMAT_VERSION {
+            self.properties.data_size = self.blocks.len() as u64;
+            let mut meta_blocks = Vec::new();
+            match self.filter_mode {
+                FilterMode::PerBlock => {}
+                FilterMode::FullTable => {
+                    let filter = self
+                        .opt
+                        .policy
+                        .create_filter_from_hashes(&self.filter_hashes);
+                    meta_blocks.push((FULL_FILTER_BLOCK, self.write_meta_block(&filter)?));
+                }
+                FilterMode::Partitioned => {
+                    let mut partition_index = BlockBuilder::new();
+                    for (last_key, filter) in std::mem::take(&mut self.filter_partitions) {
+                        let mut handle = Vec::new();
+                        self.write_meta_block(&filter)?.encode_to(&mut handle);
+                        partition_index.add(&last_key, &handle, &self.opt);
+                    }
+                    let handle = self.finish_meta_block(partition_index)?;
+                    meta_blocks.push((FILTER_PARTITIONS_BLOCK, handle));
+                }
+            }
+            let mut properties_block = BlockBuilder::new();
+            self.properties
+                .encode_to(&mut properties_block, &self.opt);
+            meta_blocks.push((PROPERTIES_BLOCK, self.finish_meta_block(properties_block)?));
+
+            // maps the names of meta blocks to their handles
+            let mut metaindex_block = BlockBuilder::new();
+            meta_blocks.sort_by_key(|(name, _)| *name);
+            for (name, handle) in meta_blocks {
+                let mut encoded = Vec::new();
+                handle.encode_to(&mut encoded);
+                metaindex_block.add(name, &encoded, &self.opt);
+            }
+            metaindex = Some(self.finish_meta_block(metaindex_block)?);
+        }
+        // the index is read in place from the mapped file, so it has to start at an offset the
+        // archive is aligned for
+        let padding = self.blocks.len().next_multiple_of(AlignedVec::ALIGNMENT) - self.blocks.len();
+        self.blocks.resize(self.blocks.len() + padding, 0);
         self.dst.write_all(self.blocks.as_slice())?;
         self.dst.write_all(&index_block)?;
-        self.dst
-            .write_all(index_block_size.to_le_bytes().as_slice())?;
-        self.dst.write_all(crc.to_le_bytes().as_slice())?;
+        if self.opt.format_version >= FOOTER_FORMAT_VERSION {
+            let footer = Footer {
+                metaindex,
+                index: BlockHandle {
+                    offset: self.blocks.len() as u64,
+                    size: index_block.len() as u64,
+                },
+                index_checksum: crc,
+                format_version: self.opt.format_version,
+            };
+            self.dst.write_all(&footer.encode())?;
+        } else {
+            self.dst
+                .write_all(index_block_size.to_le_bytes().as_slice())?;
+            self.dst.write_all(crc.to_le_bytes().as_slice())?;
+            if self.opt.format_version > 0 {
+                self.dst
+                    .write_all(self.opt.format_version.to_le_bytes().as_slice())?;
+                self.dst.write_all(TABLE_MAGIC.to_le_bytes().as_slice())?;
+            }
+        }
         self.dst.flush()?;
-        Ok(())
+        Ok(self.dst)
     }
 }
 
@@ -125,9 +257,9 @@ mod test {
         Arc::new(TableOptions {
             block_size: 11 * 5,
             table_size: 2 << 20,
-            table_capacity: ((2_u64 << 20_u64) as f64 / 0.9) as usize,
             checksum: false,
             policy: BloomFilterPolicy::new(10),
+            ..Default::default()
         })
     }
     #[test]
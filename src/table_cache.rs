@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::env::Env;
+use crate::filename::table_file_name;
+use crate::table::{Table, TableOptions};
+
+/// TableCache keeps the `max_open_files` most recently read tables of a database open, opening
+/// the others by number when they're read. The table being read is always among them, so it's
+/// kept open for the next read.
+///
+/// It's a plain LRU rather than an `rcache::Cache`: rcache's admission policy may turn a newly
+/// opened table away, and a table it keeps refusing is mapped and parsed again on every read.
+///
+/// The cache only bounds the tables it holds itself. A table it lets go of stays mapped until
+/// the last reader drops it: iterators, compactions and gets in progress, and uncompressed
+/// blocks of the table in the block cache. So a database maps `max_open_files` tables plus
+/// those its readers still pin, and a pinned table read again is mapped a second time.
+pub(crate) struct TableCache {
+    dir: PathBuf,
+    env: Arc<dyn Env>,
+    opts: Arc<TableOptions>,
+    max_open_files: usize,
+    open: Mutex<OpenTables>,
+}
+
+#[derive(Default)]
+struct OpenTables {
+    // each open table with the tick it was last read at
+    tables: HashMap<u64, (Table, u64)>,
+    // the numbers of the open tables by the tick they were last read at, least recent first
+    by_use: BTreeMap<u64, u64>,
+    tick: u64,
+}
+
+impl OpenTables {
+    /// touch marks table `number`, which is open, as the most recently read.
+    fn touch(&mut self, number: u64) -> Option<Table> {
+        let (table, last_use) = self.tables.get_mut(&number)?;
+        self.by_use.remove(last_use);
+        self.tick += 1;
+        *last_use = self.tick;
+        self.by_use.insert(self.tick, number);
+        Some(table.clone())
+    }
+
+    fn remove(&mut self, number: u64) {
+        if let Some((_, last_use)) = self.tables.remove(&number) {
+            self.by_use.remove(&last_use);
+        }
+    }
+}
+
+impl TableCache {
+    /// new returns a cache of the tables in `dir` that holds at most `max_open_files` of them
+    /// open, and at least one.
+    pub(crate) fn new(
+        dir: PathBuf,
+        env: Arc<dyn Env>,
+        opts: Arc<TableOptions>,
+        max_open_files: usize,
+    ) -> Self {
+        Self {
+            dir,
+            env,
+            opts,
+            max_open_files: max_open_files.max(1),
+            open: Mutex::new(OpenTables::default()),
+        }
+    }
+
+    /// get returns table `number`, opening it if it isn't open, in which case the least
+    /// recently read table is closed if the cache is full.
+    pub(crate) fn get(&self, number: u64) -> crate::Result<Table> {
+        if let Some(table) = self.open.lock().touch(number) {
+            return Ok(table);
+        }
+        let file = self.env.map_file(&table_file_name(&self.dir, number))?;
+        let table = Table::open(number, file, self.opts.clone())?;
+
+        let mut open = self.open.lock();
+        // another reader may have opened it meanwhile
+        if let Some(table) = open.touch(number) {
+            return Ok(table);
+        }
+        open.tick += 1;
+        let tick = open.tick;
+        open.tables.insert(number, (table.clone(), tick));
+        open.by_use.insert(tick, number);
+        while open.tables.len() > self.max_open_files {
+            let (_, oldest) = open.by_use.pop_first().unwrap();
+            open.tables.remove(&oldest);
+        }
+        Ok(table)
+    }
+
+    /// evict closes table `number`, which is about to be deleted, once its readers are done.
+    pub(crate) fn evict(&self, number: u64) {
+        self.open.lock().remove(number);
+    }
+
+    /// open_count returns the number of tables the cache keeps open.
+    #[cfg(test)]
+    pub(crate) fn open_count(&self) -> usize {
+        self.open.lock().tables.len()
+    }
+}
+
+impl Debug for TableCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TableCache")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::disk_env::PosixDiskEnv;
+    use crate::filename::{create_file, table_file_name};
+    use crate::iter::Iter;
+    use crate::table::TableOptions;
+    use crate::table_builder::TableBuilder;
+    use crate::table_cache::TableCache;
+
+    // build_tables writes tables 1 to 3 to `dir`, each with 10 keys.
+    fn build_tables(dir: &Path, opts: &Arc<TableOptions>) {
+        for number in 1..=3_u64 {
+            let mut file = create_file(&table_file_name(dir, number)).unwrap();
+            let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+            for i in 0..10 {
+                let key = format!("table{}-key{}", number, i);
+                builder.add(key.as_bytes(), b"value").unwrap();
+            }
+            builder.finish().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_get_evict() {
+        let dir = tempdir().unwrap();
+        let opts = Arc::new(TableOptions::default());
+        build_tables(dir.path(), &opts);
+        let env = Arc::new(PosixDiskEnv::new());
+        let cache = TableCache::new(dir.path().to_path_buf(), env.clone(), opts.clone(), 1);
+
+        // more tables than the cache keeps open are read alike
+        for _ in 0..3 {
+            for number in 1..=3_u64 {
+                let key = format!("table{}-key5", number);
+                let (found, _) = cache
+                    .get(number)
+                    .unwrap()
+                    .get(key.as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(found, key.as_bytes());
+                assert_eq!(cache.open_count(), 1);
+            }
+        }
+        assert!(cache.get(4).is_err());
+
+        // the least recently read table is the one closed
+        let lru = TableCache::new(dir.path().to_path_buf(), env, opts, 2);
+        for number in [1, 2, 1, 3] {
+            lru.get(number).unwrap();
+        }
+        assert_eq!(lru.open_count(), 2);
+        let moved = dir.path().join("moved");
+        for (number, open) in [(1, true), (2, false)] {
+            fs::rename(table_file_name(dir.path(), number), &moved).unwrap();
+            assert_eq!(lru.get(number).is_ok(), open);
+            fs::rename(&moved, table_file_name(dir.path(), number)).unwrap();
+        }
+
+        // an iterator keeps its table mapped after the cache let go of it and the file is gone
+        let mut iter = cache.get(2).unwrap().iter().unwrap();
+        cache.evict(2);
+        fs::remove_file(table_file_name(dir.path(), 2)).unwrap();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 10);
+        assert!(iter.status().is_ok());
+    }
+    #[test]
+    fn test_pinned_after_lru_close() {
+        let dir = tempdir().unwrap();
+        let opts = Arc::new(TableOptions::default());
+        build_tables(dir.path(), &opts);
+        let cache = TableCache::new(
+            dir.path().to_path_buf(),
+            Arc::new(PosixDiskEnv::new()),
+            opts,
+            1,
+        );
+
+        let mut iter = cache.get(1).unwrap().iter().unwrap();
+        iter.next();
+        // reading table 2 closes table 1 in the cache, but the iterator still maps it
+        cache.get(2).unwrap();
+        assert_eq!(cache.open_count(), 1);
+        let moved = dir.path().join("moved");
+        fs::rename(table_file_name(dir.path(), 1), &moved).unwrap();
+        assert!(cache.get(1).is_err());
+
+        // so the table is read to the end, even with its file gone
+        let mut count = 1;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 10);
+        assert!(iter.status().is_ok());
+
+        // read again, the table is opened anew
+        fs::rename(&moved, table_file_name(dir.path(), 1)).unwrap();
+        let table = cache.get(1).unwrap();
+        assert_eq!(cache.open_count(), 1);
+        assert!(table.get(b"table1-key5").unwrap().is_some());
+    }
+}
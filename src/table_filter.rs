@@ -0,0 +1,96 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::block::{read_block, Block};
+use crate::footer::BlockHandle;
+use crate::table::TableOptions;
+use crate::{ensure, Error};
+
+/// FULL_FILTER_BLOCK is the name of the filter of the whole table in the metaindex.
+pub(crate) const FULL_FILTER_BLOCK: &[u8] = b"beardb.filter.full";
+/// FILTER_PARTITIONS_BLOCK is the name of the index of filter partitions in the metaindex.
+pub(crate) const FILTER_PARTITIONS_BLOCK: &[u8] = b"beardb.filter.partitions";
+
+/// TableFilter is the filter a table was written with, which rules out user keys the table
+/// doesn't hold before its index is read.
+#[derive(Debug)]
+pub(crate) enum TableFilter {
+    /// Each block has its own filter in the index.
+    PerBlock,
+    /// A single filter for the table, read in place from this range of the file.
+    Full(Range<usize>),
+    /// The last key of each partition of the table with the handle of the partition filter.
+    /// A partition covers consecutive data blocks.
+    Partitioned(Vec<(Vec<u8>, BlockHandle)>),
+}
+
+impl TableFilter {
+    /// open locates the filter of `file` from its meta blocks. Filters are written
+    /// uncompressed, and the checksum of a full filter is verified once here.
+    pub(crate) fn open(
+        file: &[u8],
+        meta_blocks: &BTreeMap<Vec<u8>, BlockHandle>,
+        format_version: u32,
+        opts: &TableOptions,
+    ) -> crate::Result<Self> {
+        if let Some(handle) = meta_blocks.get(FULL_FILTER_BLOCK) {
+            let range = handle.range(file.len())?;
+            let (contents, _) = read_block(&file[range.clone()], opts)?;
+            ensure!(
+                matches!(contents, Cow::Borrowed(_)),
+                Error::Corruption("compressed table filter".to_string())
+            );
+            return Ok(TableFilter::Full(range.start..range.start + contents.len()));
+        }
+        if let Some(handle) = meta_blocks.get(FILTER_PARTITIONS_BLOCK) {
+            let range = handle.range(file.len())?;
+            let block = Block::open(range.start, &file[range], format_version, opts)?;
+            let mut iter = block.into_iter();
+            let mut partitions = Vec::new();
+            while let Some((last_key, handle)) = iter.current() {
+                partitions.push((last_key.to_vec(), BlockHandle::decode(handle)?));
+                iter.next();
+            }
+            iter.status()?;
+            return Ok(TableFilter::Partitioned(partitions));
+        }
+        Ok(TableFilter::PerBlock)
+    }
+
+    /// may_contain checks the filter for the user key of `key`. Tables with filters per block
+    /// leave that to the index.
+    pub(crate) fn may_contain(
+        &self,
+        file: &[u8],
+        key: &[u8],
+        opts: &TableOptions,
+    ) -> crate::Result<bool> {
+        let user_key = opts.comparator.user_key(key);
+        match self {
+            TableFilter::PerBlock => Ok(true),
+            TableFilter::Full(range) => {
+                Ok(opts.policy.key_and_match(user_key, &file[range.clone()]))
+            }
+            TableFilter::Partitioned(partitions) => {
+                // the entry at or after key, if the table has one, is in the first partition
+                // ending at or after it
+                let (mut low, mut high) = (0, partitions.len());
+                while low < high {
+                    let mid = low + (high - low) / 2;
+                    if opts.comparator.cmp(&partitions[mid].0, key)? == Ordering::Less {
+                        low = mid + 1;
+                    } else {
+                        high = mid;
+                    }
+                }
+                let Some((_, handle)) = partitions.get(low) else {
+                    return Ok(false);
+                };
+                let (filter, _) = read_block(&file[handle.range(file.len())?], opts)?;
+                Ok(opts.policy.key_and_match(user_key, &filter))
+            }
+        }
+    }
+}
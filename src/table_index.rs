@@ -1,11 +1,9 @@
 
-use crate::constant::BLOCK_ENTRY_HEADER_SIZE;
 use crate::table::TableOptions;
-use crate::{codec, Error};
+use crate::Error;
 use bytecheck::CheckBytes;
 use rkyv::{AlignedVec, Archive, Deserialize, Serialize};
-use std::slice::Iter as SliceIter;
-use std::sync::Arc;
+use std::cmp::Ordering;
 
 type BloomFilter = Vec<u8>;
 
@@ -35,20 +33,19 @@ pub(crate) struct TableIndexBuilder {
 
 impl TableIndexBuilder {
     fn create_table_offsets(
-        blocks: &Vec<u8>,
-        index: &Vec<u32>,
+        blocks_len: usize,
+        index: &[u32],
+        base_keys: Vec<Vec<u8>>,
         bloom_filter: Vec<BloomFilter>,
     ) -> Vec<BlockOffsetsIndex> {
         let mut offsets = Vec::new();
 
-        for (i, (start, filter)) in index.iter().zip(bloom_filter).enumerate() {
-            let array = &blocks[(*start as usize)..];
-            let key_size = codec::decode_fixed32(&array[..4]) as usize;
-            let base_key =
-                Vec::from(&array[BLOCK_ENTRY_HEADER_SIZE..BLOCK_ENTRY_HEADER_SIZE + key_size]);
+        for (i, ((start, base_key), filter)) in
+            index.iter().zip(base_keys).zip(bloom_filter).enumerate()
+        {
             let offset_start = *start;
             let offset_end = match index.get(i + 1) {
-                None => (blocks.len()) as u32,
+                None => blocks_len as u32,
                 Some(n) => *n,
             };
             let index = BlockOffsetsIndex {
@@ -62,19 +59,25 @@ impl TableIndexBuilder {
         offsets
     }
     pub(crate) fn new(
-        blocks: &Vec<u8>,
-        index: &Vec<u32>,
+        blocks_len: usize,
+        index: &[u32],
+        base_keys: Vec<Vec<u8>>,
         bloom_filter: Vec<BloomFilter>,
         key_count: u32,
     ) -> Self {
         Self {
             inner: TableIndex {
-                offsets: TableIndexBuilder::create_table_offsets(blocks, index, bloom_filter),
+                offsets: TableIndexBuilder::create_table_offsets(
+                    blocks_len,
+                    index,
+                    base_keys,
+                    bloom_filter,
+                ),
                 key_count,
             },
         }
     }
-    pub(crate) fn finish<'a>(self) -> crate::Result<AlignedVec> {
+    pub(crate) fn finish(self) -> crate::Result<AlignedVec> {
         let bytes = rkyv::to_bytes::<_, INDEX_BLOCK_SIZE>(&self.inner)
             .map_err(|e| Error::AnyError(Box::new(e)))?;
         Ok(bytes)
@@ -84,7 +87,7 @@ impl TableIndexBuilder {
 #[derive(Debug)]
 pub(crate) struct TableIndexReader<'a> {
     inner: &'a ArchivedTableIndex,
-    opts: Arc<TableOptions>,
+    opts: &'a TableOptions,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -96,16 +99,32 @@ pub(crate) struct BlockIndex<'a> {
 }
 
 impl<'a> TableIndexReader<'a> {
+    /// open validates the archived index in `data`, which walks all of it.
     pub(crate) fn open(
         data: &'a [u8],
-        opts: Arc<TableOptions>,
+        opts: &'a TableOptions,
     ) -> crate::Result<TableIndexReader<'a>> {
         let inner = rkyv::check_archived_root::<TableIndex>(data)
             .map_err(|err| Error::Corruption(format!("{}", err)))?;
         Ok(Self { inner, opts })
     }
 
-    pub(crate) fn get_block_index(&self, at: usize) -> Option<BlockIndex> {
+    /// open_unchecked reads an index without validating it.
+    ///
+    /// # Safety
+    ///
+    /// `data` must hold the same bytes `open` succeeded on before.
+    pub(crate) unsafe fn open_unchecked(
+        data: &'a [u8],
+        opts: &'a TableOptions,
+    ) -> TableIndexReader<'a> {
+        Self {
+            inner: rkyv::archived_root::<TableIndex>(data),
+            opts,
+        }
+    }
+
+    pub(crate) fn get_block_index(&self, at: usize) -> Option<BlockIndex<'a>> {
         self.inner.offsets.get(at).map(|index| BlockIndex {
             base_key: index.base_key.as_slice(),
             bloomfilter: index.filter.as_slice(),
@@ -114,62 +133,39 @@ impl<'a> TableIndexReader<'a> {
         })
     }
 
-    pub(crate) fn iter(&self) -> TableIndexIterator {
-        TableIndexIterator {
-            inner: self.inner.offsets.iter(),
-        }
-    }
-
     pub(crate) fn blocks_count(&self) -> usize {
         self.inner.offsets.len()
     }
 
+    #[cfg(test)]
     pub(crate) fn keys_count(&self) -> usize {
         self.inner.key_count as usize
     }
 
-    pub(crate) fn find_key_block(&self, key: &[u8]) -> Option<BlockIndex> {
-        self.get_block_index(self.find_target_key_block(key))
-            .and_then(|block_index| {
-                if self.opts.policy.key_and_match(key, block_index.bloomfilter) {
-                    Some(block_index)
-                } else {
-                    None
-                }
-            })
-    }
-
-    pub(crate) fn find_target_key_block(&self, target_key: &[u8]) -> usize {
-        let pos = self
-            .inner
-            .offsets
-            .partition_point(|t| target_key.ge(t.base_key.as_slice()));
-        pos - 1
-    }
-
-    pub(crate) fn contains_key(&self, key: &[u8]) -> bool {
-        self.find_key_block(key).is_some()
-    }
-}
-
-#[derive(Debug)]
-pub(crate) struct TableIndexIterator<'a> {
-    inner: SliceIter<'a, ArchivedBlockOffsetsIndex>,
-}
-
-impl<'a> Iterator for TableIndexIterator<'a> {
-    type Item = BlockIndex<'a>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|index| BlockIndex {
-            base_key: index.base_key.as_slice(),
-            bloomfilter: index.filter.as_slice(),
-            offset_start: index.offset_start as usize,
-            offset_end: index.offset_start as usize,
-        })
+    /// find_target_key_block returns the last block whose first key is at or before
+    /// `target_key`, or the first block when `target_key` sorts before all of them.
+    pub(crate) fn find_target_key_block(&self, target_key: &[u8]) -> crate::Result<usize> {
+        let cmp = &self.opts.comparator;
+        let (mut low, mut high) = (0, self.inner.offsets.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let base_key = self.inner.offsets[mid].base_key.as_slice();
+            if cmp.cmp(base_key, target_key)? == Ordering::Greater {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(low.saturating_sub(1))
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+    /// may_contain checks the block filter for the user key of `key`. Blocks of tables with
+    /// other filters have none.
+    pub(crate) fn may_contain(&self, block_index: &BlockIndex, key: &[u8]) -> bool {
+        block_index.bloomfilter.is_empty()
+            || self
+                .opts
+                .policy
+                .key_and_match(self.opts.comparator.user_key(key), block_index.bloomfilter)
     }
 }
@@ -0,0 +1,171 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::block::{BlockBuilder, BlockIterator};
+use crate::codec::{decode_varint64, encode_varint64};
+use crate::table::{CompressionType, TableOptions};
+use crate::types::{value_type_seq, ValueType};
+use crate::Error;
+
+/// PROPERTIES_BLOCK is the name the properties block is found by in the metaindex.
+pub(crate) const PROPERTIES_BLOCK: &[u8] = b"beardb.properties";
+
+/// TableProperties are statistics collected while a table is written. They're stored in the
+/// properties block as entries from a property name to its value, numbers as varints.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableProperties {
+    pub entry_count: u64,
+    pub deletion_count: u64,
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    /// Size of the data blocks as written, after compression.
+    pub data_size: u64,
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    /// Range of the sequences of the entries. Both are 0 when keys carry no sequence.
+    pub min_sequence: u64,
+    pub max_sequence: u64,
+    /// Seconds since the Unix epoch when the table was written.
+    pub creation_time: u64,
+    pub compression: CompressionType,
+    pub comparator: String,
+}
+
+impl TableProperties {
+    pub(crate) fn new(opts: &TableOptions) -> Self {
+        Self {
+            compression: opts.compression,
+            comparator: opts.comparator.name().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// add accounts for an entry added to the table, with the tag of its key if it has one.
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8], tag: Option<u64>) {
+        if self.entry_count == 0 {
+            self.smallest_key = key.to_vec();
+        }
+        self.largest_key.clear();
+        self.largest_key.extend_from_slice(key);
+        self.entry_count += 1;
+        self.raw_key_size += key.len() as u64;
+        self.raw_value_size += value.len() as u64;
+        if let Some(tag) = tag {
+            let (vtype, seq) = value_type_seq(tag);
+            if vtype == ValueType::Deletion {
+                self.deletion_count += 1;
+            }
+            if self.entry_count == 1 {
+                self.min_sequence = seq;
+            }
+            self.min_sequence = self.min_sequence.min(seq);
+            self.max_sequence = self.max_sequence.max(seq);
+        }
+    }
+
+    /// encode_to adds the properties to `block`, stamped with the current time.
+    pub(crate) fn encode_to(&mut self, block: &mut BlockBuilder, opts: &TableOptions) {
+        self.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let number = |v: u64| {
+            let mut buf = Vec::new();
+            encode_varint64(&mut buf, v);
+            buf
+        };
+        let mut entries: Vec<(&[u8], Vec<u8>)> = vec![
+            (b"beardb.comparator", self.comparator.as_bytes().to_vec()),
+            (b"beardb.compression", number(self.compression as u64)),
+            (b"beardb.creation.time", number(self.creation_time)),
+            (b"beardb.data.size", number(self.data_size)),
+            (b"beardb.deletions", number(self.deletion_count)),
+            (b"beardb.entries", number(self.entry_count)),
+            (b"beardb.largest.key", self.largest_key.clone()),
+            (b"beardb.max.sequence", number(self.max_sequence)),
+            (b"beardb.min.sequence", number(self.min_sequence)),
+            (b"beardb.raw.key.size", number(self.raw_key_size)),
+            (b"beardb.raw.value.size", number(self.raw_value_size)),
+            (b"beardb.smallest.key", self.smallest_key.clone()),
+        ];
+        entries.sort();
+        for (name, value) in entries {
+            block.add(name, &value, opts);
+        }
+    }
+
+    /// decode reads the properties from the entries of a properties block. Properties this
+    /// version doesn't know about are skipped.
+    pub(crate) fn decode(mut iter: BlockIterator) -> crate::Result<Self> {
+        let mut props = Self::default();
+        while let Some((name, value)) = iter.current() {
+            let number = || {
+                decode_varint64(value)
+                    .map(|(v, _)| v)
+                    .map_err(|_| Error::Corruption("bad table property".to_string()))
+            };
+            match name {
+                b"beardb.comparator" => {
+                    props.comparator = String::from_utf8_lossy(value).into_owned()
+                }
+                b"beardb.compression" => {
+                    props.compression = CompressionType::try_from(number()? as u8)?
+                }
+                b"beardb.creation.time" => props.creation_time = number()?,
+                b"beardb.data.size" => props.data_size = number()?,
+                b"beardb.deletions" => props.deletion_count = number()?,
+                b"beardb.entries" => props.entry_count = number()?,
+                b"beardb.largest.key" => props.largest_key = value.to_vec(),
+                b"beardb.max.sequence" => props.max_sequence = number()?,
+                b"beardb.min.sequence" => props.min_sequence = number()?,
+                b"beardb.raw.key.size" => props.raw_key_size = number()?,
+                b"beardb.raw.value.size" => props.raw_value_size = number()?,
+                b"beardb.smallest.key" => props.smallest_key = value.to_vec(),
+                _ => {}
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(props)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{Block, BlockBuilder};
+    use crate::table::{CompressionType, TableOptions};
+    use crate::table_properties::TableProperties;
+    use crate::types::ValueType;
+
+    #[test]
+    fn test_encode_decode() {
+        let opts = TableOptions {
+            compression: CompressionType::Lz4,
+            ..Default::default()
+        };
+        let mut props = TableProperties::new(&opts);
+        let tag = |seq: u64, vtype: ValueType| Some(seq << 8 | vtype as u64);
+        props.add(b"a", b"12345", tag(7, ValueType::Value));
+        props.add(b"b", b"", tag(3, ValueType::Deletion));
+        props.add(b"c", b"123", tag(9, ValueType::Value));
+        props.data_size = 1234;
+
+        let mut block = BlockBuilder::new();
+        props.encode_to(&mut block, &opts);
+        // a property written by a later version
+        block.add(b"beardb.zzz", b"?", &opts);
+        let mut data = Vec::new();
+        block.finish(&mut data, &opts).unwrap();
+        let block = Block::open(0, &data, opts.format_version, &opts).unwrap();
+        let decoded = TableProperties::decode(block.into_iter()).unwrap();
+
+        assert_eq!(decoded, props);
+        assert_eq!(decoded.entry_count, 3);
+        assert_eq!(decoded.deletion_count, 1);
+        assert_eq!((decoded.raw_key_size, decoded.raw_value_size), (3, 8));
+        assert_eq!((decoded.min_sequence, decoded.max_sequence), (3, 9));
+        assert_eq!(decoded.smallest_key, b"a");
+        assert_eq!(decoded.largest_key, b"c");
+        assert_eq!(decoded.compression, CompressionType::Lz4);
+        assert_eq!(decoded.comparator, "beardb.BytewiseComparator");
+        assert!(decoded.creation_time > 0);
+    }
+}
@@ -1,15 +1,16 @@
-use std::io::Read;
 use std::mem::size_of;
 
 use crate::{ensure, Error};
-use crate::codec::Codec;
+use crate::codec::{decode_fixed64, decode_varint32, encode_varint32, varint_length, Codec};
 
 pub(crate) type CacheKey = [u8; 16];
 pub(crate) type CacheID = u64;
 
-pub(crate) fn make_cache_key(r: CacheID) -> CacheKey {
+/// make_cache_key makes the key of the block at `offset` of the table `id`.
+pub(crate) fn make_cache_key(id: CacheID, offset: u64) -> CacheKey {
     let mut c = [0_u8; 16];
-    &c[0..8].copy_from_slice(&r.to_le_bytes());
+    c[0..8].copy_from_slice(&id.to_le_bytes());
+    c[8..16].copy_from_slice(&offset.to_le_bytes());
     c
 }
 
@@ -19,6 +20,62 @@ pub(crate) enum ValueType {
     Value = 1,
 }
 
+/// MAX_SEQUENCE is the largest sequence number that fits in an entry tag.
+pub(crate) const MAX_SEQUENCE: u64 = (1 << 56) - 1;
+
+const TAG_SIZE: usize = size_of::<u64>();
+
+/// internal_key builds a table key: the user key followed by the entry tag, the same layout as
+/// the key part of an encoded MemEntry.
+pub(crate) fn internal_key(key: &[u8], seq: u64, vtype: ValueType) -> Vec<u8> {
+    let mut ikey = Vec::with_capacity(key.len() + TAG_SIZE);
+    ikey.extend_from_slice(key);
+    ikey.extend_from_slice(&(seq << 8 | vtype as u64).to_le_bytes());
+    ikey
+}
+
+/// user_key strips the tag from a table key.
+pub(crate) fn user_key(ikey: &[u8]) -> &[u8] {
+    &ikey[..ikey.len().saturating_sub(TAG_SIZE)]
+}
+
+pub(crate) fn split_internal_key(ikey: &[u8]) -> crate::Result<(&[u8], u64)> {
+    ensure!(
+        ikey.len() >= TAG_SIZE,
+        Error::Corruption("internal key too short".to_string())
+    );
+    let (key, tag) = ikey.split_at(ikey.len() - TAG_SIZE);
+    Ok((key, decode_fixed64(tag)))
+}
+
+/// parse_internal_key splits a table key into its user key, sequence and value type.
+pub(crate) fn parse_internal_key(ikey: &[u8]) -> crate::Result<(&[u8], u64, ValueType)> {
+    let (key, tag) = split_internal_key(ikey)?;
+    let (vtype, seq) = value_type_seq(tag);
+    Ok((key, seq, vtype))
+}
+
+/// split_mem_entry returns the internal key and the value of an encoded `MemEntry`, which
+/// stores the key right before its tag.
+pub(crate) fn split_mem_entry(buf: &[u8]) -> crate::Result<(&[u8], &[u8])> {
+    let (key_size, cursor) = decode_varint32(buf)?;
+    let key_end = cursor + key_size as usize;
+    ensure!(
+        key_size as usize >= TAG_SIZE && key_end <= buf.len(),
+        Error::CodecError
+    );
+    let (value_size, len) = decode_varint32(&buf[key_end..])?;
+    let value_start = key_end + len;
+    ensure!(
+        buf.len() - value_start >= value_size as usize,
+        Error::CodecError
+    );
+    Ok((
+        &buf[cursor..key_end],
+        &buf[value_start..value_start + value_size as usize],
+    ))
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) struct MemEntry<'a> {
     tag: u64,
@@ -68,50 +125,48 @@ impl<'a> MemEntry<'a> {
     }
 }
 
+/// A MemEntry is encoded as
+///
+/// ```text
+/// key_len: varint32 | key | tag: u64 | value_len: varint32 | value
+/// ```
+///
+/// where `key_len` counts the tag along with the key.
 impl<'a> Codec<'a> for MemEntry<'a> {
     fn encode(&self) -> crate::Result<Vec<u8>> {
         ensure!(!self.key.is_empty(), Error::CodecError);
-        let key_size = (self.key.len() + size_of::<u64>()) as u32;
-        let value_size = self.value.len() as u32;
-        let mut bytes = Vec::with_capacity((key_size + value_size) as usize);
-        bytes.extend_from_slice(&key_size.to_le_bytes());
-        bytes.extend_from_slice(&self.key);
+        let key_size = self.key.len() + TAG_SIZE;
+        let mut bytes = Vec::with_capacity(
+            varint_length(key_size as u64)
+                + key_size
+                + varint_length(self.value.len() as u64)
+                + self.value.len(),
+        );
+        encode_varint32(&mut bytes, key_size as u32);
+        bytes.extend_from_slice(self.key);
         bytes.extend_from_slice(&self.tag.to_le_bytes());
-        bytes.extend_from_slice(&value_size.to_le_bytes());
-        bytes.extend_from_slice(&self.value);
+        encode_varint32(&mut bytes, self.value.len() as u32);
+        bytes.extend_from_slice(self.value);
         Ok(bytes)
     }
 
     fn decode_from_slice(buf: &'a [u8]) -> crate::Result<Self> {
-        let mut cursor = 0_usize;
-        let buffer_len = buf.len();
-        let key_offset = size_of::<u32>();
-        cursor += key_offset;
-        ensure!(cursor <= buffer_len, Error::CodecError);
-        let key_size = unsafe { (buf[..cursor].as_ptr() as *const u32).read_unaligned() } as usize;
-        let tag_offset = size_of::<u64>();
+        let (key_size, mut cursor) = decode_varint32(buf)?;
+        let key_size = key_size as usize;
+        ensure!(
+            key_size >= TAG_SIZE && buf.len() - cursor >= key_size,
+            Error::CodecError
+        );
+        let key = &buf[cursor..cursor + key_size - TAG_SIZE];
         cursor += key_size;
-        ensure!(cursor <= buffer_len, Error::CodecError);
-        let key = &buf[key_offset..cursor - tag_offset];
-        let tag = unsafe {
-            (buf[key_offset + key_size - tag_offset..cursor].as_ptr() as *const u64)
-                .read_unaligned()
-        };
-        let value_offset = size_of::<u32>();
-        cursor += value_offset;
-        ensure!(cursor <= buffer_len, Error::CodecError);
-        let value_size =
-            unsafe { (buf[key_offset + key_size..cursor].as_ptr() as *const u32).read_unaligned() }
-                as usize;
-        cursor += value_size;
-        ensure!(cursor <= buffer_len, Error::CodecError);
-        let value = &buf[key_offset + key_size + value_offset..cursor];
+        let tag = decode_fixed64(&buf[cursor - TAG_SIZE..cursor]);
+        let (value_size, len) = decode_varint32(&buf[cursor..])?;
+        cursor += len;
+        let value_size = value_size as usize;
+        ensure!(buf.len() - cursor >= value_size, Error::CodecError);
+        let value = &buf[cursor..cursor + value_size];
         Ok(Self { tag, key, value })
     }
-
-    fn decode_from_reader<R: Read>(_: R) -> crate::Result<Self> {
-        unimplemented!()
-    }
 }
 
 #[cfg(test)]
@@ -129,5 +184,16 @@ mod tests {
         assert_eq!(entry, dentry);
         assert_eq!(entry.seq(), dentry.seq());
         assert_eq!(entry.value_type(), dentry.value_type());
+        // one byte for each length
+        assert_eq!(encoded.len(), 1 + 24 + 8 + 1 + 12);
+
+        for len in 1..encoded.len() {
+            assert!(MemEntry::decode_from_slice(&encoded[..len]).is_err());
+        }
+        let empty = MemEntry::new(7, ValueType::Deletion, b"k", b"");
+        assert_eq!(
+            MemEntry::decode_from_slice(&empty.encode().unwrap()).unwrap(),
+            empty
+        );
     }
 }
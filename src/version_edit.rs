@@ -0,0 +1,277 @@
+use std::mem::size_of;
+
+use crate::codec::{decode_fixed32, decode_fixed64, Codec};
+use crate::types::user_key;
+use crate::{ensure, Error};
+
+const TAG_LOG_NUMBER: u8 = 1;
+const TAG_NEXT_FILE_NUMBER: u8 = 2;
+const TAG_LAST_SEQUENCE: u8 = 3;
+const TAG_DELETED_FILE: u8 = 4;
+// new files written before they carried their largest sequence
+const TAG_NEW_FILE_NO_SEQ: u8 = 5;
+// new files written before they carried their number of entries
+const TAG_NEW_FILE_NO_ENTRIES: u8 = 6;
+const TAG_NEW_FILE: u8 = 7;
+
+/// FileMetaData describes a table file of a version. The key range is kept as internal keys.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct FileMetaData {
+    pub(crate) number: u64,
+    pub(crate) size: u64,
+    pub(crate) smallest: Vec<u8>,
+    pub(crate) largest: Vec<u8>,
+    /// The newest sequence number in the file, which orders the overlapping level 0 files.
+    pub(crate) largest_seq: u64,
+    /// The number of entries in the file, or 0 if it was added before files carried it.
+    pub(crate) entries: u64,
+}
+
+impl FileMetaData {
+    pub(crate) fn smallest_user_key(&self) -> &[u8] {
+        user_key(&self.smallest)
+    }
+
+    pub(crate) fn largest_user_key(&self) -> &[u8] {
+        user_key(&self.largest)
+    }
+
+    /// overlaps reports whether any user key in `smallest..=largest` may be in the file.
+    pub(crate) fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest_user_key() <= largest && smallest <= self.largest_user_key()
+    }
+}
+
+/// VersionEdit is the difference between two versions, and the unit the manifest is made of.
+/// Replaying the edits of a manifest in order rebuilds the current version.
+///
+/// Each field is encoded as a one byte tag followed by its value:
+///
+/// ```text
+/// log number:       1 | number: u64
+/// next file number: 2 | number: u64
+/// last sequence:    3 | sequence: u64
+/// deleted file:     4 | level: u32 | number: u64
+/// new file:         7 | level: u32 | number: u64 | size: u64 | smallest_len: u32 | smallest
+///                     | largest_len: u32 | largest | largest_seq: u64 | entries: u64
+/// ```
+///
+/// Tag 6 is a new file without its number of entries, and tag 5 one without its largest
+/// sequence either; the missing fields are read as 0.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct VersionEdit {
+    /// Logs older than this one are no longer needed to recover the database.
+    pub(crate) log_number: Option<u64>,
+    pub(crate) next_file_number: Option<u64>,
+    pub(crate) last_sequence: Option<u64>,
+    pub(crate) deleted_files: Vec<(usize, u64)>,
+    pub(crate) new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub(crate) fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub(crate) fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+}
+
+fn put_u64(dst: &mut Vec<u8>, tag: u8, value: u64) {
+    dst.push(tag);
+    dst.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_slice(dst: &mut Vec<u8>, value: &[u8]) {
+    dst.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    dst.extend_from_slice(value);
+}
+
+impl<'a> Codec<'a> for VersionEdit {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        if let Some(number) = self.log_number {
+            put_u64(&mut buf, TAG_LOG_NUMBER, number);
+        }
+        if let Some(number) = self.next_file_number {
+            put_u64(&mut buf, TAG_NEXT_FILE_NUMBER, number);
+        }
+        if let Some(sequence) = self.last_sequence {
+            put_u64(&mut buf, TAG_LAST_SEQUENCE, sequence);
+        }
+        for (level, number) in self.deleted_files.iter() {
+            buf.push(TAG_DELETED_FILE);
+            buf.extend_from_slice(&(*level as u32).to_le_bytes());
+            buf.extend_from_slice(&number.to_le_bytes());
+        }
+        for (level, file) in self.new_files.iter() {
+            buf.push(TAG_NEW_FILE);
+            buf.extend_from_slice(&(*level as u32).to_le_bytes());
+            buf.extend_from_slice(&file.number.to_le_bytes());
+            buf.extend_from_slice(&file.size.to_le_bytes());
+            put_slice(&mut buf, &file.smallest);
+            put_slice(&mut buf, &file.largest);
+            buf.extend_from_slice(&file.largest_seq.to_le_bytes());
+            buf.extend_from_slice(&file.entries.to_le_bytes());
+        }
+        Ok(buf)
+    }
+
+    fn decode_from_slice(buf: &'a [u8]) -> crate::Result<Self> {
+        let mut edit = VersionEdit::default();
+        let mut reader = EditReader { data: buf };
+        while !reader.data.is_empty() {
+            match reader.read_u8()? {
+                TAG_LOG_NUMBER => edit.log_number = Some(reader.read_u64()?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(reader.read_u64()?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(reader.read_u64()?),
+                TAG_DELETED_FILE => {
+                    let level = reader.read_u32()? as usize;
+                    edit.delete_file(level, reader.read_u64()?);
+                }
+                tag @ (TAG_NEW_FILE | TAG_NEW_FILE_NO_ENTRIES | TAG_NEW_FILE_NO_SEQ) => {
+                    let level = reader.read_u32()? as usize;
+                    let mut file = FileMetaData {
+                        number: reader.read_u64()?,
+                        size: reader.read_u64()?,
+                        smallest: reader.read_slice()?.to_vec(),
+                        largest: reader.read_slice()?.to_vec(),
+                        largest_seq: 0,
+                        entries: 0,
+                    };
+                    if tag != TAG_NEW_FILE_NO_SEQ {
+                        file.largest_seq = reader.read_u64()?;
+                    }
+                    if tag == TAG_NEW_FILE {
+                        file.entries = reader.read_u64()?;
+                    }
+                    edit.add_file(level, file);
+                }
+                tag => {
+                    return Err(Error::Corruption(format!(
+                        "unknown version edit tag {}",
+                        tag
+                    )))
+                }
+            }
+        }
+        Ok(edit)
+    }
+}
+
+struct EditReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EditReader<'a> {
+    fn take(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        ensure!(
+            self.data.len() >= len,
+            Error::Corruption("truncated version edit".to_string())
+        );
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn read_u8(&mut self) -> crate::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> crate::Result<u32> {
+        Ok(decode_fixed32(self.take(size_of::<u32>())?))
+    }
+
+    fn read_u64(&mut self) -> crate::Result<u64> {
+        Ok(decode_fixed64(self.take(size_of::<u64>())?))
+    }
+
+    fn read_slice(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::Codec;
+    use crate::types::{internal_key, ValueType};
+    use crate::version_edit::{
+        FileMetaData, VersionEdit, TAG_NEW_FILE, TAG_NEW_FILE_NO_ENTRIES, TAG_NEW_FILE_NO_SEQ,
+    };
+    use crate::Error;
+
+    #[test]
+    fn test_edit_codec() {
+        let mut edit = VersionEdit {
+            log_number: Some(4),
+            next_file_number: Some(9),
+            last_sequence: Some(1234),
+            ..Default::default()
+        };
+        edit.delete_file(0, 3);
+        edit.add_file(
+            1,
+            FileMetaData {
+                number: 8,
+                size: 4096,
+                smallest: internal_key(b"apple", 7, ValueType::Value),
+                largest: internal_key(b"kiwi", 12, ValueType::Deletion),
+                largest_seq: 15,
+                entries: 40,
+            },
+        );
+        let encoded = edit.encode().unwrap();
+        assert_eq!(VersionEdit::decode_from_slice(&encoded).unwrap(), edit);
+        assert_eq!(
+            VersionEdit::decode_from_slice(&[]).unwrap(),
+            VersionEdit::default()
+        );
+
+        assert!(matches!(
+            VersionEdit::decode_from_slice(&encoded[..encoded.len() - 1]),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(
+            VersionEdit::decode_from_slice(&[42]),
+            Err(Error::Corruption(_))
+        ));
+
+        // a new file without its number of entries, then without its largest sequence too
+        let at = VersionEdit {
+            new_files: Vec::new(),
+            ..edit.clone()
+        }
+        .encode()
+        .unwrap()
+        .len();
+        assert_eq!(encoded[at], TAG_NEW_FILE);
+        let mut old = encoded[..encoded.len() - 8].to_vec();
+        old[at] = TAG_NEW_FILE_NO_ENTRIES;
+        edit.new_files[0].1.entries = 0;
+        assert_eq!(VersionEdit::decode_from_slice(&old).unwrap(), edit);
+        old.truncate(old.len() - 8);
+        old[at] = TAG_NEW_FILE_NO_SEQ;
+        edit.new_files[0].1.largest_seq = 0;
+        assert_eq!(VersionEdit::decode_from_slice(&old).unwrap(), edit);
+    }
+
+    #[test]
+    fn test_file_overlaps() {
+        let file = FileMetaData {
+            number: 1,
+            size: 0,
+            smallest: internal_key(b"b", 7, ValueType::Value),
+            largest: internal_key(b"d", 3, ValueType::Value),
+            largest_seq: 7,
+            entries: 2,
+        };
+        assert_eq!(file.smallest_user_key(), b"b");
+        assert!(file.overlaps(b"a", b"b"));
+        assert!(file.overlaps(b"c", b"c"));
+        assert!(file.overlaps(b"d", b"z"));
+        assert!(!file.overlaps(b"a", b"az"));
+        assert!(!file.overlaps(b"da", b"z"));
+    }
+}
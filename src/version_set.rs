@@ -0,0 +1,553 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use crate::cmp::InternalKeyComparator;
+use crate::codec::Codec;
+use crate::filename::{
+    create_file, current_file_name, manifest_file_name, sync_dir, temp_file_name,
+};
+use crate::iter::{InternalIterator, IterBounds};
+use crate::log::{LogReader, LogWriter};
+use crate::table::Table;
+use crate::table_cache::TableCache;
+use crate::types::{internal_key, parse_internal_key, ValueType};
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::{ensure, Error};
+
+pub(crate) const NUM_LEVELS: usize = 7;
+
+/// Version is an immutable snapshot of the table files that make up the database. Readers keep
+/// the version they started with, and its files aren't deleted while they do.
+pub(crate) struct Version {
+    // level 0 newest data first, deeper levels ordered by key range
+    files: Vec<Vec<Arc<FileMetaData>>>,
+    table_cache: Arc<TableCache>,
+}
+
+impl Version {
+    fn new(table_cache: Arc<TableCache>) -> Self {
+        Self {
+            files: vec![Vec::new(); NUM_LEVELS],
+            table_cache,
+        }
+    }
+
+    pub(crate) fn files(&self, level: usize) -> &[Arc<FileMetaData>] {
+        &self.files[level]
+    }
+
+    /// table returns table `number`, opening it if the table cache doesn't hold it.
+    pub(crate) fn table(&self, number: u64) -> crate::Result<Table> {
+        self.table_cache.get(number)
+    }
+
+    /// add_iterators appends an iterator over each table of the version within `bounds` to
+    /// `iters`. Tables entirely out of bounds aren't opened.
+    pub(crate) fn add_iterators(
+        &self,
+        iters: &mut Vec<Box<dyn InternalIterator>>,
+        bounds: &Arc<IterBounds>,
+    ) -> crate::Result<()> {
+        for f in self.files.iter().flatten() {
+            if bounds.before_lower(&InternalKeyComparator, &f.largest)?
+                || bounds.past_upper(&InternalKeyComparator, &f.smallest)?
+            {
+                continue;
+            }
+            let table = self.table(f.number)?;
+            iters.push(Box::new(table.iter_with_bounds(bounds.clone())?));
+        }
+        Ok(())
+    }
+
+    /// level_size is the total size in bytes of the tables of `level`.
+    pub(crate) fn level_size(&self, level: usize) -> u64 {
+        self.files[level].iter().map(|f| f.size).sum()
+    }
+
+    /// overlapping_files returns the tables of `level` that may hold user keys in
+    /// `smallest..=largest`.
+    pub(crate) fn overlapping_files(
+        &self,
+        level: usize,
+        smallest: &[u8],
+        largest: &[u8],
+    ) -> Vec<Arc<FileMetaData>> {
+        self.files[level]
+            .iter()
+            .filter(|f| f.overlaps(smallest, largest))
+            .cloned()
+            .collect()
+    }
+
+    /// get returns the newest entry for `key` up to `sequence`, which may be a deletion marker.
+    /// Level 0 files may overlap and are searched newest first; deeper levels have at most one
+    /// candidate.
+    pub(crate) fn get(
+        &self,
+        key: &[u8],
+        sequence: u64,
+    ) -> crate::Result<Option<(ValueType, Vec<u8>)>> {
+        let lookup = internal_key(key, sequence, ValueType::Value);
+        for (level, files) in self.files.iter().enumerate() {
+            let candidates: Vec<&Arc<FileMetaData>> = if level == 0 {
+                files.iter().filter(|f| f.overlaps(key, key)).collect()
+            } else {
+                let at = files.partition_point(|f| f.largest_user_key() < key);
+                files
+                    .get(at)
+                    .filter(|f| f.overlaps(key, key))
+                    .into_iter()
+                    .collect()
+            };
+            for file in candidates {
+                if let Some((ikey, value)) = self.table(file.number)?.get(&lookup)? {
+                    let (user_key, _, vtype) = parse_internal_key(&ikey)?;
+                    if user_key == key {
+                        return Ok(Some((vtype, value)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// apply returns the version `edit` turns this one into.
+    fn apply(&self, edit: &VersionEdit) -> crate::Result<Self> {
+        let mut files = self.files.clone();
+        for (level, number) in edit.deleted_files.iter() {
+            ensure!(
+                *level < NUM_LEVELS,
+                Error::Corruption(format!("bad level {}", level))
+            );
+            files[*level].retain(|f| f.number != *number);
+        }
+        for (level, file) in edit.new_files.iter() {
+            ensure!(
+                *level < NUM_LEVELS,
+                Error::Corruption(format!("bad level {}", level))
+            );
+            files[*level].push(Arc::new(file.clone()));
+        }
+        files[0].sort_by_key(|f| Reverse((f.largest_seq, f.number)));
+        for level in files.iter_mut().skip(1) {
+            level.sort_by(|a, b| a.smallest_user_key().cmp(b.smallest_user_key()));
+        }
+        Ok(Self {
+            files,
+            table_cache: self.table_cache.clone(),
+        })
+    }
+
+    fn snapshot(&self) -> VersionEdit {
+        let mut edit = VersionEdit::default();
+        for (level, files) in self.files.iter().enumerate() {
+            for file in files.iter() {
+                edit.add_file(level, file.as_ref().clone());
+            }
+        }
+        edit
+    }
+}
+
+/// VersionSet owns the current version and the manifest, the log of version edits that leads
+/// to it. Every change to the set of table files goes through `log_and_apply`, or its steps
+/// `begin_apply` and `finish_apply`, which also save the file number and sequence counters.
+pub(crate) struct VersionSet {
+    dir: PathBuf,
+    table_cache: Arc<TableCache>,
+    current: Arc<Version>,
+    // versions handed out before, whose files stay on disk while a reader holds them
+    versions: Vec<Weak<Version>>,
+    // taken by the edit being applied, if any, while it's written
+    manifest: Option<LogWriter<BufWriter<File>>>,
+    applying: bool,
+    manifest_number: u64,
+    next_file_number: u64,
+    last_sequence: u64,
+    log_number: u64,
+}
+
+impl VersionSet {
+    /// recover rebuilds the current version from the manifest named by `CURRENT`, or starts an
+    /// empty one for a new database. A new manifest is started by the first `log_and_apply`.
+    /// Tables are opened through `table_cache` as they're read.
+    pub(crate) fn recover(dir: &Path, table_cache: Arc<TableCache>) -> crate::Result<Self> {
+        let mut files = BTreeMap::new();
+        let mut recovered = VersionEdit::default();
+        let current = current_file_name(dir);
+        if current.exists() {
+            let name = fs::read_to_string(&current)?;
+            let name = name.trim_end();
+            ensure!(
+                name.starts_with("MANIFEST-") && !name.contains('/'),
+                Error::Corruption(format!("CURRENT names {:?}", name))
+            );
+            let mut reader = LogReader::new(File::open(dir.join(name))?, true);
+            let mut record = Vec::new();
+            loop {
+                match reader.read(&mut record) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        let edit = VersionEdit::decode_from_slice(&record)?;
+                        for (_, number) in edit.deleted_files.iter() {
+                            files.remove(number);
+                        }
+                        for (level, file) in edit.new_files {
+                            files.insert(file.number, (level, file));
+                        }
+                        recovered.log_number = edit.log_number.or(recovered.log_number);
+                        recovered.next_file_number =
+                            edit.next_file_number.or(recovered.next_file_number);
+                        recovered.last_sequence = edit.last_sequence.or(recovered.last_sequence);
+                    }
+                    Err(err @ Error::Corruption(_)) => {
+                        // An edit torn by a crash was never applied, the ones before it hold.
+                        if !matches!(reader.read(&mut record), Ok(0)) {
+                            return Err(err);
+                        }
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        for (level, file) in files.into_values() {
+            recovered.add_file(level, file);
+        }
+        let current = Version::new(table_cache.clone()).apply(&recovered)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            table_cache,
+            current: Arc::new(current),
+            versions: Vec::new(),
+            manifest: None,
+            applying: false,
+            manifest_number: 0,
+            next_file_number: recovered.next_file_number.unwrap_or(1),
+            last_sequence: recovered.last_sequence.unwrap_or(0),
+            log_number: recovered.log_number.unwrap_or(0),
+        })
+    }
+
+    pub(crate) fn current(&self) -> Arc<Version> {
+        self.current.clone()
+    }
+
+    pub(crate) fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// mark_file_number_used makes sure `number` isn't handed out again.
+    pub(crate) fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    pub(crate) fn set_last_sequence(&mut self, sequence: u64) {
+        self.last_sequence = sequence;
+    }
+
+    /// log_number is the oldest log still needed to recover the memtables.
+    pub(crate) fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    pub(crate) fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    /// log_and_apply saves `edit`, along with the current counters, to the manifest and installs
+    /// the version it produces.
+    pub(crate) fn log_and_apply(&mut self, edit: VersionEdit) -> crate::Result<()> {
+        let mut edit = self.begin_apply(edit)?;
+        let result = edit.write();
+        self.finish_apply(edit, result)
+    }
+
+    /// applying reports whether an edit is between `begin_apply` and `finish_apply`.
+    pub(crate) fn applying(&self) -> bool {
+        self.applying
+    }
+
+    /// begin_apply builds the version `edit` produces and stamps the edit with the current
+    /// counters. The returned edit is written with `ManifestEdit::write`, which doesn't need the
+    /// version set, and then passed to `finish_apply`. One edit is applied at a time.
+    pub(crate) fn begin_apply(&mut self, mut edit: VersionEdit) -> crate::Result<ManifestEdit> {
+        ensure!(
+            !self.applying,
+            Error::InvalidArgument("another version edit is being applied".to_string())
+        );
+        let log_number = edit.log_number.unwrap_or(self.log_number);
+        ensure!(
+            log_number >= self.log_number,
+            Error::InvalidArgument(format!("log number {} went backwards", log_number))
+        );
+        edit.log_number = Some(log_number);
+        let version = self.current.apply(&edit)?;
+        let new_manifest = match self.manifest {
+            Some(_) => None,
+            // a new manifest starts with the whole current version
+            None => Some((self.new_file_number(), self.current.snapshot().encode()?)),
+        };
+        edit.next_file_number = Some(self.next_file_number);
+        edit.last_sequence = Some(self.last_sequence);
+        self.applying = true;
+        Ok(ManifestEdit {
+            edit,
+            version,
+            manifest: self.manifest.take(),
+            new_manifest,
+            dir: self.dir.clone(),
+            table_cache: self.table_cache.clone(),
+        })
+    }
+
+    /// finish_apply installs the version of `edit` if `result`, the outcome of writing it, is
+    /// ok. A new manifest that failed is started over by the next edit.
+    pub(crate) fn finish_apply(
+        &mut self,
+        edit: ManifestEdit,
+        result: crate::Result<()>,
+    ) -> crate::Result<()> {
+        self.applying = false;
+        if result.is_ok() || edit.new_manifest.is_none() {
+            self.manifest = edit.manifest;
+        }
+        result?;
+        if let Some((number, _)) = edit.new_manifest {
+            self.manifest_number = number;
+        }
+        self.log_number = edit.edit.log_number.unwrap_or(self.log_number);
+        self.install(edit.version);
+        Ok(())
+    }
+
+    fn install(&mut self, version: Version) {
+        let old = std::mem::replace(&mut self.current, Arc::new(version));
+        self.versions.push(Arc::downgrade(&old));
+        self.versions.retain(|v| v.strong_count() > 0);
+    }
+
+    /// live_files returns the numbers of the tables in the current version and in every older
+    /// version a reader still holds.
+    pub(crate) fn live_files(&mut self) -> HashSet<u64> {
+        self.versions.retain(|v| v.strong_count() > 0);
+        let mut live = HashSet::new();
+        let versions = self.versions.iter().filter_map(Weak::upgrade);
+        for version in versions.chain(std::iter::once(self.current.clone())) {
+            for files in version.files.iter() {
+                live.extend(files.iter().map(|f| f.number));
+            }
+        }
+        live
+    }
+}
+
+/// ManifestEdit is a version edit on its way to the manifest, along with the version it
+/// produces. Writing it doesn't touch the version set, so the DB mutex is released meanwhile.
+pub(crate) struct ManifestEdit {
+    edit: VersionEdit,
+    version: Version,
+    manifest: Option<LogWriter<BufWriter<File>>>,
+    // the number and first record of the manifest to start, if there's none
+    new_manifest: Option<(u64, Vec<u8>)>,
+    dir: PathBuf,
+    table_cache: Arc<TableCache>,
+}
+
+impl ManifestEdit {
+    /// write opens the tables the edit adds, appends the edit to the manifest and syncs it. A
+    /// new manifest is created first and named by `CURRENT` once the edit is in.
+    pub(crate) fn write(&mut self) -> crate::Result<()> {
+        // the tables the edit adds have to open before anything refers to them
+        for (_, file) in self.edit.new_files.iter() {
+            self.table_cache.get(file.number)?;
+        }
+        let manifest = match (&mut self.manifest, &self.new_manifest) {
+            (Some(manifest), _) => manifest,
+            (None, Some((number, snapshot))) => {
+                let file = create_file(&manifest_file_name(&self.dir, *number))?;
+                let mut manifest = LogWriter::new(BufWriter::new(file));
+                manifest.add_record(snapshot)?;
+                self.manifest.insert(manifest)
+            }
+            (None, None) => unreachable!("an edit without a manifest starts a new one"),
+        };
+        manifest.add_record(&self.edit.encode()?)?;
+        manifest.flush()?;
+        manifest.get_ref().get_ref().sync_all()?;
+        if let Some((number, _)) = self.new_manifest {
+            set_current_file(&self.dir, number)?;
+        }
+        Ok(())
+    }
+}
+
+/// set_current_file points `CURRENT` at manifest `number`. The name is written to a temporary
+/// file first and renamed over `CURRENT`, so it's replaced atomically.
+fn set_current_file(dir: &Path, number: u64) -> crate::Result<()> {
+    let temp_path = temp_file_name(dir, number);
+    let mut file = create_file(&temp_path)?;
+    file.write_all(format!("MANIFEST-{:06}\n", number).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, current_file_name(dir))?;
+    sync_dir(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::cmp::InternalKeyComparator;
+    use crate::disk_env::PosixDiskEnv;
+    use crate::filename::{create_file, current_file_name, table_file_name};
+    use crate::table::TableOptions;
+    use crate::table_builder::TableBuilder;
+    use crate::table_cache::TableCache;
+    use crate::types::{internal_key, ValueType, MAX_SEQUENCE};
+    use crate::version_edit::{FileMetaData, VersionEdit};
+    use crate::version_set::VersionSet;
+    use crate::Error;
+
+    fn table_opts() -> Arc<TableOptions> {
+        Arc::new(TableOptions {
+            comparator: Arc::new(Box::new(InternalKeyComparator)),
+            ..Default::default()
+        })
+    }
+
+    fn table_cache(dir: &Path, opts: &Arc<TableOptions>) -> Arc<TableCache> {
+        let env = Arc::new(PosixDiskEnv::new());
+        Arc::new(TableCache::new(dir.to_path_buf(), env, opts.clone(), 100))
+    }
+
+    fn build_table(
+        versions: &mut VersionSet,
+        opts: &Arc<TableOptions>,
+        entries: &[(&str, u64)],
+    ) -> FileMetaData {
+        let number = versions.new_file_number();
+        let path = table_file_name(&versions.dir, number);
+        let mut file = create_file(&path).unwrap();
+        let mut builder = TableBuilder::new_with_options(&mut file, opts.clone());
+        for (key, seq) in entries {
+            let ikey = internal_key(key.as_bytes(), *seq, ValueType::Value);
+            builder.add(&ikey, key.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+        file.flush().unwrap();
+        let (first, last) = (entries[0], entries[entries.len() - 1]);
+        FileMetaData {
+            number,
+            size: fs::metadata(&path).unwrap().len(),
+            smallest: internal_key(first.0.as_bytes(), first.1, ValueType::Value),
+            largest: internal_key(last.0.as_bytes(), last.1, ValueType::Value),
+            largest_seq: entries.iter().map(|(_, seq)| *seq).max().unwrap(),
+            entries: entries.len() as u64,
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = tempdir().unwrap();
+        let opts = table_opts();
+        let mut versions = VersionSet::recover(dir.path(), table_cache(dir.path(), &opts)).unwrap();
+        assert_eq!(versions.current().files(0).len(), 0);
+
+        let first = build_table(&mut versions, &opts, &[("apple", 1), ("kiwi", 2)]);
+        let mut edit = VersionEdit {
+            log_number: Some(3),
+            ..Default::default()
+        };
+        edit.add_file(0, first.clone());
+        versions.set_last_sequence(2);
+        versions.log_and_apply(edit).unwrap();
+        let held = versions.current();
+
+        let second = build_table(&mut versions, &opts, &[("banana", 3)]);
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, first.number);
+        edit.add_file(1, second.clone());
+        versions.set_last_sequence(3);
+        versions.log_and_apply(edit).unwrap();
+
+        // the replaced version is still held, so its table is still live
+        let live = versions.live_files();
+        assert!(live.contains(&first.number) && live.contains(&second.number));
+        drop(held);
+        assert!(!versions.live_files().contains(&first.number));
+        let (next_file_number, manifest_number) =
+            (versions.next_file_number, versions.manifest_number());
+        drop(versions);
+
+        let versions = VersionSet::recover(dir.path(), table_cache(dir.path(), &opts)).unwrap();
+        let current = versions.current();
+        assert!(current.files(0).is_empty());
+        assert_eq!(current.files(1).len(), 1);
+        assert_eq!(current.files(1)[0].as_ref(), &second);
+        assert_eq!(versions.log_number(), 3);
+        assert_eq!(versions.last_sequence(), 3);
+        assert_eq!(versions.next_file_number, next_file_number);
+        assert_eq!(
+            fs::read_to_string(current_file_name(dir.path())).unwrap(),
+            format!("MANIFEST-{:06}\n", manifest_number)
+        );
+        assert_eq!(
+            current.get(b"banana", MAX_SEQUENCE).unwrap(),
+            Some((ValueType::Value, b"banana".to_vec()))
+        );
+        assert_eq!(current.get(b"apple", MAX_SEQUENCE).unwrap(), None);
+        assert_eq!(current.get(b"banana", 2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_apply_in_steps() {
+        let dir = tempdir().unwrap();
+        let opts = table_opts();
+        let mut versions = VersionSet::recover(dir.path(), table_cache(dir.path(), &opts)).unwrap();
+        let file = build_table(&mut versions, &opts, &[("apple", 1)]);
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, file.clone());
+
+        let mut pending = versions.begin_apply(edit).unwrap();
+        assert!(versions.applying());
+        // edits are applied one at a time, and nothing changes until the edit is finished
+        assert!(versions.begin_apply(VersionEdit::default()).is_err());
+        pending.write().unwrap();
+        assert!(versions.current().files(0).is_empty());
+        versions.finish_apply(pending, Ok(())).unwrap();
+        assert!(!versions.applying());
+        assert_eq!(versions.current().files(0)[0].as_ref(), &file);
+
+        // a failed write installs nothing, and the manifest keeps being appended to
+        let manifest_number = versions.manifest_number();
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, file.number);
+        let pending = versions.begin_apply(edit).unwrap();
+        let failed = Err(Error::IOError("injected".to_string()));
+        assert!(versions.finish_apply(pending, failed).is_err());
+        assert_eq!(versions.current().files(0).len(), 1);
+        versions.log_and_apply(VersionEdit::default()).unwrap();
+        assert_eq!(versions.manifest_number(), manifest_number);
+        drop(versions);
+
+        let versions = VersionSet::recover(dir.path(), table_cache(dir.path(), &opts)).unwrap();
+        assert_eq!(versions.current().files(0)[0].as_ref(), &file);
+    }
+}
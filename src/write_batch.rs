@@ -0,0 +1,285 @@
+use std::mem::size_of;
+
+use crate::codec::{decode_fixed32, decode_fixed64, Codec};
+use crate::memtable::MemTable;
+use crate::types::ValueType;
+use crate::{ensure, Error};
+
+// sequence (8 bytes) followed by the number of entries (4 bytes)
+const BATCH_HEADER_SIZE: usize = 12;
+
+/// WriteBatch collects puts and deletes that are applied to the database atomically. The batch
+/// is written to the log as a single record and its entries take consecutive sequence numbers
+/// starting at the batch sequence, so recovery either restores all of them or none.
+///
+/// The encoding is the header followed by one record per entry:
+///
+/// ```text
+/// sequence: u64 | count: u32 | (type: u8 | key_len: u32 | key | [value_len: u32 | value])*
+/// ```
+///
+/// Deletions carry no value.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self {
+            rep: vec![0; BATCH_HEADER_SIZE],
+        }
+    }
+
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) {
+        self.push(ValueType::Value, key.as_ref());
+        let value = value.as_ref();
+        self.rep
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.rep.extend_from_slice(value);
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.push(ValueType::Deletion, key.as_ref());
+    }
+
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(BATCH_HEADER_SIZE, 0);
+    }
+
+    pub fn count(&self) -> u32 {
+        decode_fixed32(&self.rep[8..BATCH_HEADER_SIZE])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// append adds the entries of `other` after the entries of this batch.
+    pub fn append(&mut self, other: &WriteBatch) {
+        let count = self.count() + other.count();
+        self.rep[8..BATCH_HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+        self.rep.extend_from_slice(&other.rep[BATCH_HEADER_SIZE..]);
+    }
+
+    /// approximate_size is the size of the encoded batch in bytes.
+    pub fn approximate_size(&self) -> usize {
+        self.rep.len()
+    }
+
+    pub(crate) fn sequence(&self) -> u64 {
+        decode_fixed64(&self.rep[..8])
+    }
+
+    pub(crate) fn set_sequence(&mut self, seq: u64) {
+        self.rep[..8].copy_from_slice(&seq.to_le_bytes());
+    }
+
+    pub(crate) fn iter(&self) -> WriteBatchIter<'_> {
+        WriteBatchIter {
+            data: &self.rep[BATCH_HEADER_SIZE..],
+        }
+    }
+
+    /// insert_into applies the entries to `mem`, numbering them from the batch sequence.
+    pub(crate) fn insert_into(&self, mem: &MemTable) -> crate::Result<()> {
+        for (seq, entry) in (self.sequence()..).zip(self.iter()) {
+            let (vtype, key, value) = entry?;
+            mem.add(seq, vtype, key, value)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, vtype: ValueType, key: &[u8]) {
+        let count = self.count() + 1;
+        self.rep[8..BATCH_HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+        self.rep.push(vtype as u8);
+        self.rep
+            .extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.rep.extend_from_slice(key);
+    }
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        WriteBatch::new()
+    }
+}
+
+impl<'a> Codec<'a> for WriteBatch {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        Ok(self.rep.clone())
+    }
+
+    fn decode_from_slice(buf: &'a [u8]) -> crate::Result<Self> {
+        ensure!(
+            buf.len() >= BATCH_HEADER_SIZE,
+            Error::Corruption("write batch too small".into())
+        );
+        let batch = WriteBatch { rep: buf.to_vec() };
+        let mut found = 0;
+        for entry in batch.iter() {
+            entry?;
+            found += 1;
+        }
+        ensure!(
+            found == batch.count(),
+            Error::Corruption("write batch has wrong count".into())
+        );
+        Ok(batch)
+    }
+}
+
+pub(crate) struct WriteBatchIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> WriteBatchIter<'a> {
+    fn read_slice(&mut self) -> crate::Result<&'a [u8]> {
+        ensure!(
+            self.data.len() >= size_of::<u32>(),
+            Error::Corruption("bad write batch entry".into())
+        );
+        let len = decode_fixed32(self.data) as usize;
+        let data = &self.data[size_of::<u32>()..];
+        ensure!(
+            data.len() >= len,
+            Error::Corruption("bad write batch entry".into())
+        );
+        self.data = &data[len..];
+        Ok(&data[..len])
+    }
+
+    fn read_entry(&mut self) -> crate::Result<(ValueType, &'a [u8], &'a [u8])> {
+        let vtype = match self.data[0] {
+            0 => ValueType::Deletion,
+            1 => ValueType::Value,
+            typ => {
+                return Err(Error::Corruption(format!(
+                    "unknown write batch entry type {}",
+                    typ
+                )))
+            }
+        };
+        self.data = &self.data[1..];
+        let key = self.read_slice()?;
+        let value = match vtype {
+            ValueType::Deletion => &[],
+            ValueType::Value => self.read_slice()?,
+        };
+        Ok((vtype, key, value))
+    }
+}
+
+impl<'a> Iterator for WriteBatchIter<'a> {
+    type Item = crate::Result<(ValueType, &'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let entry = self.read_entry();
+        if entry.is_err() {
+            // stop after reporting a malformed entry
+            self.data = &[];
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::Codec;
+    use crate::memtable::MemTable;
+    use crate::types::{ValueType, MAX_SEQUENCE};
+    use crate::write_batch::WriteBatch;
+    use crate::Error;
+
+    #[test]
+    fn test_batch() {
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+        batch.put(b"apple", b"red");
+        batch.delete(b"banana");
+        batch.put(b"kiwi", b"");
+        batch.set_sequence(100);
+        assert_eq!(batch.count(), 3);
+        assert_eq!(batch.sequence(), 100);
+
+        let entries: Vec<_> = batch.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (ValueType::Value, b"apple".as_slice(), b"red".as_slice()),
+                (ValueType::Deletion, b"banana".as_slice(), b"".as_slice()),
+                (ValueType::Value, b"kiwi".as_slice(), b"".as_slice()),
+            ]
+        );
+
+        let decoded = WriteBatch::decode_from_slice(&batch.encode().unwrap()).unwrap();
+        assert_eq!(decoded, batch);
+
+        let mut other = WriteBatch::new();
+        other.delete(b"apple");
+        batch.append(&other);
+        assert_eq!(batch.count(), 4);
+        assert_eq!(
+            batch.iter().last().unwrap().unwrap(),
+            (ValueType::Deletion, b"apple".as_slice(), b"".as_slice())
+        );
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_batch_insert_into() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"apple", b"red");
+        batch.delete(b"apple");
+        batch.put(b"kiwi", b"green");
+        batch.set_sequence(7);
+
+        let mem = MemTable::new();
+        batch.insert_into(&mem).unwrap();
+        assert_eq!(
+            mem.get(b"apple", MAX_SEQUENCE).unwrap(),
+            Some((ValueType::Deletion, b"".as_slice()))
+        );
+        assert_eq!(
+            mem.get(b"kiwi", MAX_SEQUENCE).unwrap(),
+            Some((ValueType::Value, b"green".as_slice()))
+        );
+    }
+
+    #[test]
+    fn test_batch_corrupted() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"apple", b"red");
+        batch.put(b"kiwi", b"green");
+        let encoded = batch.encode().unwrap();
+
+        assert!(matches!(
+            WriteBatch::decode_from_slice(&encoded[..8]),
+            Err(Error::Corruption(_))
+        ));
+        assert!(matches!(
+            WriteBatch::decode_from_slice(&encoded[..encoded.len() - 1]),
+            Err(Error::Corruption(_))
+        ));
+        let mut wrong_count = encoded.clone();
+        wrong_count[8] = 3;
+        assert!(matches!(
+            WriteBatch::decode_from_slice(&wrong_count),
+            Err(Error::Corruption(_))
+        ));
+        let mut wrong_type = encoded;
+        wrong_type[12] = 9;
+        assert!(matches!(
+            WriteBatch::decode_from_slice(&wrong_type),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -14,7 +14,7 @@ pub trait BloomTest {
                 results += 1.0;
             }
         }
-        return results / 10000.0;
+        results / 10000.0
     }
 
     fn build(&mut self) {}
@@ -29,7 +29,7 @@ fn next_length(mut len: usize) -> usize {
     } else {
         len += 1000;
     }
-    return len;
+    len
 }
 
 pub fn empty_filter<T>(mut t: T)